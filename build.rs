@@ -0,0 +1,67 @@
+use std::{env, fs, path::{Path, PathBuf}, process::Command};
+
+// user programs packed into the kernel image, see src/programs.rs
+const USER_PROGRAMS: &[&str] = &["hello", "cat"];
+
+// Space reserved in the kernel image for the symbol table. It is reserved even
+// when no table is embedded, so that the second pass of `tools/ksyms.sh` doesn't
+// move any code and the addresses collected in the first pass stay valid.
+const KSYMS_CAPACITY: usize = 256 * 1024;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=RUSTOS_KSYMS");
+
+    // `address name` lines sorted by address, as produced by tools/ksyms.sh
+    let mut table = match env::var("RUSTOS_KSYMS") {
+        Ok(path) => {
+            println!("cargo:rerun-if-changed={}", path);
+            fs::read(&path).unwrap_or_else(|err| panic!("failed to read symbol table {}: {}", path, err))
+        }
+        Err(_) => Vec::new(),
+    };
+    if table.len() > KSYMS_CAPACITY {
+        panic!("symbol table is {} bytes, only {} are reserved", table.len(), KSYMS_CAPACITY);
+    }
+    table.resize(KSYMS_CAPACITY, 0);
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    fs::write(out_dir.join("ksyms.bin"), table).expect("failed to write ksyms.bin");
+
+    build_user_programs(&out_dir);
+}
+
+// Builds the workspace's user programs for their own target, which unlike the
+// kernel's may use SSE (the kernel switches the FPU state per thread), with a target
+// directory of their own so this doesn't wait on the lock of the running build, and
+// copies the executables to OUT_DIR/user.
+fn build_user_programs(out_dir: &Path) {
+    println!("cargo:rerun-if-changed=user");
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let target_dir = out_dir.join("user-target");
+    let cargo = env::var("CARGO").unwrap_or_else(|_| String::from("cargo"));
+    let status = Command::new(cargo)
+        .current_dir(&manifest_dir)
+        .args(["build", "--release", "--package", "programs", "--bins"])
+        .arg("--target")
+        .arg(manifest_dir.join("user/x86_64-rustos-user.json"))
+        .args(["-Zbuild-std=core,compiler_builtins,alloc", "-Zbuild-std-features=compiler-builtins-mem"])
+        .arg("--target-dir")
+        .arg(&target_dir)
+        // flags meant for the kernel build, e.g. clippy as rustc wrapper
+        .env_remove("RUSTC_WORKSPACE_WRAPPER")
+        .env_remove("RUSTC_WRAPPER")
+        .env_remove("CARGO_ENCODED_RUSTFLAGS")
+        .status()
+        .expect("failed to run cargo for the user programs");
+    if !status.success() {
+        panic!("building the user programs failed");
+    }
+
+    let binaries = target_dir.join("x86_64-rustos-user").join("release");
+    fs::create_dir_all(out_dir.join("user")).expect("failed to create the user program directory");
+    for program in USER_PROGRAMS {
+        fs::copy(binaries.join(program), out_dir.join("user").join(program))
+            .unwrap_or_else(|err| panic!("failed to copy user program {}: {}", program, err));
+    }
+}
@@ -0,0 +1,237 @@
+use core::{mem, ptr, slice, str};
+use x86_64::PhysAddr;
+use crate::memory::phys_to_virt;
+
+pub mod fadt;
+pub mod hpet;
+pub mod madt;
+pub mod mcfg;
+
+pub use fadt::Fadt;
+pub use hpet::HpetTable;
+pub use madt::{Madt, MadtEntry, MadtHeader};
+pub use mcfg::{Mcfg, McfgEntry, McfgHeader};
+
+// Root System Description Pointer, the entry point into the ACPI tables.
+// The fields after `rsdt_address` are only present for revision 2 and above.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct Rsdp {
+    pub signature: [u8; 8],
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub revision: u8,
+    pub rsdt_address: u32,
+    pub length: u32,
+    pub xsdt_address: u64,
+    pub extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+// Header shared by every System Description Table.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+// Generic Address Structure, describes the location of a register in some address space.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct GenericAddress {
+    pub address_space_id: u8,
+    pub register_bit_width: u8,
+    pub register_bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+// size of the ACPI 1.0 part of the RSDP that the first checksum covers
+const RSDP_V1_LENGTH: usize = 20;
+
+// Sums up `len` bytes starting at `addr`; valid ACPI structures sum up to zero.
+unsafe fn checksum(addr: *const u8, len: usize) -> u8 {
+    slice::from_raw_parts(addr, len).iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+// Looks for a valid RSDP in the physical range [start, end) on 16 byte boundaries.
+fn scan_for_rsdp(start: u64, end: u64) -> Option<&'static Rsdp> {
+    for addr in (start..end).step_by(16) {
+        let ptr = phys_to_virt(PhysAddr::new(addr)).as_ptr::<u8>();
+        let signature = unsafe { slice::from_raw_parts(ptr, RSDP_SIGNATURE.len()) };
+        if signature == RSDP_SIGNATURE && unsafe { checksum(ptr, RSDP_V1_LENGTH) } == 0 {
+            return Some(unsafe { &*(ptr as *const Rsdp) });
+        }
+    }
+    None
+}
+
+// Locates the RSDP by scanning the first KiB of the Extended BIOS Data Area and
+// then the BIOS read-only area between 0xE0000 and 0xFFFFF. (The 0.9 bootloader
+// passes no RSDP address in its BootInfo, so scanning is the only option.)
+pub fn find_rsdp() -> Option<&'static Rsdp> {
+    // the real mode segment of the EBDA is stored at 0x40E in the BIOS data area
+    let ebda_segment = unsafe { *phys_to_virt(PhysAddr::new(0x40e)).as_ptr::<u16>() };
+    let ebda_start = (ebda_segment as u64) << 4;
+    if ebda_start != 0 {
+        if let Some(rsdp) = scan_for_rsdp(ebda_start, ebda_start + 1024) {
+            return Some(rsdp);
+        }
+    }
+    scan_for_rsdp(0xe0000, 0x100000)
+}
+
+// Returns the table header located at the given physical address, if its checksum is valid.
+pub fn sdt_at(addr: u64) -> Option<&'static SdtHeader> {
+    let header = unsafe { &*phys_to_virt(PhysAddr::new(addr)).as_ptr::<SdtHeader>() };
+    let len = header.length as usize;
+    if len < mem::size_of::<SdtHeader>() {
+        return None;
+    }
+    if unsafe { checksum(header as *const SdtHeader as *const u8, len) } != 0 {
+        return None;
+    }
+    Some(header)
+}
+
+// The XSDT address of a revision 2+ RSDP, if the extended checksum over its
+// `length` bytes is valid.
+fn xsdt_address(rsdp: &Rsdp) -> Option<u64> {
+    let length = rsdp.length as usize;
+    if rsdp.revision < 2 || rsdp.xsdt_address == 0 || length < mem::size_of::<Rsdp>() {
+        return None;
+    }
+    if unsafe { checksum(rsdp as *const Rsdp as *const u8, length) } != 0 {
+        return None;
+    }
+    Some(rsdp.xsdt_address)
+}
+
+// Returns the root table (XSDT when available, otherwise RSDT) and the size of its entries.
+fn root_table() -> Option<(&'static SdtHeader, usize)> {
+    let rsdp = find_rsdp()?;
+    // revision 2+ provides the 64 bit XSDT; older firmware only has the RSDT, which
+    // is also the fallback when the extended RSDP or the XSDT doesn't check out
+    if let Some(xsdt) = xsdt_address(rsdp).and_then(sdt_at) {
+        return Some((xsdt, 8));
+    }
+    Some((sdt_at(rsdp.rsdt_address as u64)?, 4))
+}
+
+// Iterates over all valid tables referenced by the RSDT/XSDT.
+pub fn tables() -> impl Iterator<Item = &'static SdtHeader> {
+    let (entries_start, entry_count, entry_size) = match root_table() {
+        Some((root, entry_size)) => (
+            root as *const SdtHeader as usize + mem::size_of::<SdtHeader>(),
+            (root.length as usize - mem::size_of::<SdtHeader>()) / entry_size,
+            entry_size,
+        ),
+        None => (0, 0, 4),
+    };
+    (0..entry_count).filter_map(move |i| {
+        let entry = (entries_start + i * entry_size) as *const u8;
+        let addr = unsafe {
+            if entry_size == 8 {
+                ptr::read_unaligned(entry as *const u64)
+            } else {
+                ptr::read_unaligned(entry as *const u32) as u64
+            }
+        };
+        sdt_at(addr)
+    })
+}
+
+// Searches the RSDT (or XSDT when available) for a table with the given signature.
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static SdtHeader> {
+    tables().find(|table| &table.signature == signature)
+}
+
+// Reinterprets a table header as the complete table `T` it starts.
+// The caller must make sure the signature matches `T`.
+unsafe fn typed<T>(header: &'static SdtHeader) -> Option<&'static T> {
+    if (header.length as usize) < mem::size_of::<T>() {
+        return None;
+    }
+    Some(&*(header as *const SdtHeader as *const T))
+}
+
+// Multiple APIC Description Table: interrupt controllers and processors.
+pub fn madt() -> Option<Madt> {
+    let header = find_table(b"APIC")?;
+    unsafe { typed::<MadtHeader>(header) }.map(Madt::new)
+}
+
+// Fixed ACPI Description Table: power management registers and the DSDT.
+pub fn fadt() -> Option<&'static Fadt> {
+    // the FADT grew with every revision, only the ACPI 1.0 part is guaranteed
+    let header = find_table(b"FACP")?;
+    if (header.length as usize) < fadt::FADT_V1_LENGTH {
+        return None;
+    }
+    Some(unsafe { &*(header as *const SdtHeader as *const Fadt) })
+}
+
+// Differentiated System Description Table, referenced by the FADT instead of the RSDT.
+pub fn dsdt() -> Option<&'static SdtHeader> {
+    let header = sdt_at(fadt()?.dsdt_address())?;
+    if &header.signature == b"DSDT" { Some(header) } else { None }
+}
+
+// High Precision Event Timer description.
+pub fn hpet() -> Option<&'static HpetTable> {
+    unsafe { typed(find_table(b"HPET")?) }
+}
+
+// PCI Express memory mapped configuration space description.
+pub fn mcfg() -> Option<Mcfg> {
+    let header = find_table(b"MCFG")?;
+    unsafe { typed::<McfgHeader>(header) }.map(Mcfg::new)
+}
+
+// Reads the signature as text, for listings.
+pub fn signature_str(header: &SdtHeader) -> &str {
+    str::from_utf8(&header.signature).unwrap_or("????")
+}
+
+// Reads the OEM ID as text, for listings.
+pub fn oem_id_str(header: &SdtHeader) -> &str {
+    str::from_utf8(&header.oem_id).unwrap_or("??????").trim_end()
+}
+
+// Physical address of a table, for listings.
+pub fn table_address(header: &'static SdtHeader) -> u64 {
+    header as *const SdtHeader as u64 - crate::memory::phys_to_virt(PhysAddr::new(0)).as_u64()
+}
+
+#[test_case]
+fn test_xsdt_needs_a_valid_extended_checksum() {
+    let mut rsdp = Rsdp {
+        signature: *RSDP_SIGNATURE,
+        checksum: 0,
+        oem_id: *b"RUSTOS",
+        revision: 2,
+        rsdt_address: 0x1000,
+        length: mem::size_of::<Rsdp>() as u32,
+        xsdt_address: 0x2000,
+        extended_checksum: 0,
+        reserved: [0; 3],
+    };
+    let sum = unsafe { checksum(&rsdp as *const Rsdp as *const u8, mem::size_of::<Rsdp>()) };
+    rsdp.extended_checksum = sum.wrapping_neg();
+    assert_eq!(xsdt_address(&rsdp), Some(0x2000));
+    rsdp.reserved[0] = 1;
+    assert_eq!(xsdt_address(&rsdp), None);
+    rsdp.reserved[0] = 0;
+    // too short to hold the XSDT address
+    rsdp.length = RSDP_V1_LENGTH as u32;
+    assert_eq!(xsdt_address(&rsdp), None);
+}
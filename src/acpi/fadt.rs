@@ -0,0 +1,90 @@
+use core::mem;
+use super::{GenericAddress, SdtHeader};
+
+// size of the FADT as defined by ACPI 1.0, everything after `flags` came later
+pub const FADT_V1_LENGTH: usize = 116;
+
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct Fadt {
+    pub header: SdtHeader,
+    pub firmware_ctrl: u32,
+    pub dsdt: u32,
+    reserved: u8,
+    pub preferred_pm_profile: u8,
+    pub sci_int: u16,
+    pub smi_cmd: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub s4bios_req: u8,
+    pub pstate_cnt: u8,
+    pub pm1a_evt_blk: u32,
+    pub pm1b_evt_blk: u32,
+    pub pm1a_cnt_blk: u32,
+    pub pm1b_cnt_blk: u32,
+    pub pm2_cnt_blk: u32,
+    pub pm_tmr_blk: u32,
+    pub gpe0_blk: u32,
+    pub gpe1_blk: u32,
+    pub pm1_evt_len: u8,
+    pub pm1_cnt_len: u8,
+    pub pm2_cnt_len: u8,
+    pub pm_tmr_len: u8,
+    pub gpe0_blk_len: u8,
+    pub gpe1_blk_len: u8,
+    pub gpe1_base: u8,
+    pub cst_cnt: u8,
+    pub p_lvl2_lat: u16,
+    pub p_lvl3_lat: u16,
+    pub flush_size: u16,
+    pub flush_stride: u16,
+    pub duty_offset: u8,
+    pub duty_width: u8,
+    pub day_alrm: u8,
+    pub mon_alrm: u8,
+    pub century: u8,
+    pub iapc_boot_arch: u16,
+    reserved2: u8,
+    pub flags: u32,
+    // fields below only exist in ACPI 2.0+ tables, check `has_field` before use
+    pub reset_reg: GenericAddress,
+    pub reset_value: u8,
+    pub arm_boot_arch: u16,
+    pub fadt_minor_version: u8,
+    pub x_firmware_ctrl: u64,
+    pub x_dsdt: u64,
+    pub x_pm1a_evt_blk: GenericAddress,
+    pub x_pm1b_evt_blk: GenericAddress,
+    pub x_pm1a_cnt_blk: GenericAddress,
+    pub x_pm1b_cnt_blk: GenericAddress,
+}
+
+// flag: the reset register is supported
+pub const RESET_REG_SUP: u32 = 1 << 10;
+
+impl Fadt {
+    // Whether the table is long enough to contain the field ending at `offset + size`.
+    pub fn has_field(&self, offset: usize, size: usize) -> bool {
+        offset + size <= self.header.length as usize
+    }
+
+    // Physical address of the DSDT, preferring the 64 bit pointer.
+    pub fn dsdt_address(&self) -> u64 {
+        let x_dsdt_offset = mem::offset_of!(Fadt, x_dsdt);
+        if self.has_field(x_dsdt_offset, 8) && self.x_dsdt != 0 {
+            self.x_dsdt
+        } else {
+            self.dsdt as u64
+        }
+    }
+
+    // The reset register and the value to write into it, when the firmware supports it.
+    pub fn reset_register(&self) -> Option<(GenericAddress, u8)> {
+        let reset_value_offset = mem::offset_of!(Fadt, reset_value);
+        if self.has_field(reset_value_offset, 1) && self.flags & RESET_REG_SUP != 0 {
+            Some((self.reset_reg, self.reset_value))
+        } else {
+            None
+        }
+    }
+}
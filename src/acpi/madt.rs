@@ -0,0 +1,86 @@
+use core::{mem, ptr};
+use super::SdtHeader;
+
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct MadtHeader {
+    pub header: SdtHeader,
+    pub local_apic_address: u32,
+    pub flags: u32,
+}
+
+// processor flag: the CPU is usable (or can be brought online)
+pub const PROCESSOR_ENABLED: u32 = 1 << 0;
+pub const PROCESSOR_ONLINE_CAPABLE: u32 = 1 << 1;
+
+// Interrupt controller structures following the MADT header.
+#[derive(Debug, Clone, Copy)]
+pub enum MadtEntry {
+    LocalApic { processor_id: u8, apic_id: u8, flags: u32 },
+    IoApic { id: u8, address: u32, gsi_base: u32 },
+    InterruptSourceOverride { bus: u8, source: u8, gsi: u32, flags: u16 },
+    LocalApicNmi { processor_id: u8, flags: u16, lint: u8 },
+    LocalApicAddressOverride { address: u64 },
+    Unknown { entry_type: u8, length: u8 },
+}
+
+#[derive(Clone, Copy)]
+pub struct Madt {
+    pub table: &'static MadtHeader,
+}
+
+impl Madt {
+    pub fn new(table: &'static MadtHeader) -> Self {
+        Madt { table }
+    }
+
+    // Physical address of the local APICs, taking a 64 bit override into account.
+    pub fn local_apic_address(&self) -> u64 {
+        self.entries()
+            .find_map(|entry| match entry {
+                MadtEntry::LocalApicAddressOverride { address } => Some(address),
+                _ => None,
+            })
+            .unwrap_or(self.table.local_apic_address as u64)
+    }
+
+    // APIC IDs of all processors that are enabled or can be brought online.
+    pub fn processor_apic_ids(&self) -> impl Iterator<Item = u8> {
+        self.entries().filter_map(|entry| match entry {
+            MadtEntry::LocalApic { apic_id, flags, .. } if flags & (PROCESSOR_ENABLED | PROCESSOR_ONLINE_CAPABLE) != 0 => Some(apic_id),
+            _ => None,
+        })
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = MadtEntry> {
+        let start = self.table as *const MadtHeader as usize;
+        let end = start + self.table.header.length as usize;
+        let mut offset = start + mem::size_of::<MadtHeader>();
+        core::iter::from_fn(move || {
+            // every entry starts with its type and total length
+            if offset + 2 > end {
+                return None;
+            }
+            let entry_type = unsafe { *(offset as *const u8) };
+            let length = unsafe { *((offset + 1) as *const u8) };
+            if length < 2 || offset + length as usize > end {
+                return None;
+            }
+            let body = offset + 2;
+            offset += length as usize;
+
+            let u8_at = |at: usize| unsafe { *((body + at) as *const u8) };
+            let u16_at = |at: usize| unsafe { ptr::read_unaligned((body + at) as *const u16) };
+            let u32_at = |at: usize| unsafe { ptr::read_unaligned((body + at) as *const u32) };
+            let u64_at = |at: usize| unsafe { ptr::read_unaligned((body + at) as *const u64) };
+            Some(match entry_type {
+                0 => MadtEntry::LocalApic { processor_id: u8_at(0), apic_id: u8_at(1), flags: u32_at(2) },
+                1 => MadtEntry::IoApic { id: u8_at(0), address: u32_at(2), gsi_base: u32_at(6) },
+                2 => MadtEntry::InterruptSourceOverride { bus: u8_at(0), source: u8_at(1), gsi: u32_at(2), flags: u16_at(6) },
+                4 => MadtEntry::LocalApicNmi { processor_id: u8_at(0), flags: u16_at(1), lint: u8_at(3) },
+                5 => MadtEntry::LocalApicAddressOverride { address: u64_at(2) },
+                _ => MadtEntry::Unknown { entry_type, length },
+            })
+        })
+    }
+}
@@ -0,0 +1,40 @@
+use core::{mem, ptr};
+use super::SdtHeader;
+
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct McfgHeader {
+    pub header: SdtHeader,
+    reserved: u64,
+}
+
+// Configuration space of one PCI segment group, covering the buses start_bus..=end_bus.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct McfgEntry {
+    pub base_address: u64,
+    pub segment_group: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+    reserved: u32,
+}
+
+// The MCFG table: a header followed by an array of configuration space allocations.
+#[derive(Clone, Copy)]
+pub struct Mcfg {
+    pub table: &'static McfgHeader,
+}
+
+impl Mcfg {
+    pub fn new(table: &'static McfgHeader) -> Self {
+        Mcfg { table }
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = McfgEntry> {
+        let start = self.table as *const McfgHeader as usize + mem::size_of::<McfgHeader>();
+        let count = (self.table.header.length as usize - mem::size_of::<McfgHeader>()) / mem::size_of::<McfgEntry>();
+        (0..count).map(move |i| unsafe {
+            ptr::read_unaligned((start + i * mem::size_of::<McfgEntry>()) as *const McfgEntry)
+        })
+    }
+}
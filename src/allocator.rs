@@ -1,3 +1,4 @@
+use crate::sync::{SpinLock, SpinLockGuard};
 use x86_64::{
     structures::paging::{
         mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
@@ -21,19 +22,21 @@ static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAl
 pub const HEAP_START: usize = 0x_4444_4444_0000;
 pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
 
-// A wrapper around spin::Mutex to permit trait implementations.
+// A wrapper around SpinLock to permit trait implementations. Interrupts are disabled
+// while the allocator is locked, so a handler allocating can't deadlock on it.
 pub struct Locked<T> {
-    inner: spin::Mutex<T>,
+    inner: SpinLock<T>,
 }
 
 impl<T> Locked<T> {
     pub const fn new(inner: T) -> Self {
         Locked {
-            inner: spin::Mutex::new(inner),
+            inner: SpinLock::new(inner),
         }
     }
 
-    pub fn lock(&self) -> spin::MutexGuard<T> {
+    #[track_caller]
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
         self.inner.lock()
     }
 }
@@ -0,0 +1,121 @@
+use core::ptr;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::{PhysAddr, VirtAddr};
+use crate::{acpi, memory};
+
+// virtual address the local APIC registers get mapped at; every CPU sees its own APIC there
+pub const LAPIC_VIRT_BASE: u64 = 0x_5555_5556_0000;
+
+// vector of the local APIC's spurious interrupt
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+// vector of the local APIC timer
+pub const TIMER_VECTOR: u8 = 0xfe;
+
+// register offsets
+const ID: u64 = 0x020;
+const EOI: u64 = 0x0b0;
+const SPURIOUS_INTERRUPT_VECTOR: u64 = 0x0f0;
+const ERROR_STATUS: u64 = 0x280;
+const ICR_LOW: u64 = 0x300;
+const ICR_HIGH: u64 = 0x310;
+const LVT_TIMER: u64 = 0x320;
+const TIMER_INITIAL_COUNT: u64 = 0x380;
+const TIMER_CURRENT_COUNT: u64 = 0x390;
+const TIMER_DIVIDE: u64 = 0x3e0;
+
+// spurious interrupt vector register: software enable
+const APIC_ENABLE: u32 = 1 << 8;
+
+// interrupt command register bits
+const ICR_DELIVERY_INIT: u32 = 0b101 << 8;
+const ICR_DELIVERY_STARTUP: u32 = 0b110 << 8;
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+const ICR_TRIGGER_LEVEL: u32 = 1 << 15;
+
+// LVT timer register: masked, and periodic instead of one shot
+const TIMER_MASKED: u32 = 1 << 16;
+const TIMER_PERIODIC: u32 = 1 << 17;
+// divide configuration for dividing the bus clock by 16
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+// how long the timer is measured against `time::busy_wait_us`
+const TIMER_CALIBRATION_US: u64 = 10_000;
+
+static BASE: AtomicU64 = AtomicU64::new(0);
+
+fn read(offset: u64) -> u32 {
+    let base = BASE.load(Ordering::Relaxed);
+    unsafe { ptr::read_volatile((base + offset) as *const u32) }
+}
+
+fn write(offset: u64, value: u32) {
+    let base = BASE.load(Ordering::Relaxed);
+    unsafe { ptr::write_volatile((base + offset) as *mut u32, value) }
+}
+
+// Maps the local APIC registers found through the MADT. Must run once, on the BSP.
+pub fn init() -> Result<(), &'static str> {
+    let madt = acpi::madt().ok_or("no MADT")?;
+    let phys = PhysAddr::new(madt.local_apic_address());
+    let virt = memory::map_mmio(phys, VirtAddr::new(LAPIC_VIRT_BASE), 4096).map_err(|_| "mapping the local APIC failed")?;
+    BASE.store(virt.as_u64(), Ordering::Relaxed);
+    enable();
+    Ok(())
+}
+
+// Software enables the local APIC of the calling CPU.
+pub fn enable() {
+    write(SPURIOUS_INTERRUPT_VECTOR, APIC_ENABLE | SPURIOUS_VECTOR as u32);
+}
+
+pub fn is_initialized() -> bool {
+    BASE.load(Ordering::Relaxed) != 0
+}
+
+// APIC ID of the calling CPU.
+pub fn id() -> u8 {
+    (read(ID) >> 24) as u8
+}
+
+// Signals the end of an interrupt delivered through the local APIC.
+pub fn end_of_interrupt() {
+    write(EOI, 0);
+}
+
+// Starts the calling CPU's local APIC timer, raising TIMER_VECTOR every `period_us`
+// microseconds. Its frequency is measured first, which needs the system tick.
+pub fn start_timer(period_us: u64) {
+    write(TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+    write(LVT_TIMER, TIMER_MASKED | TIMER_VECTOR as u32);
+    write(TIMER_INITIAL_COUNT, u32::MAX);
+    crate::time::busy_wait_us(TIMER_CALIBRATION_US);
+    let counted = (u32::MAX - read(TIMER_CURRENT_COUNT)) as u64;
+    let count = (counted * period_us / TIMER_CALIBRATION_US).clamp(1, u32::MAX as u64);
+    write(LVT_TIMER, TIMER_PERIODIC | TIMER_VECTOR as u32);
+    write(TIMER_INITIAL_COUNT, count as u32);
+}
+
+fn wait_for_delivery() {
+    while read(ICR_LOW) & ICR_DELIVERY_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+fn send_ipi(apic_id: u8, command: u32) {
+    write(ERROR_STATUS, 0);
+    write(ICR_HIGH, (apic_id as u32) << 24);
+    write(ICR_LOW, command);
+    wait_for_delivery();
+}
+
+// Puts the target CPU into its wait-for-SIPI state.
+pub fn send_init(apic_id: u8) {
+    send_ipi(apic_id, ICR_DELIVERY_INIT | ICR_LEVEL_ASSERT | ICR_TRIGGER_LEVEL);
+    // deassert, required by older CPUs and harmless on newer ones
+    send_ipi(apic_id, ICR_DELIVERY_INIT | ICR_TRIGGER_LEVEL);
+}
+
+// Starts the target CPU in real mode at physical address `page << 12`.
+pub fn send_startup(apic_id: u8, page: u8) {
+    send_ipi(apic_id, ICR_DELIVERY_STARTUP | page as u32);
+}
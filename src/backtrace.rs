@@ -0,0 +1,96 @@
+use core::arch::asm;
+use core::fmt;
+use core::str;
+use x86_64::VirtAddr;
+use crate::memory;
+
+// Symbol table embedded by build.rs: "<hex address> <name>\n" lines sorted by
+// address, zero padded. All zeroes when the kernel wasn't built with tools/ksyms.sh.
+static KSYMS: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/ksyms.bin"));
+
+// stop walking corrupted or cyclic chains after this many frames
+const MAX_FRAMES: usize = 32;
+
+// Iterates over the (address, name) entries of the embedded symbol table.
+fn symbols() -> impl Iterator<Item = (u64, &'static str)> {
+    let len = KSYMS.iter().position(|&byte| byte == 0).unwrap_or(KSYMS.len());
+    KSYMS[..len].split(|&byte| byte == b'\n').filter_map(|line| {
+        let line = str::from_utf8(line).ok()?;
+        let (addr, name) = line.split_once(' ')?;
+        Some((u64::from_str_radix(addr, 16).ok()?, name))
+    })
+}
+
+// Returns the function containing `addr` and the offset into it, when a symbol table is embedded.
+pub fn resolve(addr: u64) -> Option<(&'static str, u64)> {
+    symbols()
+        .take_while(|(start, _)| *start <= addr)
+        .last()
+        .map(|(start, name)| (name, addr - start))
+}
+
+fn is_readable(addr: u64) -> bool {
+    // non-canonical addresses would fault even before the page table is consulted
+    VirtAddr::try_new(addr).is_ok_and(memory::is_mapped)
+}
+
+// Calls `f` with the return address of every frame on the RBP chain starting at `rbp`.
+pub fn walk_from(mut rbp: u64, mut f: impl FnMut(u64)) {
+    for _ in 0..MAX_FRAMES {
+        // a frame record is [saved rbp, return address]
+        if rbp == 0 || !rbp.is_multiple_of(8) || !is_readable(rbp) || !is_readable(rbp + 8) {
+            break;
+        }
+        let frame = rbp as *const u64;
+        let (next, return_address) = unsafe { (*frame, *frame.add(1)) };
+        if return_address == 0 {
+            break;
+        }
+        f(return_address);
+        // the stack grows down, so callers' frames must be at higher addresses
+        if next <= rbp {
+            break;
+        }
+        rbp = next;
+    }
+}
+
+// Walks the RBP chain of the caller.
+#[inline(always)]
+pub fn walk(f: impl FnMut(u64)) {
+    let rbp: u64;
+    unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+    walk_from(rbp, f);
+}
+
+// Writes to both the VGA buffer and the serial port, so backtraces survive a dead screen.
+fn emit(args: fmt::Arguments) {
+    crate::vga_buffer::_print(args);
+    crate::serial::_print(args);
+}
+
+// Prints a single frame, symbolized when possible.
+pub fn print_frame(index: usize, addr: u64) {
+    match resolve(addr) {
+        Some((name, offset)) => emit(format_args!("  #{:<2} {:#018x} {}+{:#x}\n", index, addr, name, offset)),
+        None => emit(format_args!("  #{:<2} {:#018x}\n", index, addr)),
+    }
+}
+
+// Prints the return addresses of the calling code to VGA and serial.
+#[inline(always)]
+pub fn print_backtrace() {
+    emit(format_args!("Backtrace:\n"));
+    let mut index = 0;
+    walk(|addr| {
+        print_frame(index, addr);
+        index += 1;
+    });
+}
+
+#[test_case]
+fn test_walk_finds_frames() {
+    let mut frames = 0;
+    walk(|_| frames += 1);
+    assert!(frames > 0);
+}
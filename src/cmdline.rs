@@ -0,0 +1,105 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use x86_64::instructions::port::Port;
+use crate::sync::SpinLock;
+
+// Kernel command line: space separated `key=value` options. The bootloader doesn't
+// pass one, so it comes from QEMU's firmware configuration device (fw_cfg) as the
+// file `opt/rustos/cmdline`, e.g. `-fw_cfg name=opt/rustos/cmdline,string=tick=pit`.
+
+// fw_cfg I/O ports: the item selector and the data port reading through the selected item
+const FW_CFG_SELECTOR: u16 = 0x510;
+const FW_CFG_DATA: u16 = 0x511;
+// items at fixed selectors
+const FW_CFG_SIGNATURE: u16 = 0x0000;
+const FW_CFG_FILE_DIR: u16 = 0x0019;
+// a file directory entry: big endian size and selector, 2 reserved bytes, the name
+const FILE_ENTRY_SIZE: usize = 64;
+const FILE_NAME_OFFSET: usize = 8;
+
+const CMDLINE_FILE: &str = "opt/rustos/cmdline";
+
+static CMDLINE: SpinLock<String> = SpinLock::new(String::new());
+
+fn fw_cfg_select(item: u16) {
+    unsafe { Port::<u16>::new(FW_CFG_SELECTOR).write(item) };
+}
+
+// Reads the next `buf.len()` bytes of the selected item.
+fn fw_cfg_read(buf: &mut [u8]) {
+    let mut data = Port::<u8>::new(FW_CFG_DATA);
+    for byte in buf {
+        *byte = unsafe { data.read() };
+    }
+}
+
+// Contents of the fw_cfg file `name`; None without the file or without fw_cfg,
+// whose ports then read as all ones.
+pub fn fw_cfg_file(name: &str) -> Option<Vec<u8>> {
+    let mut signature = [0u8; 4];
+    fw_cfg_select(FW_CFG_SIGNATURE);
+    fw_cfg_read(&mut signature);
+    if &signature != b"QEMU" {
+        return None;
+    }
+    let mut count = [0u8; 4];
+    fw_cfg_select(FW_CFG_FILE_DIR);
+    fw_cfg_read(&mut count);
+    for _ in 0..u32::from_be_bytes(count) {
+        let mut entry = [0u8; FILE_ENTRY_SIZE];
+        fw_cfg_read(&mut entry);
+        let entry_name = &entry[FILE_NAME_OFFSET..];
+        let len = entry_name.iter().position(|&byte| byte == 0).unwrap_or(entry_name.len());
+        if &entry_name[..len] == name.as_bytes() {
+            let size = u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]) as usize;
+            let mut contents = vec![0; size];
+            fw_cfg_select(u16::from_be_bytes([entry[4], entry[5]]));
+            fw_cfg_read(&mut contents);
+            return Some(contents);
+        }
+    }
+    None
+}
+
+// The value of option `key` in `cmdline`, empty for a bare `key`. Like on Linux, a
+// later option overrides an earlier one.
+fn find<'a>(cmdline: &'a str, key: &str) -> Option<&'a str> {
+    cmdline.split_whitespace().rev().find_map(|option| {
+        let (name, value) = option.split_once('=').unwrap_or((option, ""));
+        (name == key).then_some(value)
+    })
+}
+
+// Reads the command line, once the heap is up. Without one it stays empty.
+pub fn init() {
+    if let Some(contents) = fw_cfg_file(CMDLINE_FILE) {
+        let cmdline = String::from_utf8_lossy(&contents);
+        *CMDLINE.lock() = String::from(cmdline.trim_matches(|c: char| c == '\0' || c.is_whitespace()));
+    }
+}
+
+pub fn get() -> String {
+    CMDLINE.lock().clone()
+}
+
+// The value of option `key`, e.g. "pit" for `tick=pit`.
+pub fn value(key: &str) -> Option<String> {
+    find(&CMDLINE.lock(), key).map(String::from)
+}
+
+#[test_case]
+fn test_options_are_found_by_key() {
+    let cmdline = "tick=pit quiet watchdog=500 tick=hpet";
+    assert_eq!(find(cmdline, "tick"), Some("hpet"));
+    assert_eq!(find(cmdline, "watchdog"), Some("500"));
+    assert_eq!(find(cmdline, "quiet"), Some(""));
+    assert_eq!(find(cmdline, "tic"), None);
+    assert_eq!(find("", "tick"), None);
+}
+
+#[test_case]
+fn test_unknown_fw_cfg_files_are_missing() {
+    // QEMU always has fw_cfg, but not this file
+    assert_eq!(fw_cfg_file("opt/rustos/missing"), None);
+}
@@ -0,0 +1,130 @@
+use core::mem;
+use core::ptr;
+
+// ELF identification
+const MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const CLASS_64: u8 = 2;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+// e_type and e_machine
+const TYPE_EXECUTABLE: u16 = 2;
+const MACHINE_X86_64: u16 = 62;
+
+// program header types and flags
+pub const PT_LOAD: u32 = 1;
+pub const PF_X: u32 = 1 << 0;
+pub const PF_W: u32 = 1 << 1;
+pub const PF_R: u32 = 1 << 2;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ElfHeader {
+    pub ident: [u8; 16],
+    pub elf_type: u16,
+    pub machine: u16,
+    pub version: u32,
+    pub entry: u64,
+    pub phoff: u64,
+    pub shoff: u64,
+    pub flags: u32,
+    pub ehsize: u16,
+    pub phentsize: u16,
+    pub phnum: u16,
+    pub shentsize: u16,
+    pub shnum: u16,
+    pub shstrndx: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub paddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+    pub align: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    TooShort,
+    BadMagic,
+    // not a little endian ELF64 file
+    UnsupportedClass,
+    NotX86_64,
+    // relocatable, shared object or core file
+    NotExecutable,
+    BadProgramHeader,
+}
+
+// A validated ELF64 executable for x86_64.
+pub struct Elf<'a> {
+    data: &'a [u8],
+    header: ElfHeader,
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        if data.len() < mem::size_of::<ElfHeader>() {
+            return Err(ElfError::TooShort);
+        }
+        // the data comes from a Vec<u8> and needn't be aligned
+        let header = unsafe { ptr::read_unaligned(data.as_ptr() as *const ElfHeader) };
+        if header.ident[..4] != MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+        if header.ident[4] != CLASS_64 || header.ident[5] != DATA_LITTLE_ENDIAN {
+            return Err(ElfError::UnsupportedClass);
+        }
+        if header.machine != MACHINE_X86_64 {
+            return Err(ElfError::NotX86_64);
+        }
+        if header.elf_type != TYPE_EXECUTABLE {
+            return Err(ElfError::NotExecutable);
+        }
+        let table_size = header.phnum as u64 * mem::size_of::<ProgramHeader>() as u64;
+        if header.phentsize as usize != mem::size_of::<ProgramHeader>()
+            || header.phoff.checked_add(table_size).is_none_or(|end| end > data.len() as u64)
+        {
+            return Err(ElfError::BadProgramHeader);
+        }
+
+        let elf = Elf { data, header };
+        for segment in elf.program_headers() {
+            let in_file = segment.offset.checked_add(segment.filesz).is_some_and(|end| end <= data.len() as u64);
+            if !in_file || segment.filesz > segment.memsz || segment.vaddr.checked_add(segment.memsz).is_none() {
+                return Err(ElfError::BadProgramHeader);
+            }
+        }
+        Ok(elf)
+    }
+
+    pub fn header(&self) -> &ElfHeader {
+        &self.header
+    }
+
+    pub fn entry(&self) -> u64 {
+        self.header.entry
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        let data = self.data;
+        let phoff = self.header.phoff as usize;
+        (0..self.header.phnum as usize).map(move |i| {
+            let offset = phoff + i * mem::size_of::<ProgramHeader>();
+            unsafe { ptr::read_unaligned(data[offset..].as_ptr() as *const ProgramHeader) }
+        })
+    }
+
+    // The PT_LOAD segments, the only ones that need to be mapped.
+    pub fn load_segments(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        self.program_headers().filter(|segment| segment.p_type == PT_LOAD)
+    }
+
+    // The bytes of `segment` stored in the file; the rest up to `memsz` is BSS.
+    pub fn segment_data(&self, segment: &ProgramHeader) -> &'a [u8] {
+        &self.data[segment.offset as usize..(segment.offset + segment.filesz) as usize]
+    }
+}
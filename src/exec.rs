@@ -0,0 +1,125 @@
+use alloc::vec::Vec;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+use crate::elf::{Elf, ElfError, ProgramHeader, PF_W, PF_X};
+use crate::fs::FILE_SYSTEM;
+use crate::memory::{AddressSpace, USER_END, USER_START};
+use crate::process;
+use crate::usermode::UserExit;
+
+// the user stack sits at the very end of the user range
+pub const USER_STACK_TOP: u64 = USER_END;
+const USER_STACK_PAGES: u64 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecError {
+    NotFound,
+    Elf(ElfError),
+    // a segment or the entry point lies outside the user range
+    BadAddress,
+    OutOfMemory,
+    ArgumentsTooLarge,
+}
+
+impl From<ElfError> for ExecError {
+    fn from(err: ElfError) -> Self {
+        ExecError::Elf(err)
+    }
+}
+
+fn load_segment(space: &mut AddressSpace, elf: &Elf, segment: &ProgramHeader) -> Result<(), ExecError> {
+    if segment.memsz == 0 {
+        return Ok(());
+    }
+    let (start, end) = (segment.vaddr, segment.vaddr + segment.memsz);
+    if start < USER_START || end > USER_END {
+        return Err(ExecError::BadAddress);
+    }
+    let mut flags = PageTableFlags::empty();
+    if segment.flags & PF_W != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if segment.flags & PF_X == 0 {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    let first_page = start & !0xfff;
+    let pages = (end - first_page).div_ceil(4096);
+    space.map_user_pages(VirtAddr::new(first_page), pages, flags).map_err(|_| ExecError::OutOfMemory)?;
+    // the pages come zeroed, which takes care of the BSS part
+    space.write(VirtAddr::new(start), elf.segment_data(segment));
+    Ok(())
+}
+
+// Maps the user stack and lays out the System V process entry state on it: argc at the
+// (16 byte aligned) stack pointer, followed by the argv and envp pointer arrays, each
+// null terminated, and an empty auxiliary vector. The strings live above.
+fn setup_stack(space: &mut AddressSpace, args: &[&str], env: &[&str]) -> Result<VirtAddr, ExecError> {
+    let stack_bottom = USER_STACK_TOP - USER_STACK_PAGES * 4096;
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    space.map_user_pages(VirtAddr::new(stack_bottom), USER_STACK_PAGES, flags).map_err(|_| ExecError::OutOfMemory)?;
+
+    let strings_size: u64 = args.iter().chain(env).map(|s| s.len() as u64 + 1).sum();
+    let strings_start = (USER_STACK_TOP - strings_size) & !0xf;
+    let words = 1 + args.len() + 1 + env.len() + 1 + 2;
+    let sp = (strings_start - words as u64 * 8) & !0xf;
+    // keep most of the stack for the program
+    if USER_STACK_TOP - sp > USER_STACK_PAGES * 4096 / 2 {
+        return Err(ExecError::ArgumentsTooLarge);
+    }
+
+    let mut strings = Vec::with_capacity(strings_size as usize);
+    let mut vector: Vec<u64> = Vec::with_capacity(words);
+    let mut add_string = |s: &str| {
+        let address = strings_start + strings.len() as u64;
+        strings.extend_from_slice(s.as_bytes());
+        strings.push(0);
+        address
+    };
+    vector.push(args.len() as u64);
+    vector.extend(args.iter().map(|s| add_string(s)));
+    // end of argv, also when there are no arguments
+    vector.push(0);
+    vector.extend(env.iter().map(|s| add_string(s)));
+    // end of envp, then AT_NULL
+    vector.extend_from_slice(&[0, 0, 0]);
+
+    space.write(VirtAddr::new(strings_start), &strings);
+    let vector_bytes: Vec<u8> = vector.iter().flat_map(|word| word.to_le_bytes()).collect();
+    space.write(VirtAddr::new(sp), &vector_bytes);
+    Ok(VirtAddr::new(sp))
+}
+
+// An executable loaded into its address space, ready to run.
+pub struct Program {
+    pub space: AddressSpace,
+    pub entry: VirtAddr,
+    pub stack_pointer: VirtAddr,
+}
+
+// Loads the ELF executable `image` into a fresh address space, with the given
+// arguments and environment on its stack.
+pub fn load(image: &[u8], args: &[&str], env: &[&str]) -> Result<Program, ExecError> {
+    let elf = Elf::parse(image)?;
+    if elf.entry() < USER_START || elf.entry() >= USER_END {
+        return Err(ExecError::BadAddress);
+    }
+    let mut space = AddressSpace::new().map_err(|_| ExecError::OutOfMemory)?;
+    for segment in elf.load_segments() {
+        load_segment(&mut space, &elf, &segment)?;
+    }
+    let stack_pointer = setup_stack(&mut space, args, env)?;
+    Ok(Program { space, entry: VirtAddr::new(elf.entry()), stack_pointer })
+}
+
+// Runs the ELF executable `image` as a child process and waits until it exits.
+pub fn exec(image: &[u8], args: &[&str], env: &[&str]) -> Result<UserExit, ExecError> {
+    let pid = process::spawn(image, args, env)?;
+    let (_, exit) = process::wait(Some(pid)).expect("spawned process vanished");
+    Ok(exit)
+}
+
+// Runs the executable stored at `path`; `args[0]` is conventionally the path itself.
+pub fn exec_file(path: &str, args: &[&str]) -> Result<UserExit, ExecError> {
+    let image = FILE_SYSTEM.lock().file(path).ok_or(ExecError::NotFound)?.data.clone();
+    exec(&image, args, &[])
+}
@@ -0,0 +1,63 @@
+use core::arch::asm;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+
+// The kernel itself is built without SSE (see x86_64-rustos.json), so only user
+// programs and explicit assembly touch the x87/SSE registers. Their state is
+// switched lazily: a thread switch just sets CR0.TS, and the #NM exception raised by
+// the next FPU instruction saves the previous owner's registers and loads the
+// running thread's, see `thread::fpu_trap`.
+
+// x87 control word and MXCSR after FNINIT: all exceptions masked, round to nearest
+const DEFAULT_FCW: u16 = 0x037f;
+const DEFAULT_MXCSR: u32 = 0x1f80;
+
+// The FXSAVE area: x87, MMX and SSE registers plus control and status words.
+#[repr(C, align(16))]
+pub struct FpuState([u8; 512]);
+
+impl FpuState {
+    // The registers as a thread starts out with them.
+    pub fn new() -> Self {
+        let mut area = [0; 512];
+        area[0..2].copy_from_slice(&DEFAULT_FCW.to_le_bytes());
+        area[24..28].copy_from_slice(&DEFAULT_MXCSR.to_le_bytes());
+        FpuState(area)
+    }
+
+    // Stores the CPU's FPU registers in here. CR0.TS must be clear.
+    pub(crate) unsafe fn save(&mut self) {
+        asm!("fxsave64 [{}]", in(reg) self.0.as_mut_ptr(), options(nostack, preserves_flags));
+    }
+
+    // Loads the CPU's FPU registers from here. CR0.TS must be clear.
+    pub(crate) unsafe fn restore(&self) {
+        asm!("fxrstor64 [{}]", in(reg) self.0.as_ptr(), options(nostack, preserves_flags, readonly));
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Enables the FPU and SSE on this CPU: no x87 emulation, FXSAVE/FXRSTOR and SSE
+// exceptions allowed, and #NM on the first FPU instruction after a thread switch.
+pub fn init() {
+    unsafe {
+        Cr0::update(|flags| {
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR);
+            flags.insert(Cr0Flags::MONITOR_COPROCESSOR | Cr0Flags::NUMERIC_ERROR | Cr0Flags::TASK_SWITCHED);
+        });
+        Cr4::update(|flags| flags.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE));
+    }
+}
+
+// Whether the next FPU instruction traps with #NM.
+pub fn set_trap(enabled: bool) {
+    if Cr0::read().contains(Cr0Flags::TASK_SWITCHED) != enabled {
+        unsafe {
+            Cr0::update(|flags| flags.set(Cr0Flags::TASK_SWITCHED, enabled));
+        }
+    }
+}
@@ -5,6 +5,15 @@ use alloc::string::String;
 use alloc::vec::Vec;
 use alloc::format;
 use crate::print;
+use crate::sync::SpinLock;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    // The file system shared by the shell and user programs (through system calls).
+    // The shell holds it while running commands, so interrupts stay enabled.
+    pub static ref FILE_SYSTEM: SpinLock<FileSystem> = SpinLock::new_interruptible(FileSystem::new());
+}
+
 pub struct File {
     pub name: String,
     pub data: Vec<u8>,
@@ -182,4 +191,78 @@ impl FileSystem {
             println!("File '{}' not found.", name);
         }
     }
+
+    // Turns `path`, relative to the current directory unless it starts with '/', into
+    // its components, with "." and ".." resolved.
+    pub fn resolve(&self, path: &str) -> Vec<String> {
+        let mut components: Vec<String> = Vec::new();
+        if !path.starts_with('/') {
+            components.extend(self.current_directory.split('/').filter(|s| !s.is_empty()).map(String::from));
+        }
+        for component in path.split('/').filter(|s| !s.is_empty()) {
+            match component {
+                "." => {}
+                ".." => {
+                    components.pop();
+                }
+                name => components.push(String::from(name)),
+            }
+        }
+        components
+    }
+
+    fn directory(&self, components: &[String]) -> Option<&Directory> {
+        let mut current = &self.root;
+        for component in components {
+            current = current.subdirectories.iter().find(|dir| &dir.name == component)?;
+        }
+        Some(current)
+    }
+
+    fn directory_mut(&mut self, components: &[String]) -> Option<&mut Directory> {
+        let mut current = &mut self.root;
+        for component in components {
+            current = current.subdirectories.iter_mut().find(|dir| &dir.name == component)?;
+        }
+        Some(current)
+    }
+
+    // Looks up a file by path without printing anything, for use by system calls.
+    pub fn file(&self, path: &str) -> Option<&File> {
+        let components = self.resolve(path);
+        let (name, parent) = components.split_last()?;
+        self.directory(parent)?.files.iter().find(|file| &file.name == name)
+    }
+
+    pub fn file_mut(&mut self, path: &str) -> Option<&mut File> {
+        let components = self.resolve(path);
+        let (name, parent) = components.split_last()?;
+        self.directory_mut(parent)?.files.iter_mut().find(|file| &file.name == name)
+    }
+
+    // Creates the directory at `path` unless it exists already. Fails when the parent
+    // directory doesn't exist.
+    pub fn create_directory_at(&mut self, path: &str) -> Option<&mut Directory> {
+        let components = self.resolve(path);
+        let (name, parent) = components.split_last()?;
+        let directory = self.directory_mut(parent)?;
+        if let Some(index) = directory.subdirectories.iter().position(|dir| &dir.name == name) {
+            return Some(&mut directory.subdirectories[index]);
+        }
+        directory.subdirectories.push(Directory { name: name.clone(), files: Vec::new(), subdirectories: Vec::new() });
+        directory.subdirectories.last_mut()
+    }
+
+    // Creates an empty file at `path` unless it exists already. Fails when the
+    // parent directory doesn't exist.
+    pub fn create_file_at(&mut self, path: &str) -> Option<&mut File> {
+        let components = self.resolve(path);
+        let (name, parent) = components.split_last()?;
+        let directory = self.directory_mut(parent)?;
+        if let Some(index) = directory.files.iter().position(|file| &file.name == name) {
+            return Some(&mut directory.files[index]);
+        }
+        directory.files.push(File { name: name.clone(), data: Vec::new() });
+        directory.files.last_mut()
+    }
 }
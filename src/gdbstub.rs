@@ -0,0 +1,661 @@
+// Kernel side GDB Remote Serial Protocol stub on COM2.
+//
+// Once `init` ran, breakpoint (#BP) and debug (#DB) exceptions stop the kernel
+// and hand control to a GDB connected to the second serial port, e.g. with
+// `-serial stdio -serial tcp::1234,server,nowait` and `target remote :1234`.
+use core::arch::asm;
+use spin::Mutex;
+use uart_16550::SerialPort;
+use x86_64::registers::control::{Cr0, Cr0Flags};
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::VirtAddr;
+use crate::irq::{self, IrqReturn};
+use crate::memory;
+use crate::trap::TrapFrame;
+
+const COM2_BASE: u16 = 0x2f8;
+const COM2_IRQ: u8 = 3;
+
+const MAX_PACKET: usize = 1024;
+const MAX_BREAKPOINTS: usize = 32;
+// the largest memory read that still fits into a reply packet
+const MAX_MEMORY_READ: usize = (MAX_PACKET - 4) / 2;
+
+// trap flag in RFLAGS, raises #DB after every instruction
+const RFLAGS_TF: u64 = 1 << 8;
+const INT3: u8 = 0xcc;
+const SIGTRAP: u8 = 5;
+// GDB sends ^C out of band to interrupt the target
+const INTERRUPT_REQUEST: u8 = 0x03;
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+// number of registers in the 'g' packet: 16 GPRs, rip, eflags, cs, ss, ds, es, fs, gs
+const NUM_REGISTERS: usize = 24;
+
+struct Breakpoint {
+    addr: u64,
+    original: u8,
+}
+
+struct GdbStub {
+    port: SerialPort,
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+}
+
+static STUB: Mutex<Option<GdbStub>> = Mutex::new(None);
+
+// Bytes of a reply packet, built without allocating since the heap might be what's being debugged.
+struct Reply {
+    data: [u8; MAX_PACKET],
+    len: usize,
+}
+
+impl Reply {
+    fn new() -> Self {
+        Reply { data: [0; MAX_PACKET], len: 0 }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.len < MAX_PACKET {
+            self.data[self.len] = byte;
+            self.len += 1;
+        }
+    }
+
+    fn push_str(&mut self, s: &str) {
+        s.bytes().for_each(|byte| self.push(byte));
+    }
+
+    fn push_hex_byte(&mut self, byte: u8) {
+        self.push(HEX[(byte >> 4) as usize]);
+        self.push(HEX[(byte & 0xf) as usize]);
+    }
+
+    // registers are transferred in target (little endian) byte order
+    fn push_le(&mut self, value: u64, bytes: usize) {
+        value.to_le_bytes()[..bytes].iter().for_each(|byte| self.push_hex_byte(*byte));
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+// Sum of the packet data modulo 256, sent as two hex digits after the '#'.
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+// The bytes of `$data#checksum` as they go over the wire.
+fn framed(data: &[u8]) -> impl Iterator<Item = u8> + '_ {
+    let checksum = checksum(data);
+    let trailer = [b'#', HEX[(checksum >> 4) as usize], HEX[(checksum & 0xf) as usize]];
+    core::iter::once(b'$').chain(data.iter().copied()).chain(trailer)
+}
+
+// What the last byte fed to a `PacketDecoder` completed.
+#[derive(Debug, PartialEq, Eq)]
+enum Decoded {
+    Incomplete,
+    Packet,
+    // the checksum didn't match, GDB has to send the packet again
+    Corrupt,
+}
+
+#[derive(Clone, Copy)]
+enum DecodeState {
+    // anything before the start of a packet (acks, ^C) is ignored while stopped
+    Idle,
+    Data,
+    ChecksumHigh,
+    // the high digit, None if it wasn't a hex digit
+    ChecksumLow(Option<u8>),
+}
+
+// Picks `$data#checksum` packets out of the bytes received from GDB, one byte at
+// a time. Data beyond MAX_PACKET is dropped but still counts for the checksum.
+struct PacketDecoder {
+    buf: [u8; MAX_PACKET],
+    len: usize,
+    checksum: u8,
+    state: DecodeState,
+}
+
+impl PacketDecoder {
+    fn new() -> Self {
+        PacketDecoder { buf: [0; MAX_PACKET], len: 0, checksum: 0, state: DecodeState::Idle }
+    }
+
+    fn feed(&mut self, byte: u8) -> Decoded {
+        match self.state {
+            DecodeState::Idle if byte == b'$' => {
+                self.len = 0;
+                self.checksum = 0;
+                self.state = DecodeState::Data;
+            }
+            DecodeState::Idle => {}
+            DecodeState::Data if byte == b'#' => self.state = DecodeState::ChecksumHigh,
+            DecodeState::Data => {
+                self.checksum = self.checksum.wrapping_add(byte);
+                if self.len < MAX_PACKET {
+                    self.buf[self.len] = byte;
+                    self.len += 1;
+                }
+            }
+            DecodeState::ChecksumHigh => self.state = DecodeState::ChecksumLow(hex_digit(byte)),
+            DecodeState::ChecksumLow(high) => {
+                self.state = DecodeState::Idle;
+                return match (high, hex_digit(byte)) {
+                    (Some(high), Some(low)) if high << 4 | low == self.checksum => Decoded::Packet,
+                    _ => Decoded::Corrupt,
+                };
+            }
+        }
+        Decoded::Incomplete
+    }
+
+    // The data of the last complete packet.
+    fn packet(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn parse_hex(s: &[u8]) -> Option<u64> {
+    if s.is_empty() {
+        return None;
+    }
+    s.iter().try_fold(0u64, |value, c| Some(value << 4 | hex_digit(*c)? as u64))
+}
+
+// Decodes a little endian hex register value of `bytes` bytes.
+fn parse_le(s: &[u8], bytes: usize) -> Option<u64> {
+    if s.len() < bytes * 2 {
+        return None;
+    }
+    let mut raw = [0u8; 8];
+    for (i, byte) in raw.iter_mut().take(bytes).enumerate() {
+        *byte = (hex_digit(s[2 * i])? << 4) | hex_digit(s[2 * i + 1])?;
+    }
+    Some(u64::from_le_bytes(raw))
+}
+
+// Splits "addr,len" (optionally followed by ":data") into its parts.
+fn parse_addr_len(args: &[u8]) -> Option<(u64, usize, &[u8])> {
+    let (range, data) = match args.iter().position(|&c| c == b':') {
+        Some(colon) => (&args[..colon], &args[colon + 1..]),
+        None => (args, &args[args.len()..]),
+    };
+    let comma = range.iter().position(|&c| c == b',')?;
+    let addr = parse_hex(&range[..comma])?;
+    let len = parse_hex(&range[comma + 1..])? as usize;
+    Some((addr, len, data))
+}
+
+fn segment_selectors() -> [u64; 4] {
+    let (ds, es, fs, gs): (u16, u16, u16, u16);
+    unsafe {
+        asm!("mov {:x}, ds", out(reg) ds, options(nomem, nostack, preserves_flags));
+        asm!("mov {:x}, es", out(reg) es, options(nomem, nostack, preserves_flags));
+        asm!("mov {:x}, fs", out(reg) fs, options(nomem, nostack, preserves_flags));
+        asm!("mov {:x}, gs", out(reg) gs, options(nomem, nostack, preserves_flags));
+    }
+    [ds as u64, es as u64, fs as u64, gs as u64]
+}
+
+// Register `n` in GDB's amd64 numbering, with its size in bytes.
+fn register(frame: &TrapFrame, n: usize) -> Option<(u64, usize)> {
+    let value = match n {
+        0 => frame.rax,
+        1 => frame.rbx,
+        2 => frame.rcx,
+        3 => frame.rdx,
+        4 => frame.rsi,
+        5 => frame.rdi,
+        6 => frame.rbp,
+        7 => frame.rsp,
+        8 => frame.r8,
+        9 => frame.r9,
+        10 => frame.r10,
+        11 => frame.r11,
+        12 => frame.r12,
+        13 => frame.r13,
+        14 => frame.r14,
+        15 => frame.r15,
+        16 => frame.rip,
+        17 => return Some((frame.rflags, 4)),
+        18 => return Some((frame.cs, 4)),
+        19 => return Some((frame.ss, 4)),
+        20..=23 => return Some((segment_selectors()[n - 20], 4)),
+        _ => return None,
+    };
+    Some((value, 8))
+}
+
+// Writes register `n`; the segment registers are read-only.
+fn set_register(frame: &mut TrapFrame, n: usize, value: u64) -> bool {
+    let slot = match n {
+        0 => &mut frame.rax,
+        1 => &mut frame.rbx,
+        2 => &mut frame.rcx,
+        3 => &mut frame.rdx,
+        4 => &mut frame.rsi,
+        5 => &mut frame.rdi,
+        6 => &mut frame.rbp,
+        7 => &mut frame.rsp,
+        8 => &mut frame.r8,
+        9 => &mut frame.r9,
+        10 => &mut frame.r10,
+        11 => &mut frame.r11,
+        12 => &mut frame.r12,
+        13 => &mut frame.r13,
+        14 => &mut frame.r14,
+        15 => &mut frame.r15,
+        16 => &mut frame.rip,
+        17 => &mut frame.rflags,
+        18..=23 => return true,
+        _ => return false,
+    };
+    *slot = value;
+    true
+}
+
+fn is_accessible(addr: u64, len: usize) -> bool {
+    (0..len as u64).all(|i| {
+        VirtAddr::try_new(addr.wrapping_add(i)).is_ok_and(memory::is_mapped)
+    })
+}
+
+// Writes to kernel memory even where it is mapped read-only, like the code
+// that breakpoints get patched into.
+unsafe fn write_memory(addr: u64, data: &[u8]) {
+    let cr0 = Cr0::read();
+    Cr0::write(cr0 - Cr0Flags::WRITE_PROTECT);
+    for (i, byte) in data.iter().enumerate() {
+        core::ptr::write_volatile((addr as *mut u8).add(i), *byte);
+    }
+    Cr0::write(cr0);
+}
+
+// What to do after a command was processed.
+enum Resume {
+    Stay,
+    Continue,
+    Step,
+}
+
+impl GdbStub {
+    fn read_byte(&mut self) -> u8 {
+        self.port.receive()
+    }
+
+    // Receives the next well formed packet, acknowledging it.
+    fn receive_packet<'a>(&mut self, decoder: &'a mut PacketDecoder) -> &'a [u8] {
+        loop {
+            match decoder.feed(self.read_byte()) {
+                Decoded::Packet => {
+                    self.port.send(b'+');
+                    return decoder.packet();
+                }
+                Decoded::Corrupt => self.port.send(b'-'),
+                Decoded::Incomplete => {}
+            }
+        }
+    }
+
+    // Sends `$data#checksum` until GDB acknowledges it.
+    fn send_packet(&mut self, data: &[u8]) {
+        loop {
+            framed(data).for_each(|byte| self.port.send(byte));
+            if self.read_byte() == b'+' {
+                return;
+            }
+        }
+    }
+
+    fn is_breakpoint(&self, addr: u64) -> bool {
+        self.breakpoints.iter().flatten().any(|bp| bp.addr == addr)
+    }
+
+    fn insert_breakpoint(&mut self, addr: u64) -> bool {
+        if self.is_breakpoint(addr) {
+            return true;
+        }
+        if !is_accessible(addr, 1) {
+            return false;
+        }
+        let slot = match self.breakpoints.iter_mut().find(|bp| bp.is_none()) {
+            Some(slot) => slot,
+            None => return false,
+        };
+        let original = unsafe { *(addr as *const u8) };
+        unsafe { write_memory(addr, &[INT3]) };
+        *slot = Some(Breakpoint { addr, original });
+        true
+    }
+
+    fn remove_breakpoint(&mut self, addr: u64) -> bool {
+        for slot in self.breakpoints.iter_mut() {
+            if let Some(bp) = slot {
+                if bp.addr == addr {
+                    unsafe { write_memory(bp.addr, &[bp.original]) };
+                    *slot = None;
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn remove_all_breakpoints(&mut self) {
+        for slot in self.breakpoints.iter_mut() {
+            if let Some(bp) = slot.take() {
+                unsafe { write_memory(bp.addr, &[bp.original]) };
+            }
+        }
+    }
+
+    fn handle_command(&mut self, packet: &[u8], frame: &mut TrapFrame, reply: &mut Reply) -> Resume {
+        let (command, args) = match packet.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Resume::Stay,
+        };
+        match command {
+            b'?' => {
+                reply.push(b'S');
+                reply.push_hex_byte(SIGTRAP);
+            }
+            b'g' => {
+                for n in 0..NUM_REGISTERS {
+                    let (value, bytes) = register(frame, n).unwrap();
+                    reply.push_le(value, bytes);
+                }
+            }
+            b'G' => {
+                let mut offset = 0;
+                for n in 0..NUM_REGISTERS {
+                    let bytes = register(frame, n).unwrap().1;
+                    match parse_le(&args[offset.min(args.len())..], bytes) {
+                        Some(value) => set_register(frame, n, value),
+                        None => break,
+                    };
+                    offset += bytes * 2;
+                }
+                reply.push_str("OK");
+            }
+            b'p' => match parse_hex(args).and_then(|n| register(frame, n as usize)) {
+                Some((value, bytes)) => reply.push_le(value, bytes),
+                None => reply.push_str("E00"),
+            },
+            b'P' => {
+                let parsed = args.iter().position(|&c| c == b'=').and_then(|eq| {
+                    let n = parse_hex(&args[..eq])? as usize;
+                    let bytes = register(frame, n)?.1;
+                    Some((n, parse_le(&args[eq + 1..], bytes)?))
+                });
+                match parsed {
+                    Some((n, value)) if set_register(frame, n, value) => reply.push_str("OK"),
+                    _ => reply.push_str("E00"),
+                }
+            }
+            b'm' => match parse_addr_len(args) {
+                Some((addr, len, _)) if is_accessible(addr, len.min(MAX_MEMORY_READ)) => {
+                    for i in 0..len.min(MAX_MEMORY_READ) {
+                        reply.push_hex_byte(unsafe { *((addr + i as u64) as *const u8) });
+                    }
+                }
+                _ => reply.push_str("E14"),
+            },
+            b'M' => match parse_addr_len(args) {
+                Some((addr, len, data)) if data.len() >= len * 2 && is_accessible(addr, len) => {
+                    for i in 0..len {
+                        let byte = parse_le(&data[2 * i..], 1).unwrap_or(0) as u8;
+                        unsafe { write_memory(addr + i as u64, &[byte]) };
+                    }
+                    reply.push_str("OK");
+                }
+                _ => reply.push_str("E14"),
+            },
+            b'Z' | b'z' if args.starts_with(b"0,") => {
+                let addr = args[2..].iter().position(|&c| c == b',').and_then(|comma| parse_hex(&args[2..2 + comma]));
+                let done = match addr {
+                    Some(addr) if command == b'Z' => self.insert_breakpoint(addr),
+                    Some(addr) => self.remove_breakpoint(addr),
+                    None => false,
+                };
+                reply.push_str(if done { "OK" } else { "E00" });
+            }
+            b'c' | b's' => {
+                if let Some(addr) = parse_hex(args) {
+                    frame.rip = addr;
+                }
+                return if command == b's' { Resume::Step } else { Resume::Continue };
+            }
+            b'D' => {
+                self.remove_all_breakpoints();
+                self.send_packet(b"OK");
+                return Resume::Continue;
+            }
+            b'k' => {
+                self.remove_all_breakpoints();
+                return Resume::Continue;
+            }
+            b'H' => reply.push_str("OK"),
+            b'q' if args.starts_with(b"Supported") => reply.push_str("PacketSize=400"),
+            b'q' if args == b"Attached" => reply.push(b'1'),
+            b'q' if args == b"C" => reply.push_str("QC1"),
+            // anything else is answered with an empty packet, meaning "unsupported"
+            _ => {}
+        }
+        Resume::Stay
+    }
+
+    // Talks to GDB until it resumes execution.
+    fn session(&mut self, frame: &mut TrapFrame) {
+        // a hit software breakpoint leaves rip after the int3, report its address instead
+        if frame.vector == 3 && self.is_breakpoint(frame.rip.wrapping_sub(1)) {
+            frame.rip -= 1;
+        }
+        // report the stop, then serve requests
+        let mut stop = Reply::new();
+        stop.push(b'S');
+        stop.push_hex_byte(SIGTRAP);
+        self.send_packet(stop.as_bytes());
+
+        let mut decoder = PacketDecoder::new();
+        loop {
+            let packet = self.receive_packet(&mut decoder);
+            let mut reply = Reply::new();
+            match self.handle_command(packet, frame, &mut reply) {
+                Resume::Stay => self.send_packet(reply.as_bytes()),
+                Resume::Continue => {
+                    frame.rflags &= !RFLAGS_TF;
+                    return;
+                }
+                Resume::Step => {
+                    frame.rflags |= RFLAGS_TF;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// Sets up COM2 for the stub. From now on #BP and #DB stop the kernel for GDB,
+// which can also interrupt the running kernel by sending ^C.
+pub fn init() {
+    let mut port = unsafe { SerialPort::new(COM2_BASE) };
+    port.init();
+    *STUB.lock() = Some(GdbStub {
+        port,
+        breakpoints: [const { None }; MAX_BREAKPOINTS],
+    });
+    irq::register(COM2_IRQ, "gdbstub", &com2_interrupt_handler).expect("COM2 IRQ registration failed");
+}
+
+pub fn is_attached() -> bool {
+    STUB.lock().is_some()
+}
+
+// Stops the kernel and waits for GDB; called on #BP and #DB while the stub is active.
+pub fn handle_trap(frame: &mut TrapFrame) {
+    if let Some(stub) = STUB.lock().as_mut() {
+        stub.session(frame);
+    }
+}
+
+// Hands control to GDB at the current position, e.g. to wait for it during boot.
+pub fn breakpoint() {
+    x86_64::instructions::interrupts::int3();
+}
+
+fn com2_interrupt_handler(_stack_frame: &InterruptStackFrame) -> IrqReturn {
+    let interrupted = match STUB.lock().as_mut() {
+        Some(stub) => stub.read_byte() == INTERRUPT_REQUEST,
+        None => return IrqReturn::NotHandled,
+    };
+    if interrupted {
+        // stop inside the IRQ handler, GDB shows the interrupted code in the backtrace
+        breakpoint();
+    }
+    IrqReturn::Handled
+}
+
+#[cfg(test)]
+fn test_stub() -> GdbStub {
+    // the port is never touched by the commands under test
+    GdbStub { port: unsafe { SerialPort::new(COM2_BASE) }, breakpoints: [const { None }; MAX_BREAKPOINTS] }
+}
+
+// Feeds all of `bytes` to `decoder`, only the last one may complete a packet.
+#[cfg(test)]
+fn decode(decoder: &mut PacketDecoder, bytes: &[u8]) -> Decoded {
+    let (last, rest) = bytes.split_last().unwrap();
+    for byte in rest {
+        assert_eq!(decoder.feed(*byte), Decoded::Incomplete);
+    }
+    decoder.feed(*last)
+}
+
+// Builds e.g. "m<addr>,<len>" for `addr`, which is sent most significant digit first.
+#[cfg(test)]
+fn memory_command(command: u8, addr: u64, len: usize, data: &str) -> Reply {
+    let mut packet = Reply::new();
+    packet.push(command);
+    (0..16).rev().for_each(|digit| packet.push(HEX[(addr >> (digit * 4)) as usize & 0xf]));
+    packet.push(b',');
+    packet.push(HEX[len]);
+    if !data.is_empty() {
+        packet.push(b':');
+        packet.push_str(data);
+    }
+    packet
+}
+
+#[test_case]
+fn test_packets_are_framed_with_their_checksum() {
+    assert_eq!(checksum(b""), 0);
+    assert_eq!(checksum(b"OK"), 0x9a);
+    assert_eq!(checksum(&[0xff, 0x02]), 0x01);
+    assert!(framed(b"OK").eq(b"$OK#9a".iter().copied()));
+    assert!(framed(b"").eq(b"$#00".iter().copied()));
+}
+
+#[test_case]
+fn test_decoder_checks_the_checksum() {
+    let mut decoder = PacketDecoder::new();
+    // acks and ^C between packets are skipped
+    assert_eq!(decode(&mut decoder, b"+\x03-$OK#9a"), Decoded::Packet);
+    assert_eq!(decoder.packet(), b"OK");
+    assert_eq!(decode(&mut decoder, b"$OK#9A"), Decoded::Packet);
+    assert_eq!(decode(&mut decoder, b"$OK#9b"), Decoded::Corrupt);
+    assert_eq!(decode(&mut decoder, b"$OK#zz"), Decoded::Corrupt);
+    // and recovers with the retransmission
+    assert_eq!(decode(&mut decoder, b"$#00"), Decoded::Packet);
+    assert_eq!(decoder.packet(), b"");
+
+    let mut packet = [0u8; 11];
+    packet.iter_mut().zip(framed(b"m1000,4")).for_each(|(slot, byte)| *slot = byte);
+    assert_eq!(decode(&mut decoder, &packet), Decoded::Packet);
+    assert_eq!(decoder.packet(), b"m1000,4");
+}
+
+#[test_case]
+fn test_hex_encoding_and_decoding() {
+    let mut reply = Reply::new();
+    reply.push_hex_byte(0xa5);
+    reply.push_le(0x1234, 2);
+    reply.push_le(0x0102_0304_0506_0708, 8);
+    assert_eq!(reply.as_bytes(), b"a534120807060504030201");
+
+    assert_eq!(parse_hex(b"dEaD"), Some(0xdead));
+    assert_eq!(parse_hex(b""), None);
+    assert_eq!(parse_hex(b"12g"), None);
+    assert_eq!(parse_le(b"3412", 2), Some(0x1234));
+    assert_eq!(parse_le(b"0807060504030201", 8), Some(0x0102_0304_0506_0708));
+    assert_eq!(parse_le(b"34", 2), None);
+    assert_eq!(parse_addr_len(b"ffff8000,10"), Some((0xffff_8000, 0x10, b"".as_ref())));
+    assert_eq!(parse_addr_len(b"1000,2:cafe"), Some((0x1000, 2, b"cafe".as_ref())));
+    assert_eq!(parse_addr_len(b"1000"), None);
+}
+
+#[test_case]
+fn test_register_replies_are_little_endian() {
+    let mut stub = test_stub();
+    let mut frame = TrapFrame { rax: 0x1122_3344_5566_7788, rip: 0xffff_8000_0000_1234, rflags: 0x202, ..TrapFrame::default() };
+    let mut reply = Reply::new();
+    assert!(matches!(stub.handle_command(b"g", &mut frame, &mut reply), Resume::Stay));
+    // 17 registers of 8 bytes, then 7 of 4, two hex digits per byte
+    assert_eq!(reply.len, 17 * 16 + 7 * 8);
+    assert_eq!(&reply.as_bytes()[..16], b"8877665544332211");
+    assert_eq!(&reply.as_bytes()[16 * 16..17 * 16], b"341200000080ffff");
+    assert_eq!(&reply.as_bytes()[17 * 16..17 * 16 + 8], b"02020000");
+
+    // writing the same registers back restores them
+    let mut written = TrapFrame::default();
+    let mut packet = Reply::new();
+    packet.push(b'G');
+    reply.as_bytes().iter().for_each(|byte| packet.push(*byte));
+    let mut ok = Reply::new();
+    stub.handle_command(packet.as_bytes(), &mut written, &mut ok);
+    assert_eq!(ok.as_bytes(), b"OK");
+    assert_eq!((written.rax, written.rip, written.rflags), (frame.rax, frame.rip, frame.rflags));
+
+    let mut reply = Reply::new();
+    stub.handle_command(b"p10", &mut frame, &mut reply);
+    assert_eq!(reply.as_bytes(), b"341200000080ffff");
+}
+
+#[test_case]
+fn test_memory_is_read_and_written_in_hex() {
+    let mut stub = test_stub();
+    let mut frame = TrapFrame::default();
+    let mut memory = [0x12u8, 0x34, 0x56, 0x78];
+    let addr = memory.as_mut_ptr() as u64;
+
+    let mut reply = Reply::new();
+    stub.handle_command(memory_command(b'm', addr, 4, "").as_bytes(), &mut frame, &mut reply);
+    assert_eq!(reply.as_bytes(), b"12345678");
+
+    let mut reply = Reply::new();
+    stub.handle_command(memory_command(b'M', addr + 1, 2, "cafe").as_bytes(), &mut frame, &mut reply);
+    assert_eq!(reply.as_bytes(), b"OK");
+    assert_eq!(unsafe { core::ptr::read_volatile(&memory) }, [0x12, 0xca, 0xfe, 0x78]);
+
+    // too little data for the length, or no length at all
+    let mut reply = Reply::new();
+    stub.handle_command(memory_command(b'M', addr, 2, "ca").as_bytes(), &mut frame, &mut reply);
+    assert_eq!(reply.as_bytes(), b"E14");
+    let mut reply = Reply::new();
+    stub.handle_command(b"m1000", &mut frame, &mut reply);
+    assert_eq!(reply.as_bytes(), b"E14");
+}
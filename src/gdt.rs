@@ -3,45 +3,96 @@ use x86_64::structures::tss::TaskStateSegment;
 use x86_64::structures::gdt::{GlobalDescriptorTable, Descriptor};
 use x86_64::structures::gdt::SegmentSelector;
 use lazy_static::lazy_static;
+use alloc::boxed::Box;
+use crate::{memory, percpu};
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(&raw const STACK);
-            let stack_end = stack_start + STACK_SIZE;
-            stack_end
-        };
-        tss
-    };
+const STACK_SIZE: usize = 4096 * 5;
+
+// The BSP's TSS. The CPU reads it on every privilege level change, so it's
+// changed through a raw pointer (see `set_kernel_stack`) and never borrowed mutably.
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+fn stack_end(stack: *const [u8; STACK_SIZE]) -> VirtAddr {
+    VirtAddr::from_ptr(stack) + STACK_SIZE
+}
+
+// The segment order is fixed by `sysret`, which expects user data right below
+// user code, and is the same in every CPU's GDT.
+pub struct Selectors {
+    pub kernel_code: SegmentSelector,
+    pub kernel_data: SegmentSelector,
+    pub user_data: SegmentSelector,
+    pub user_code: SegmentSelector,
+    pub tss: SegmentSelector,
 }
 
-struct Selectors {
-    code_selector: SegmentSelector,
-    tss_selector: SegmentSelector,
+fn build(tss: &'static TaskStateSegment) -> (GlobalDescriptorTable, Selectors) {
+    let mut gdt = GlobalDescriptorTable::new();
+    let kernel_code = gdt.add_entry(Descriptor::kernel_code_segment());
+    let kernel_data = gdt.add_entry(Descriptor::kernel_data_segment());
+    let user_data = gdt.add_entry(Descriptor::user_data_segment());
+    let user_code = gdt.add_entry(Descriptor::user_code_segment());
+    let tss = gdt.add_entry(Descriptor::tss_segment(tss));
+    (gdt, Selectors {kernel_code, kernel_data, user_data, user_code, tss})
 }
 
 lazy_static! {
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
-        let mut gdt = GlobalDescriptorTable::new();
-        let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
-        (gdt, Selectors {code_selector, tss_selector})
+        static mut DOUBLE_FAULT_STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+        // the stack interrupts from user mode arrive on, until a task sets its own
+        static mut KERNEL_STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+        let tss = &raw mut TSS;
+        unsafe {
+            (*tss).interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = stack_end(&raw const DOUBLE_FAULT_STACK);
+            (*tss).privilege_stack_table[0] = stack_end(&raw const KERNEL_STACK);
+            build(&*tss)
+        }
     };
 }
 
-pub fn init() {
+// Segment selectors, valid for the GDT of every CPU.
+pub fn selectors() -> &'static Selectors {
+    &GDT.1
+}
+
+fn load(gdt: &'static GlobalDescriptorTable, selectors: &Selectors) {
     use x86_64::instructions::tables::load_tss;
-    use x86_64::instructions::segmentation::{CS, Segment};
-    
-    GDT.0.load();
+    use x86_64::instructions::segmentation::{CS, SS, Segment};
+
+    gdt.load();
     unsafe {
-        CS::set_reg(GDT.1.code_selector);
-        load_tss(GDT.1.tss_selector);
+        CS::set_reg(selectors.kernel_code);
+        SS::set_reg(selectors.kernel_data);
+        load_tss(selectors.tss);
     }
-}
\ No newline at end of file
+}
+
+pub fn init() {
+    load(&GDT.0, &GDT.1);
+    percpu::get(0).set_tss(&raw mut TSS);
+}
+
+// Loads a fresh GDT and TSS on an application processor. Every CPU needs its own
+// TSS (it is marked busy once loaded) and its own double fault and kernel stacks.
+pub fn init_ap(cpu_index: usize) {
+    let mut tss = TaskStateSegment::new();
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+        memory::alloc_stack(5).expect("allocating the double fault stack failed");
+    tss.privilege_stack_table[0] = memory::alloc_stack(5).expect("allocating the kernel stack failed");
+    let tss = Box::into_raw(Box::new(tss));
+
+    let (gdt, selectors) = build(unsafe { &*tss });
+    let gdt: &'static GlobalDescriptorTable = Box::leak(Box::new(gdt));
+    load(gdt, &selectors);
+    percpu::get(cpu_index).set_tss(tss);
+}
+
+// Sets the stack the calling CPU switches to when an interrupt or exception
+// arrives while it runs in user mode.
+pub fn set_kernel_stack(stack_top: VirtAddr) {
+    let tss = percpu::current().tss();
+    unsafe { (*tss).privilege_stack_table[0] = stack_top };
+}
@@ -0,0 +1,180 @@
+use core::ptr;
+use spin::Mutex;
+use x86_64::{
+    structures::paging::{
+        mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+use crate::acpi;
+
+// virtual address the HPET register block gets mapped at (arbitrary unused address, like the heap)
+pub const HPET_VIRT_BASE: u64 = 0x_5555_5555_0000;
+
+// register offsets inside the HPET MMIO block
+const GENERAL_CAPABILITIES: u64 = 0x000;
+const GENERAL_CONFIG: u64 = 0x010;
+const GENERAL_INTERRUPT_STATUS: u64 = 0x020;
+const MAIN_COUNTER: u64 = 0x0f0;
+
+const fn timer_config(n: u64) -> u64 {
+    0x100 + 0x20 * n
+}
+
+const fn timer_comparator(n: u64) -> u64 {
+    0x108 + 0x20 * n
+}
+
+// general configuration bits
+const ENABLE_CNF: u64 = 1 << 0;
+const LEG_RT_CNF: u64 = 1 << 1;
+
+// general capability bits
+const LEG_RT_CAP: u64 = 1 << 15;
+
+// per timer configuration bits
+const TN_INT_ENB_CNF: u64 = 1 << 2;
+const TN_TYPE_CNF: u64 = 1 << 3;
+const TN_PER_INT_CAP: u64 = 1 << 4;
+const TN_VAL_SET_CNF: u64 = 1 << 6;
+
+const FEMTOSECONDS_PER_SECOND: u64 = 1_000_000_000_000_000;
+const FEMTOSECONDS_PER_NANOSECOND: u64 = 1_000_000;
+
+#[derive(Debug)]
+pub enum HpetError {
+    // no HPET table in ACPI
+    NotFound,
+    // the register block is not memory mapped
+    NotMemoryMapped,
+    // comparator 0 can't be routed to IRQ0 (legacy replacement unsupported)
+    NoLegacyReplacement,
+    // comparator 0 has no periodic mode
+    NoPeriodicMode,
+    MapFailed(MapToError<Size4KiB>),
+}
+
+impl From<MapToError<Size4KiB>> for HpetError {
+    fn from(err: MapToError<Size4KiB>) -> Self {
+        HpetError::MapFailed(err)
+    }
+}
+
+pub struct Hpet {
+    base: VirtAddr,
+    // main counter tick period in femtoseconds
+    period_fs: u64,
+    num_timers: u8,
+}
+
+impl Hpet {
+    fn read(&self, offset: u64) -> u64 {
+        unsafe { ptr::read_volatile((self.base + offset).as_ptr::<u64>()) }
+    }
+
+    fn write(&mut self, offset: u64, value: u64) {
+        unsafe { ptr::write_volatile((self.base + offset).as_mut_ptr::<u64>(), value) }
+    }
+
+    // Current value of the free running main counter.
+    pub fn counter(&self) -> u64 {
+        self.read(MAIN_COUNTER)
+    }
+
+    // Frequency of the main counter in Hz.
+    pub fn frequency(&self) -> u64 {
+        FEMTOSECONDS_PER_SECOND / self.period_fs
+    }
+
+    // Nanoseconds elapsed since the main counter was last reset.
+    pub fn nanos(&self) -> u64 {
+        (self.counter() as u128 * self.period_fs as u128 / FEMTOSECONDS_PER_NANOSECOND as u128) as u64
+    }
+
+    pub fn num_timers(&self) -> u8 {
+        self.num_timers
+    }
+
+    fn halt(&mut self) {
+        let config = self.read(GENERAL_CONFIG);
+        self.write(GENERAL_CONFIG, config & !ENABLE_CNF);
+    }
+
+    // Starts the main counter with comparator 0 routed to IRQ0 in place of the PIT.
+    fn enable_legacy_routing(&mut self) {
+        let config = self.read(GENERAL_CONFIG);
+        self.write(GENERAL_CONFIG, config | LEG_RT_CNF | ENABLE_CNF);
+    }
+
+    // Fires IRQ0 `hz` times per second using comparator 0.
+    pub fn start_periodic(&mut self, hz: u64) -> Result<(), HpetError> {
+        let timer_cfg = self.read(timer_config(0));
+        if timer_cfg & TN_PER_INT_CAP == 0 {
+            return Err(HpetError::NoPeriodicMode);
+        }
+        let period = self.frequency() / hz;
+
+        self.halt();
+        self.write(MAIN_COUNTER, 0);
+        // in periodic mode the first comparator write sets the first deadline, and with
+        // VAL_SET the second write sets the accumulator that gets added after each interrupt
+        self.write(timer_config(0), timer_cfg | TN_INT_ENB_CNF | TN_TYPE_CNF | TN_VAL_SET_CNF);
+        self.write(timer_comparator(0), period);
+        self.write(timer_comparator(0), period);
+        self.enable_legacy_routing();
+        Ok(())
+    }
+
+    // Fires IRQ0 once, `delay_ns` nanoseconds from now, using comparator 0.
+    pub fn start_oneshot(&mut self, delay_ns: u64) {
+        let delta = (delay_ns as u128 * FEMTOSECONDS_PER_NANOSECOND as u128 / self.period_fs as u128) as u64;
+        let timer_cfg = self.read(timer_config(0)) & !TN_TYPE_CNF;
+
+        self.write(timer_config(0), timer_cfg | TN_INT_ENB_CNF);
+        let deadline = self.counter().wrapping_add(delta.max(1));
+        self.write(timer_comparator(0), deadline);
+        self.enable_legacy_routing();
+    }
+
+    // Masks comparator 0 without stopping the main counter.
+    pub fn stop_timer(&mut self) {
+        let timer_cfg = self.read(timer_config(0));
+        self.write(timer_config(0), timer_cfg & !TN_INT_ENB_CNF);
+        // clear a possibly pending level triggered status bit
+        self.write(GENERAL_INTERRUPT_STATUS, 1);
+    }
+}
+
+pub static HPET: Mutex<Option<Hpet>> = Mutex::new(None);
+
+// Locates the HPET through ACPI and maps its register block at HPET_VIRT_BASE.
+// The counter is left halted until one of the comparator modes is started.
+pub fn init(mapper: &mut impl Mapper<Size4KiB>, frame_allocator: &mut impl FrameAllocator<Size4KiB>) -> Result<(), HpetError> {
+    let table = acpi::hpet().ok_or(HpetError::NotFound)?;
+    let base_address = table.base_address;
+    // address space 0 is system memory
+    if base_address.address_space_id != 0 {
+        return Err(HpetError::NotMemoryMapped);
+    }
+
+    let phys = PhysAddr::new(base_address.address);
+    let page = Page::<Size4KiB>::containing_address(VirtAddr::new(HPET_VIRT_BASE));
+    let frame = PhysFrame::containing_address(phys);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+    unsafe {
+        mapper.map_to(page, frame, flags, frame_allocator)?.flush();
+    }
+
+    let base = page.start_address() + (phys.as_u64() - frame.start_address().as_u64());
+    let mut hpet = Hpet { base, period_fs: 0, num_timers: 0 };
+    let capabilities = hpet.read(GENERAL_CAPABILITIES);
+    if capabilities & LEG_RT_CAP == 0 {
+        return Err(HpetError::NoLegacyReplacement);
+    }
+    hpet.period_fs = capabilities >> 32;
+    hpet.num_timers = ((capabilities >> 8) & 0x1f) as u8 + 1;
+    hpet.halt();
+
+    *HPET.lock() = Some(hpet);
+    Ok(())
+}
@@ -1,41 +1,108 @@
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
-use crate::{hlt_loop, print, println, gdt, keyboard};
+use crate::{hlt_loop, print, println, gdt, irq, backtrace, gdbstub, apic, percpu, usermode, thread, watchdog};
+use crate::trap::{self, TrapFrame};
+use alloc::string::String;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU64, Ordering};
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
-use spin;
+use crate::sync::SpinLock;
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
-pub static PICS: spin::Mutex<ChainedPics> = spin::Mutex::new(unsafe{ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET)});
+pub static PICS: SpinLock<ChainedPics> = SpinLock::new(unsafe{ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET)});
 
-#[derive(Debug, Clone, Copy)]
-#[repr(u8)]
-pub enum InterruptIndex {
-    Timer = PIC_1_OFFSET,
-    Keyboard
+// how often each IDT vector was taken since boot
+static VECTOR_COUNTS: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+
+// Counts one occurrence of the given vector.
+pub fn record_vector(vector: u8) {
+    VECTOR_COUNTS[vector as usize].fetch_add(1, Ordering::Relaxed);
 }
 
-impl InterruptIndex {
-    fn as_u8(self) -> u8 {
-        self as u8
-    }
+// Number of times the given vector was taken since boot.
+pub fn vector_count(vector: u8) -> u64 {
+    VECTOR_COUNTS[vector as usize].load(Ordering::Relaxed)
+}
 
-    fn as_usize(self) -> usize {
-        usize::from(self.as_u8())
+// Mnemonic of the CPU exception with the given vector.
+pub fn exception_name(vector: u8) -> &'static str {
+    match vector {
+        0 => "#DE divide error",
+        1 => "#DB debug",
+        2 => "NMI",
+        3 => "#BP breakpoint",
+        4 => "#OF overflow",
+        5 => "#BR bound range",
+        6 => "#UD invalid opcode",
+        7 => "#NM device not available",
+        8 => "#DF double fault",
+        10 => "#TS invalid TSS",
+        11 => "#NP segment not present",
+        12 => "#SS stack segment fault",
+        13 => "#GP general protection",
+        14 => "#PF page fault",
+        16 => "#MF x87 floating point",
+        17 => "#AC alignment check",
+        18 => "#MC machine check",
+        19 => "#XM SIMD floating point",
+        20 => "#VE virtualization",
+        _ => "reserved",
     }
 }
 
+// Renders the interrupt counters, one vector per line: exceptions that occurred,
+// every IRQ line with its handlers and the spurious PIC interrupts.
+pub fn stats_report() -> String {
+    let mut report = String::new();
+    let _ = writeln!(report, " VEC        COUNT  SOURCE");
+    for vector in 0..PIC_1_OFFSET {
+        let count = vector_count(vector);
+        if count > 0 {
+            let _ = writeln!(report, "{:>4} {:>12}  {}", vector, count, exception_name(vector));
+        }
+    }
+    for line in 0..irq::IRQ_COUNT as u8 {
+        let _ = write!(report, "{:>4} {:>12}  IRQ{}", PIC_1_OFFSET + line, irq::count(line), line);
+        irq::for_each_handler(line, |name| {
+            let _ = write!(report, " {}", name);
+        });
+        let _ = writeln!(report);
+    }
+    for line in irq::SPURIOUS_LINES {
+        let _ = writeln!(report, "{:>4} {:>12}  IRQ{} spurious", "-", irq::spurious_count(line), line);
+    }
+    report
+}
 
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
+        idt.divide_error.set_handler_fn(divide_error_handler);
+        // #DB and #BP go through the register saving trap stubs, so the GDB stub can
+        // inspect and modify the complete register state
+        unsafe {
+            idt.debug.set_handler_addr(trap::debug_entry());
+            idt.breakpoint.set_handler_addr(trap::breakpoint_entry());
+        }
+        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+        idt.device_not_available.set_handler_fn(device_not_available_handler);
+        idt.invalid_tss.set_handler_fn(invalid_tss_handler);
+        idt.segment_not_present.set_handler_fn(segment_not_present_handler);
+        idt.stack_segment_fault.set_handler_fn(stack_segment_fault_handler);
+        idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+        idt.x87_floating_point.set_handler_fn(x87_floating_point_handler);
+        idt.alignment_check.set_handler_fn(alignment_check_handler);
+        idt.machine_check.set_handler_fn(machine_check_handler);
+        idt.simd_floating_point.set_handler_fn(simd_floating_point_handler);
         unsafe {
             idt.double_fault.set_handler_fn(double_fault_handler).set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
         }
-        idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
-        idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        // hardware IRQs all go through the irq module, drivers register their handlers there
+        irq::install_handlers(&mut idt);
+        idt[apic::SPURIOUS_VECTOR as usize].set_handler_fn(apic_spurious_handler);
+        idt[apic::TIMER_VECTOR as usize].set_handler_fn(apic_timer_handler);
         idt.page_fault.set_handler_fn(page_fault_handler);
         idt
     };
@@ -45,68 +112,126 @@ pub fn init_idt() {
     IDT.load();
 }
 
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    print!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+extern "x86-interrupt" fn divide_error_handler(stack_frame: InterruptStackFrame) {
+    record_vector(0);
+    usermode::handle_fault(0, &stack_frame);
+    // walked from here it starts at the faulting code, not inside the panic machinery
+    backtrace::print_backtrace();
+    panic!("EXCEPTION: DIVIDE ERROR\n{:#?}", stack_frame);
+}
+
+pub fn debug_handler(frame: &mut TrapFrame) {
+    record_vector(1);
+    if gdbstub::is_attached() {
+        gdbstub::handle_trap(frame);
+        return;
+    }
+    println!("EXCEPTION: DEBUG\n{:#x?}", frame);
+}
+
+pub fn breakpoint_handler(frame: &mut TrapFrame) {
+    record_vector(3);
+    if gdbstub::is_attached() {
+        gdbstub::handle_trap(frame);
+        return;
+    }
+    print!("EXCEPTION: BREAKPOINT\n{:#x?}", frame);
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+    record_vector(6);
+    usermode::handle_fault(6, &stack_frame);
+    backtrace::print_backtrace();
+    panic!("EXCEPTION: INVALID OPCODE\n{:#?}", stack_frame);
+}
+
+// The first FPU instruction after a thread switch, see `fpu`. User programs use SSE,
+// so this is taken from ring 3 as well.
+extern "x86-interrupt" fn device_not_available_handler(stack_frame: InterruptStackFrame) {
+    let _gs = percpu::SwapGsGuard::new(&stack_frame);
+    record_vector(7);
+    thread::fpu_trap();
 }
 
 extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame, _error_code: u64) -> ! {
+    record_vector(8);
+    backtrace::print_backtrace();
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    // print!(".");
-    unsafe {
-        PICS.lock().notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
-    }
+extern "x86-interrupt" fn invalid_tss_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    record_vector(10);
+    usermode::handle_fault(10, &stack_frame);
+    panic!("EXCEPTION: INVALID TSS ({:#x})\n{:#?}", error_code, stack_frame);
+}
+
+extern "x86-interrupt" fn segment_not_present_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    record_vector(11);
+    usermode::handle_fault(11, &stack_frame);
+    panic!("EXCEPTION: SEGMENT NOT PRESENT ({:#x})\n{:#?}", error_code, stack_frame);
+}
+
+extern "x86-interrupt" fn stack_segment_fault_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    record_vector(12);
+    usermode::handle_fault(12, &stack_frame);
+    panic!("EXCEPTION: STACK SEGMENT FAULT ({:#x})\n{:#?}", error_code, stack_frame);
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    record_vector(13);
+    usermode::handle_fault(13, &stack_frame);
+    backtrace::print_backtrace();
+    panic!("EXCEPTION: GENERAL PROTECTION FAULT ({:#x})\n{:#?}", error_code, stack_frame);
+}
+
+// Unmasked x87 exceptions, raised by the next FPU instruction after the faulting one.
+extern "x86-interrupt" fn x87_floating_point_handler(stack_frame: InterruptStackFrame) {
+    record_vector(16);
+    usermode::handle_fault(16, &stack_frame);
+    panic!("EXCEPTION: x87 FLOATING POINT\n{:#?}", stack_frame);
+}
+
+// Needs CR0.AM as well as RFLAGS.AC, and the kernel leaves CR0.AM clear.
+extern "x86-interrupt" fn alignment_check_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    record_vector(17);
+    usermode::handle_fault(17, &stack_frame);
+    panic!("EXCEPTION: ALIGNMENT CHECK ({:#x})\n{:#?}", error_code, stack_frame);
+}
+
+// A hardware error; not the running program's fault, even when it came from user mode.
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    record_vector(18);
+    panic!("EXCEPTION: MACHINE CHECK\n{:#?}", stack_frame);
+}
+
+// Unmasked SSE exceptions, e.g. a division by zero with MXCSR.ZM cleared.
+extern "x86-interrupt" fn simd_floating_point_handler(stack_frame: InterruptStackFrame) {
+    record_vector(19);
+    usermode::handle_fault(19, &stack_frame);
+    panic!("EXCEPTION: SIMD FLOATING POINT\n{:#?}", stack_frame);
+}
+
+// Spurious local APIC interrupts must not be acknowledged with an EOI.
+extern "x86-interrupt" fn apic_spurious_handler(_stack_frame: InterruptStackFrame) {
+    record_vector(apic::SPURIOUS_VECTOR);
+}
+
+// Only the application processor watching the system tick starts its timer.
+extern "x86-interrupt" fn apic_timer_handler(_stack_frame: InterruptStackFrame) {
+    record_vector(apic::TIMER_VECTOR);
+    watchdog::check_system_tick();
+    apic::end_of_interrupt();
 }
 
 extern "x86-interrupt" fn page_fault_handler(stack_frame: InterruptStackFrame, error_code: PageFaultErrorCode) {
     use x86_64::registers::control::Cr2;
 
+    record_vector(14);
+    usermode::handle_fault(14, &stack_frame);
     println!("EXCEPTION: PAGE FAULT");
     println!("Accessed Address: {:?}", Cr2::read());
     println!("Error Code: {:?}", error_code);
     println!("{:#?}", stack_frame);
+    backtrace::print_backtrace();
     hlt_loop();
 }
-
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    use pc_keyboard::{layouts, Keyboard, HandleControl, ScancodeSet1, DecodedKey};
-    use x86_64::instructions::port::Port;
-    use spin::Mutex;
-
-    // Process the scancode using the keyboard's state machine
-    lazy_static! {
-        pub static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> = Mutex::new(Keyboard::new(
-            ScancodeSet1::new(),
-            layouts::Us104Key,
-            HandleControl::Ignore,
-        ));
-    }
-
-    let mut keyboard = KEYBOARD.lock();
-    // Read from the keyboard I/O port
-    let mut port = Port::new(0x60);
-    let scancode: u8 = unsafe { port.read() };
-
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            match key {
-                DecodedKey::Unicode(character) => {
-                    // Handle printable characters, including space (' '), Enter ('\n'), and Backspace ('\x08')
-                    if character.is_ascii_graphic() || character.is_whitespace() || character == '\n' || character == '\x08' {
-                        keyboard::add_to_buffer(character as u8);
-                    }
-                }
-                DecodedKey::RawKey(_key) => {
-                    // Ignore non-printable keys
-                }
-            }
-        }
-    }
-
-    // Notify the PIC that the interrupt was handled
-    unsafe {
-        PICS.lock().notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
-    }
-}
@@ -0,0 +1,12 @@
+// Ways for threads and processes to talk to each other: byte stream pipes, typed
+// channels between kernel threads, and named message queues for user programs. All
+// are bounded and block the caller while there's nothing to read or no room to
+// write, so they can't be used from interrupt handlers.
+
+pub mod channel;
+pub mod message_queue;
+pub mod pipe;
+
+pub use channel::{channel, Receiver, RecvError, RecvTimeoutError, SendError, Sender, TryRecvError};
+pub use message_queue::{MessageQueue, MessageTooLong};
+pub use pipe::{pipe, BrokenPipe, PipeReader, PipeWriter};
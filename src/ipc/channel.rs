@@ -0,0 +1,180 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use crate::sync::{SpinLock, WaitQueue};
+
+// Sending on a channel without receivers; gives the message back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+// Receiving on an empty channel without senders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Disconnected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+    Timeout,
+    Disconnected,
+}
+
+struct State<T> {
+    messages: VecDeque<T>,
+    capacity: usize,
+    senders: usize,
+    receivers: usize,
+}
+
+struct Shared<T> {
+    state: SpinLock<State<T>>,
+    not_empty: WaitQueue,
+    not_full: WaitQueue,
+}
+
+// Sends messages into a channel, blocking while it's full. Can be cloned to have
+// several producers.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+// Receives a channel's messages in the order they were sent. Can be cloned, each
+// message goes to one of the receivers.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+// Creates a channel holding up to `capacity` messages (at least one).
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        state: SpinLock::new(State {
+            messages: VecDeque::new(),
+            capacity: capacity.max(1),
+            senders: 1,
+            receivers: 1,
+        }),
+        not_empty: WaitQueue::new(),
+        not_full: WaitQueue::new(),
+    });
+    (Sender { shared: shared.clone() }, Receiver { shared })
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, message: T) -> Result<(), SendError<T>> {
+        let mut message = Some(message);
+        self.shared.not_full.wait_until(|| {
+            let mut state = self.shared.state.lock();
+            if state.receivers == 0 {
+                return true;
+            }
+            if state.messages.len() == state.capacity {
+                return false;
+            }
+            state.messages.extend(message.take());
+            true
+        });
+        match message {
+            Some(message) => Err(SendError(message)),
+            None => {
+                self.shared.not_empty.wake_one();
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    // Blocks until a message arrives; fails once the channel is empty and all
+    // senders are gone.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.recv_unless(|| false).unwrap_or(Err(RecvError))
+    }
+
+    // Like `recv`, but gives up as soon as `interrupted` returns true, which is
+    // checked whenever the thread wakes up. None if it gave up.
+    pub fn recv_unless(&self, mut interrupted: impl FnMut() -> bool) -> Option<Result<T, RecvError>> {
+        let mut result = None;
+        self.shared.not_empty.wait_until(|| match self.try_recv() {
+            Ok(message) => {
+                result = Some(Ok(message));
+                true
+            }
+            Err(TryRecvError::Empty) => interrupted(),
+            Err(TryRecvError::Disconnected) => {
+                result = Some(Err(RecvError));
+                true
+            }
+        });
+        result
+    }
+
+    // Like `recv`, but gives up after `ms` milliseconds.
+    pub fn recv_timeout(&self, ms: u64) -> Result<T, RecvTimeoutError> {
+        let mut result = Err(RecvTimeoutError::Timeout);
+        self.shared.not_empty.wait_until_timeout(ms, || match self.try_recv() {
+            Ok(message) => {
+                result = Ok(message);
+                true
+            }
+            Err(TryRecvError::Empty) => false,
+            Err(TryRecvError::Disconnected) => {
+                result = Err(RecvTimeoutError::Disconnected);
+                true
+            }
+        });
+        result
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.shared.state.lock();
+        match state.messages.pop_front() {
+            Some(message) => {
+                drop(state);
+                self.shared.not_full.wake_one();
+                Ok(message)
+            }
+            None if state.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    // Number of messages waiting.
+    pub fn len(&self) -> usize {
+        self.shared.state.lock().messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().senders += 1;
+        Sender { shared: self.shared.clone() }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().receivers += 1;
+        Receiver { shared: self.shared.clone() }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().senders -= 1;
+        self.shared.not_empty.wake_all();
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().receivers -= 1;
+        self.shared.not_full.wake_all();
+    }
+}
@@ -0,0 +1,80 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use super::channel::{channel, Receiver, Sender, TryRecvError};
+use crate::sync::Mutex;
+
+// limits of every message queue
+pub const MAX_MESSAGES: usize = 16;
+pub const MAX_MESSAGE_SIZE: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageTooLong;
+
+// A queue of byte messages that processes (or kernel threads) find by name. It
+// holds both ends of a channel, so it never disconnects and lives on after the
+// last user closed it.
+pub struct MessageQueue {
+    sender: Sender<Vec<u8>>,
+    receiver: Receiver<Vec<u8>>,
+}
+
+static QUEUES: Mutex<BTreeMap<String, Arc<MessageQueue>>> = Mutex::new(BTreeMap::new());
+
+// Returns the queue called `name`, creating it on first use.
+pub fn open(name: &str) -> Arc<MessageQueue> {
+    QUEUES
+        .lock()
+        .entry(String::from(name))
+        .or_insert_with(|| {
+            let (sender, receiver) = channel(MAX_MESSAGES);
+            Arc::new(MessageQueue { sender, receiver })
+        })
+        .clone()
+}
+
+// Names of the existing queues.
+pub fn names() -> Vec<String> {
+    QUEUES.lock().keys().cloned().collect()
+}
+
+impl MessageQueue {
+    // Queues a copy of `message`, waiting while the queue is full.
+    pub fn send(&self, message: &[u8]) -> Result<(), MessageTooLong> {
+        if message.len() > MAX_MESSAGE_SIZE {
+            return Err(MessageTooLong);
+        }
+        // the queue holds its own receiver, so sending can't fail
+        let _ = self.sender.send(message.to_vec());
+        Ok(())
+    }
+
+    // Takes the oldest message, waiting for one if the queue is empty.
+    pub fn receive(&self) -> Vec<u8> {
+        self.receiver.recv().expect("message queue lost its sender")
+    }
+
+    // Like `receive`, but gives up as soon as `interrupted` returns true, which is
+    // checked whenever the thread wakes up. None if it gave up.
+    pub fn receive_unless(&self, interrupted: impl FnMut() -> bool) -> Option<Vec<u8>> {
+        self.receiver.recv_unless(interrupted).map(|message| message.expect("message queue lost its sender"))
+    }
+
+    pub fn try_receive(&self) -> Option<Vec<u8>> {
+        match self.receiver.try_recv() {
+            Ok(message) => Some(message),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => unreachable!("message queue lost its sender"),
+        }
+    }
+
+    // Number of messages waiting.
+    pub fn len(&self) -> usize {
+        self.receiver.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.receiver.is_empty()
+    }
+}
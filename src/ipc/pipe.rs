@@ -0,0 +1,137 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use crate::sync::{SpinLock, WaitQueue};
+
+// bytes a pipe holds before writers have to wait
+pub const PIPE_CAPACITY: usize = 4096;
+
+// Writing to a pipe whose read ends are all gone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BrokenPipe;
+
+struct State {
+    buffer: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+}
+
+struct Pipe {
+    state: SpinLock<State>,
+    readable: WaitQueue,
+    writable: WaitQueue,
+}
+
+// The read end of a pipe. Clones share it, reads see end of file once every write
+// end is dropped.
+pub struct PipeReader {
+    pipe: Arc<Pipe>,
+}
+
+// The write end of a pipe.
+pub struct PipeWriter {
+    pipe: Arc<Pipe>,
+}
+
+// Creates an anonymous pipe and returns both its ends.
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let pipe = Arc::new(Pipe {
+        state: SpinLock::new(State { buffer: VecDeque::new(), readers: 1, writers: 1 }),
+        readable: WaitQueue::new(),
+        writable: WaitQueue::new(),
+    });
+    (PipeReader { pipe: pipe.clone() }, PipeWriter { pipe })
+}
+
+impl PipeReader {
+    // Blocks until there's data, then reads as much as fits into `buffer`. Returns 0
+    // at end of file.
+    pub fn read(&self, buffer: &mut [u8]) -> usize {
+        self.read_unless(buffer, || false).unwrap_or(0)
+    }
+
+    // Like `read`, but gives up as soon as `interrupted` returns true, which is
+    // checked whenever the thread wakes up. None if it gave up before reading.
+    pub fn read_unless(&self, buffer: &mut [u8], mut interrupted: impl FnMut() -> bool) -> Option<usize> {
+        if buffer.is_empty() {
+            return Some(0);
+        }
+        let mut count = 0;
+        let mut stop = false;
+        self.pipe.readable.wait_until(|| {
+            let mut state = self.pipe.state.lock();
+            if state.buffer.is_empty() && state.writers > 0 {
+                drop(state);
+                stop = interrupted();
+                return stop;
+            }
+            count = buffer.len().min(state.buffer.len());
+            for (byte, data) in buffer.iter_mut().zip(state.buffer.drain(..count)) {
+                *byte = data;
+            }
+            true
+        });
+        if stop {
+            return None;
+        }
+        if count > 0 {
+            self.pipe.writable.wake_all();
+        }
+        Some(count)
+    }
+}
+
+impl PipeWriter {
+    // Writes all of `data`, waiting for room whenever the pipe is full.
+    pub fn write(&self, data: &[u8]) -> Result<usize, BrokenPipe> {
+        let mut written = 0;
+        while written < data.len() {
+            let mut broken = false;
+            self.pipe.writable.wait_until(|| {
+                let mut state = self.pipe.state.lock();
+                if state.readers == 0 {
+                    broken = true;
+                    return true;
+                }
+                let count = (PIPE_CAPACITY - state.buffer.len()).min(data.len() - written);
+                state.buffer.extend(&data[written..written + count]);
+                written += count;
+                count > 0
+            });
+            if broken {
+                return Err(BrokenPipe);
+            }
+            self.pipe.readable.wake_all();
+        }
+        Ok(written)
+    }
+}
+
+impl Clone for PipeReader {
+    fn clone(&self) -> Self {
+        self.pipe.state.lock().readers += 1;
+        PipeReader { pipe: self.pipe.clone() }
+    }
+}
+
+impl Clone for PipeWriter {
+    fn clone(&self) -> Self {
+        self.pipe.state.lock().writers += 1;
+        PipeWriter { pipe: self.pipe.clone() }
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.pipe.state.lock().readers -= 1;
+        // blocked writers find the pipe broken
+        self.pipe.writable.wake_all();
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.pipe.state.lock().writers -= 1;
+        // blocked readers may be at the end of file now
+        self.pipe.readable.wake_all();
+    }
+}
@@ -0,0 +1,230 @@
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::RwLock;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use crate::interrupts::{self as idt, PICS, PIC_1_OFFSET};
+use x86_64::VirtAddr;
+use crate::signal::UserContext;
+use crate::{percpu, process, thread, usermode, workqueue};
+
+// number of IRQ lines provided by the two chained PICs
+pub const IRQ_COUNT: usize = 16;
+// how many handlers can share a single IRQ line
+pub const MAX_HANDLERS_PER_IRQ: usize = 4;
+
+// What a handler reports back, so shared lines can tell which device raised the IRQ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqReturn {
+    Handled,
+    NotHandled,
+}
+
+// A handler is either a plain function or a closure living for the rest of the kernel's life.
+pub type IrqHandler = &'static (dyn Fn(&InterruptStackFrame) -> IrqReturn + Send + Sync);
+
+#[derive(Clone, Copy)]
+struct IrqAction {
+    name: &'static str,
+    handler: IrqHandler,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    InvalidLine,
+    // all MAX_HANDLERS_PER_IRQ slots of the line are taken
+    LineFull,
+}
+
+// Identifies a registered handler so it can be removed again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandlerId {
+    line: u8,
+    slot: usize,
+}
+
+// the lowest priority line of each PIC, which it reports when an IRQ vanished
+// before it could be acknowledged
+pub const SPURIOUS_LINES: [u8; 2] = [7, 15];
+
+const PIC_1_COMMAND: u16 = 0x20;
+const PIC_2_COMMAND: u16 = 0xa0;
+// OCW3 command selecting the In-Service Register for the next command port read
+const PIC_READ_ISR: u8 = 0x0b;
+const PIC_EOI: u8 = 0x20;
+
+const NO_ACTIONS: [Option<IrqAction>; MAX_HANDLERS_PER_IRQ] = [None; MAX_HANDLERS_PER_IRQ];
+
+static ACTIONS: RwLock<[[Option<IrqAction>; MAX_HANDLERS_PER_IRQ]; IRQ_COUNT]> = RwLock::new([NO_ACTIONS; IRQ_COUNT]);
+static SPURIOUS_COUNTS: [AtomicU64; 2] = [const { AtomicU64::new(0) }; 2];
+
+// Attaches `handler` to the IRQ line. Handlers on a shared line are called in
+// registration order; the end of interrupt is sent after all of them ran.
+pub fn register(line: u8, name: &'static str, handler: IrqHandler) -> Result<HandlerId, IrqError> {
+    if line as usize >= IRQ_COUNT {
+        return Err(IrqError::InvalidLine);
+    }
+    // the write lock must not be held while an interrupt on this CPU tries to read it
+    interrupts::without_interrupts(|| {
+        let mut actions = ACTIONS.write();
+        let slots = &mut actions[line as usize];
+        let slot = slots.iter().position(|action| action.is_none()).ok_or(IrqError::LineFull)?;
+        slots[slot] = Some(IrqAction { name, handler });
+        Ok(HandlerId { line, slot })
+    })
+}
+
+// Like `register`, for closures that capture state. The closure is leaked, so
+// it stays allocated even after being unregistered.
+pub fn register_boxed(
+    line: u8,
+    name: &'static str,
+    handler: Box<dyn Fn(&InterruptStackFrame) -> IrqReturn + Send + Sync>,
+) -> Result<HandlerId, IrqError> {
+    register(line, name, Box::leak(handler))
+}
+
+// Detaches a previously registered handler.
+pub fn unregister(id: HandlerId) {
+    interrupts::without_interrupts(|| {
+        ACTIONS.write()[id.line as usize][id.slot] = None;
+    });
+}
+
+// Number of times the IRQ line has fired since boot, spurious interrupts excluded.
+pub fn count(line: u8) -> u64 {
+    idt::vector_count(PIC_1_OFFSET + line)
+}
+
+// Number of spurious interrupts reported on IRQ7 or IRQ15.
+pub fn spurious_count(line: u8) -> u64 {
+    match line {
+        7 => SPURIOUS_COUNTS[0].load(Ordering::Relaxed),
+        15 => SPURIOUS_COUNTS[1].load(Ordering::Relaxed),
+        _ => 0,
+    }
+}
+
+// A spurious IRQ7/IRQ15 is delivered without the matching bit set in the PIC's
+// In-Service Register. Returns true (after acknowledging it correctly) in that case.
+fn handle_spurious(line: u8) -> bool {
+    if !SPURIOUS_LINES.contains(&line) {
+        return false;
+    }
+    // hold the PIC lock so nobody else talks to the PICs in between
+    let _pics = PICS.lock();
+    let (command, index) = if line == 7 { (PIC_1_COMMAND, 0) } else { (PIC_2_COMMAND, 1) };
+    let mut command_port: Port<u8> = Port::new(command);
+    let isr = unsafe {
+        command_port.write(PIC_READ_ISR);
+        command_port.read()
+    };
+    if isr & (1 << 7) != 0 {
+        return false;
+    }
+
+    SPURIOUS_COUNTS[index].fetch_add(1, Ordering::Relaxed);
+    // a spurious IRQ15 was still forwarded by the master through its cascade line,
+    // so only the master gets an EOI; a spurious IRQ7 must not be acknowledged at all
+    if line == 15 {
+        unsafe { Port::<u8>::new(PIC_1_COMMAND).write(PIC_EOI) };
+    }
+    true
+}
+
+// Calls `f` with the name of every handler attached to the IRQ line.
+pub fn for_each_handler(line: u8, mut f: impl FnMut(&'static str)) {
+    let actions = interrupts::without_interrupts(|| ACTIONS.read()[line as usize]);
+    for action in actions.iter().flatten() {
+        f(action.name);
+    }
+}
+
+// Common path of all IRQ lines: filter spurious IRQs, count, run every attached
+// handler and acknowledge the PIC.
+fn dispatch(line: u8, stack_frame: &mut InterruptStackFrame) {
+    let _gs = percpu::SwapGsGuard::new(stack_frame);
+    if handle_spurious(line) {
+        return;
+    }
+    idt::record_vector(PIC_1_OFFSET + line);
+    let cpu = percpu::current();
+    cpu.enter_interrupt();
+
+    // copy the handlers out so they may (un)register handlers themselves
+    let actions = ACTIONS.read()[line as usize];
+    for action in actions.iter().flatten() {
+        (action.handler)(stack_frame);
+    }
+
+    unsafe {
+        PICS.lock().notify_end_of_interrupt(PIC_1_OFFSET + line);
+    }
+    cpu.leave_interrupt();
+    workqueue::irq_exit();
+    // may switch to another thread, which is fine once the PIC got its EOI
+    thread::irq_exit();
+    if usermode::is_from_user(stack_frame) {
+        let mut context = UserContext {
+            rip: stack_frame.instruction_pointer.as_u64(),
+            rsp: stack_frame.stack_pointer.as_u64(),
+            rflags: stack_frame.cpu_flags,
+        };
+        process::deliver_signals(&mut context);
+        if context.rip != stack_frame.instruction_pointer.as_u64() {
+            // iretq enters the signal handler
+            unsafe {
+                stack_frame.as_mut().update(|frame| {
+                    frame.instruction_pointer = VirtAddr::new(context.rip);
+                    frame.stack_pointer = VirtAddr::new(context.rsp);
+                })
+            };
+        }
+    }
+}
+
+// generates one interrupt entry point per IRQ line, all forwarding to `dispatch`
+macro_rules! irq_entries {
+    ($($line:literal => $name:ident),* $(,)?) => {
+        $(
+            extern "x86-interrupt" fn $name(mut stack_frame: InterruptStackFrame) {
+                dispatch($line, &mut stack_frame);
+            }
+        )*
+
+        // Points the IDT vectors of all IRQ lines at the dispatching entry points.
+        pub fn install_handlers(idt: &mut InterruptDescriptorTable) {
+            $(
+                idt[(PIC_1_OFFSET + $line) as usize].set_handler_fn($name);
+            )*
+        }
+    };
+}
+
+irq_entries! {
+    0 => irq0, 1 => irq1, 2 => irq2, 3 => irq3,
+    4 => irq4, 5 => irq5, 6 => irq6, 7 => irq7,
+    8 => irq8, 9 => irq9, 10 => irq10, 11 => irq11,
+    12 => irq12, 13 => irq13, 14 => irq14, 15 => irq15,
+}
+
+#[test_case]
+fn test_shared_timer_handler() {
+    use core::sync::atomic::AtomicBool;
+
+    static FIRED: AtomicBool = AtomicBool::new(false);
+
+    fn on_timer(_stack_frame: &InterruptStackFrame) -> IrqReturn {
+        FIRED.store(true, Ordering::Relaxed);
+        IrqReturn::NotHandled
+    }
+
+    let before = count(0);
+    let id = register(0, "test", &on_timer).expect("register failed");
+    while !FIRED.load(Ordering::Relaxed) {
+        x86_64::instructions::hlt();
+    }
+    unregister(id);
+    assert!(count(0) > before);
+}
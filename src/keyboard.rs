@@ -1,16 +1,95 @@
-use spin::Mutex;
 use lazy_static::lazy_static;
 use alloc::string::String;
 use alloc::collections::VecDeque;
 use crate::print; // Ensure this is imported
 use crate::vga_buffer::WRITER;
+use crate::irq::{self, IrqReturn};
+use crate::sync::{SpinLock, WaitQueue};
+use crate::workqueue::Work;
+use crate::{process, task};
+use pc_keyboard::{layouts, Keyboard, HandleControl, ScancodeSet1, DecodedKey};
+use x86_64::structures::idt::InterruptStackFrame;
 
-lazy_static! {pub static ref INPUT_BUFFER: Mutex<VecDeque<u8>> = Mutex::new(VecDeque::new());}
+// the PS/2 keyboard is wired to IRQ1
+pub const KEYBOARD_IRQ: u8 = 1;
+
+// raw scancodes from the interrupt handler, decoded later by `DECODE_WORK`
+static SCANCODES: SpinLock<VecDeque<u8>> = SpinLock::new(VecDeque::new());
+static DECODE_WORK: Work = Work::new("keyboard", decode_scancodes);
+
+// filled by the interrupt handler's deferred work, and locked with interrupts
+// disabled as that may run in interrupt context
+pub static INPUT_BUFFER: SpinLock<VecDeque<u8>> = SpinLock::new(VecDeque::new());
+// threads waiting for input
+static INPUT_WAITERS: WaitQueue = WaitQueue::new();
+
+lazy_static! {
+    // Process the scancode using the keyboard's state machine
+    static ref KEYBOARD: SpinLock<Keyboard<layouts::Us104Key, ScancodeSet1>> = SpinLock::new(Keyboard::new(
+        ScancodeSet1::new(),
+        layouts::Us104Key,
+        // Ctrl-C comes through as '\u{3}'
+        HandleControl::MapLettersToUnicode,
+    ));
+}
+
+// Attaches the keyboard handler to its IRQ line.
+pub fn init() {
+    irq::register(KEYBOARD_IRQ, "keyboard", &keyboard_interrupt_handler).expect("keyboard IRQ registration failed");
+}
+
+// Only reads the scancode, the key is decoded outside of the interrupt handler.
+fn keyboard_interrupt_handler(_stack_frame: &InterruptStackFrame) -> IrqReturn {
+    use x86_64::instructions::port::Port;
+
+    // Read from the keyboard I/O port
+    let mut port = Port::new(0x60);
+    let scancode: u8 = unsafe { port.read() };
+    SCANCODES.lock().push_back(scancode);
+    DECODE_WORK.schedule();
+    IrqReturn::Handled
+}
+
+// Runs the scancodes that came in through the keyboard's state machine.
+fn decode_scancodes() {
+    let mut keyboard = KEYBOARD.lock();
+    loop {
+        // not locked while decoding, more scancodes may come in meanwhile
+        let Some(scancode) = SCANCODES.lock().pop_front() else { return };
+        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
+            if let Some(key) = keyboard.process_keyevent(key_event) {
+                handle_key(key);
+            }
+        }
+    }
+}
+
+fn handle_key(key: DecodedKey) {
+    match key {
+        DecodedKey::Unicode('\u{3}') => {
+            // Ctrl-C, which the shell itself ignores
+            if process::interrupt_foreground() {
+                print!("^C\n");
+            }
+        }
+        DecodedKey::Unicode(character) => {
+            // Handle printable characters, including space (' '), Enter ('\n'), and Backspace ('\x08')
+            if character.is_ascii_graphic() || character.is_whitespace() || character == '\n' || character == '\x08' {
+                add_to_buffer(character as u8);
+            }
+        }
+        DecodedKey::RawKey(_key) => {
+            // Ignore non-printable keys
+        }
+    }
+}
 
 // Adds a character to the buffer
 pub fn add_to_buffer(character: u8) {
-    let mut buffer = INPUT_BUFFER.lock();
-    buffer.push_back(character);
+    INPUT_BUFFER.lock().push_back(character);
+    // a thread or task waiting for input can go on
+    INPUT_WAITERS.wake_all();
+    task::keyboard::wake();
 }
 
 // Fetches a character from the buffer
@@ -21,31 +100,51 @@ pub fn fetch_from_buffer() -> Option<u8> {
 }
 
 pub fn read_keyboard(buffer: &mut String) {
+    read_keyboard_unless(buffer, || false);
+}
+
+// Like `read_keyboard`, but gives up as soon as `interrupted` returns true, which is
+// checked whenever the thread wakes up. Returns whether a line was read.
+pub fn read_keyboard_unless(buffer: &mut String, mut interrupted: impl FnMut() -> bool) -> bool {
     loop {
+        // sleep until a key comes in, other threads keep running meanwhile
+        let mut stop = false;
+        INPUT_WAITERS.wait_until(|| {
+            stop = interrupted();
+            stop || !INPUT_BUFFER.lock().is_empty()
+        });
+        if stop {
+            return false;
+        }
         // Fetch the next character from the buffer
         if let Some(character) = fetch_from_buffer() {
-            if character!= b'\x08'{
-                print!("{}", character as char); // Print the character
+            if edit_line(buffer, character) {
+                return true;
             }
-            
-            if character == b'\n' {
-                // Enter pressed, input completed
-                if !buffer.is_empty() {
-                    buffer.push('\n');
-                    return; // Exit the loop
-                }
-            } else if character == b'\x08' {
-                // Backspace pressed, remove last character
-                    buffer.pop(); // Remove the last character from the buffer
-                    WRITER.lock().handle_backspace(); // Move cursor back and clear the character on screen
-            } else {
-                // Append character to the buffer
-                buffer.push(character as char);
-            }
-        } else {
-            // If the buffer is empty, let the CPU wait briefly (e.g., avoid busy-waiting)
-            x86_64::instructions::hlt(); // Halts the CPU until the next interrupt
         }
     }
 }
 
+// Echoes a typed character and applies it to the line being edited. Returns true
+// once Enter completes a non-empty line, which then ends with '\n'.
+pub fn edit_line(buffer: &mut String, character: u8) -> bool {
+    if character != b'\x08' {
+        print!("{}", character as char); // Print the character
+    }
+
+    if character == b'\n' {
+        // Enter pressed, input completed
+        if !buffer.is_empty() {
+            buffer.push('\n');
+            return true;
+        }
+    } else if character == b'\x08' {
+        // Backspace pressed, remove last character
+        buffer.pop(); // Remove the last character from the buffer
+        WRITER.lock().handle_backspace(); // Move cursor back and clear the character on screen
+    } else {
+        // Append character to the buffer
+        buffer.push(character as char);
+    }
+    false
+}
@@ -13,15 +13,52 @@ pub mod serial;
 pub mod vga_buffer;
 pub mod interrupts;
 pub mod gdt;
+pub mod fpu;
 pub mod memory;
 pub mod keyboard;
 pub mod shell;
 pub mod fs;
+pub mod acpi;
+pub mod cmdline;
+pub mod hpet;
+pub mod time;
+pub mod timer;
+pub mod irq;
+pub mod procfs;
+pub mod backtrace;
+pub mod trap;
+pub mod gdbstub;
+pub mod power;
+pub mod apic;
+pub mod smp;
+pub mod percpu;
+pub mod usermode;
+pub mod syscall;
+pub mod elf;
+pub mod exec;
+pub mod programs;
+pub mod thread;
+pub mod task;
+pub mod sync;
+pub mod process;
+pub mod ipc;
+pub mod signal;
+pub mod workqueue;
+pub mod watchdog;
+pub mod profiler;
 
 pub fn init() {
+    // the boot processor's per-CPU area, needed before the first interrupt
+    percpu::init(0);
+    percpu::current().set_online();
     // new gdt with our custom tss in it loaded
     gdt::init();
+    fpu::init();
+    syscall::init();
     interrupts::init_idt();
+    // drivers attach to their IRQ lines at runtime
+    time::init_irq();
+    keyboard::init();
     // init PIC (Programmable Interrupt Controller)
     unsafe {interrupts::PICS.lock().initialize()};
     // change CPU config for CPU to listen to PIC
@@ -105,4 +142,11 @@ fn panic(info: &PanicInfo) -> ! {
 #[test_case]
 fn test_breakpoint_exception() {
     x86_64::instructions::interrupts::int3(); // invoke a breakpoint exception
-}
\ No newline at end of file
+}
+// the breakpoint exception shows up in the per vector statistics
+#[test_case]
+fn test_exception_is_counted() {
+    let before = interrupts::vector_count(3);
+    x86_64::instructions::interrupts::int3();
+    assert_eq!(interrupts::vector_count(3), before + 1);
+}
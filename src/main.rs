@@ -6,7 +6,7 @@
 
 extern crate alloc;
 
-use rustos::{fs::FileSystem, shell::start_shell, println};
+use rustos::{shell::start_shell, println};
 use core::panic::PanicInfo;
 use bootloader::{BootInfo, entry_point};
 
@@ -16,7 +16,7 @@ entry_point!(kernel_main);
 // this function is the entry point, since the linker looks for a function
 fn kernel_main(boot_info: &'static BootInfo) -> ! {
     use x86_64::{VirtAddr};
-    use rustos::{allocator, memory::{self, BootInfoFrameAllocator}};
+    use rustos::{allocator, memory::{self, BootInfoFrameAllocator}, time::{self, TickSource}};
 
     println!("Welcome to RustOS, {}!!", "from The Rusty Crew");
 
@@ -29,11 +29,39 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     let mut frame_allocator = unsafe {BootInfoFrameAllocator::init(&boot_info.memory_map)};
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
 
-    // Init FileSystem
-    let mut file_system = FileSystem::new();
+    // System tick: the HPET unless the command line says `tick=pit`; without a usable
+    // HPET `time::init` falls back to the PIT
+    rustos::cmdline::init();
+    let tick_source = time::init(TickSource::from_cmdline(), &mut mapper, &mut frame_allocator);
+    println!("System tick source: {:?}", tick_source);
+
+    // From here on the page table and frame allocator are shared kernel wide
+    memory::install(mapper, frame_allocator);
+
+    // Ship the user programs in /bin
+    rustos::programs::install();
+
+    // Bring up the application processors
+    rustos::smp::init();
+    println!("CPUs online: {}", rustos::smp::online_count());
+
+    // Kernel threads, preempted by the timer; the shell keeps running as "main"
+    rustos::thread::init();
+    // Interrupt handlers defer the rest of their work to the "kworker" thread
+    rustos::workqueue::init();
+    // Report threads that don't get the CPU to serial
+    rustos::watchdog::init();
+
+    // Wait for GDB on COM2 before going any further
+    #[cfg(feature = "gdbstub")]
+    {
+        rustos::gdbstub::init();
+        println!("Waiting for GDB on COM2...");
+        rustos::gdbstub::breakpoint();
+    }
 
     // Start the shell for user input
-    start_shell(&mut file_system);
+    start_shell();
 
     // below line defines a conditional compile command
     // whenever test is true the below lines will be compiled and executed
@@ -50,6 +78,8 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     println!("{}", info);
+    rustos::serial_println!("{}", info);
+    rustos::backtrace::print_backtrace();
     rustos::hlt_loop();
 }
 
@@ -1,18 +1,49 @@
 use x86_64::{
     structures::paging::{
         OffsetPageTable,PageTable,
-        PhysFrame,
-        Size4KiB, FrameAllocator
+        PhysFrame, Page, PageTableFlags, Mapper,
+        Size4KiB, FrameAllocator, FrameDeallocator,
+        mapper::{MapToError, TranslateResult}, Translate,
     },
     VirtAddr,
     PhysAddr
 };
 use bootloader::bootinfo::{MemoryRegionType, MemoryMap};
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use alloc::vec::Vec;
+
+pub mod address_space;
+pub use address_space::AddressSpace;
+
+// virtual address at which the bootloader mapped the complete physical memory
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+// frames below 1 MiB are never handed out, they are left for real mode code like the AP trampoline
+const LOW_MEMORY_END: u64 = 0x10_0000;
+
+// kernel stacks get carved out of this region, each one below an unmapped guard page
+pub const STACKS_START: u64 = 0x_6666_0000_0000;
+static NEXT_STACK: AtomicU64 = AtomicU64::new(STACKS_START);
+
+// user programs live in the level 4 entries 1 to 127, clear of everything the kernel maps
+pub const USER_START: u64 = 0x_0000_0080_0000_0000;
+pub const USER_END: u64 = 0x_0000_4000_0000_0000;
+
+// Page table and frame allocator, available to the whole kernel once `install` was called.
+pub struct MemoryManager {
+    pub mapper: OffsetPageTable<'static>,
+    pub frame_allocator: BootInfoFrameAllocator,
+}
+
+static MEMORY: Mutex<Option<MemoryManager>> = Mutex::new(None);
 
 // A FrameAllocator that returns usable frames from the bootloader's memory map.
 pub struct BootInfoFrameAllocator {
     memory_map: &'static MemoryMap,
     next: usize,
+    // frames given back through `deallocate_frame`, handed out first
+    free_frames: Vec<PhysFrame>,
 }
 
 impl BootInfoFrameAllocator {
@@ -21,6 +52,7 @@ impl BootInfoFrameAllocator {
         BootInfoFrameAllocator {
             memory_map,
             next: 0,
+            free_frames: Vec::new(),
         }
     }
     // Returns an iterator over the usable frames specified in the memory map.
@@ -31,27 +63,183 @@ impl BootInfoFrameAllocator {
         // map each region to its address range
         let addr_ranges = usable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
         // transform to an iterator of frame start addresses
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
+        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096)).filter(|addr| *addr >= LOW_MEMORY_END);
         // create `PhysFrame` types from the start addresses
         frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
     }
+
+    // First usable frame below 1 MiB, reachable from real mode. Never handed out by
+    // `allocate_frame`, so the caller owns it.
+    pub fn low_memory_frame(&self) -> Option<PhysFrame> {
+        self.memory_map
+            .iter()
+            .filter(|r| r.region_type == MemoryRegionType::Usable)
+            .find_map(|r| {
+                // frame 0 holds the real mode IVT
+                let start = r.range.start_addr().max(4096);
+                (start + 4096 <= r.range.end_addr().min(LOW_MEMORY_END)).then_some(start)
+            })
+            .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+    }
 }
 
 // allocate an empty frame from the usable_frames map
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if let Some(frame) = self.free_frames.pop() {
+            return Some(frame);
+        }
         let frame = self.usable_frames().nth(self.next);
         self.next += 1;
         frame
     }
 }
 
+// only used once the heap is up, so the free list can grow
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.free_frames.push(frame);
+    }
+}
+
 // Initialize a new OffsetPageTable.
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
     let level_4_table = active_level_4_table(physical_memory_offset);
     OffsetPageTable::new(level_4_table, physical_memory_offset)
 }
 
+// Hands the page table and frame allocator over for use after boot, e.g. by
+// `alloc_stack` and `map_mmio`.
+pub fn install(mapper: OffsetPageTable<'static>, frame_allocator: BootInfoFrameAllocator) {
+    *MEMORY.lock() = Some(MemoryManager { mapper, frame_allocator });
+}
+
+// Runs `f` with the installed memory manager. Panics when called before `install`.
+pub fn with_memory<R>(f: impl FnOnce(&mut MemoryManager) -> R) -> R {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        f(MEMORY.lock().as_mut().expect("memory manager not installed"))
+    })
+}
+
+// Maps `pages` fresh frames as a kernel stack, leaving an unmapped guard page below
+// it so an overflow faults instead of corrupting memory. Returns the stack's top.
+pub fn alloc_stack(pages: u64) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let guard = NEXT_STACK.fetch_add((pages + 1) * 4096, Ordering::Relaxed);
+    let bottom = Page::<Size4KiB>::containing_address(VirtAddr::new(guard + 4096));
+    with_memory(|memory| {
+        for page in Page::range(bottom, bottom + pages) {
+            let frame = memory.frame_allocator.allocate_frame().ok_or(MapToError::FrameAllocationFailed)?;
+            let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+            unsafe { memory.mapper.map_to(page, frame, flags, &mut memory.frame_allocator)?.flush() };
+        }
+        Ok((bottom + pages).start_address())
+    })
+}
+
+// Page table of the running program: user mappings go into the active address space,
+// while kernel mappings always go into the boot page table, whose kernel entries every
+// address space shares.
+fn user_mapper() -> OffsetPageTable<'static> {
+    let physical_memory_offset = VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed));
+    unsafe { OffsetPageTable::new(active_level_4_table(physical_memory_offset), physical_memory_offset) }
+}
+
+// Maps `pages` zeroed frames at `start` (in the user range) into the active address
+// space, accessible from user mode with the given additional flags.
+pub fn map_user_pages(start: VirtAddr, pages: u64, flags: PageTableFlags) -> Result<(), MapToError<Size4KiB>> {
+    assert!(start.as_u64() >= USER_START && start.as_u64() + pages * 4096 <= USER_END, "not a user address");
+    let first_page = Page::<Size4KiB>::containing_address(start);
+    with_memory(|memory| {
+        let mut mapper = user_mapper();
+        for page in Page::range(first_page, first_page + pages) {
+            let frame = memory.frame_allocator.allocate_frame().ok_or(MapToError::FrameAllocationFailed)?;
+            unsafe { core::ptr::write_bytes(phys_to_virt(frame.start_address()).as_mut_ptr::<u8>(), 0, 4096) };
+            let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+            unsafe { mapper.map_to(page, frame, flags, &mut memory.frame_allocator)?.flush() };
+        }
+        Ok(())
+    })
+}
+
+// Unmaps user pages of the active address space and frees their frames. Pages that
+// aren't mapped are skipped.
+pub fn unmap_user_pages(start: VirtAddr, pages: u64) {
+    let first_page = Page::<Size4KiB>::containing_address(start);
+    with_memory(|memory| {
+        let mut mapper = user_mapper();
+        for page in Page::range(first_page, first_page + pages) {
+            if let Ok((frame, flush)) = mapper.unmap(page) {
+                flush.flush();
+                unsafe { memory.frame_allocator.deallocate_frame(frame) };
+            }
+        }
+    })
+}
+
+// Checks that `len` bytes at `start` lie in the user range and are mapped user
+// accessible (and writable, if requested), before the kernel touches them on behalf
+// of a user program.
+pub fn check_user_range(start: u64, len: u64, writable: bool) -> bool {
+    let end = match start.checked_add(len) {
+        Some(end) => end,
+        None => return false,
+    };
+    if start < USER_START || end > USER_END {
+        return false;
+    }
+    if len == 0 {
+        return true;
+    }
+    let required = if writable {
+        PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE
+    } else {
+        PageTableFlags::USER_ACCESSIBLE
+    };
+    let first_page = Page::<Size4KiB>::containing_address(VirtAddr::new(start));
+    let last_page = Page::<Size4KiB>::containing_address(VirtAddr::new(end - 1));
+    // under the lock, so the page tables don't change while they're walked
+    with_memory(|_| {
+        let mapper = user_mapper();
+        Page::range_inclusive(first_page, last_page).all(|page| match mapper.translate(page.start_address()) {
+            TranslateResult::Mapped { flags, .. } => flags.contains(required),
+            _ => false,
+        })
+    })
+}
+
+// Maps the device registers at `phys` uncached at the (page aligned) virtual address `virt`.
+pub fn map_mmio(phys: PhysAddr, virt: VirtAddr, size: u64) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let first_frame = PhysFrame::<Size4KiB>::containing_address(phys);
+    let first_page = Page::<Size4KiB>::containing_address(virt);
+    let pages = (phys.as_u64() - first_frame.start_address().as_u64() + size).div_ceil(4096);
+    with_memory(|memory| {
+        for i in 0..pages {
+            let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+            unsafe { memory.mapper.map_to(first_page + i, first_frame + i, flags, &mut memory.frame_allocator)?.flush() };
+        }
+        Ok(first_page.start_address() + (phys.as_u64() - first_frame.start_address().as_u64()))
+    })
+}
+
+// Translates a physical address into the virtual address it is mapped at through the
+// bootloader's physical memory mapping. Only valid after `init` has been called.
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed) + addr.as_u64())
+}
+
+// Checks whether `addr` is mapped in the active page table, e.g. before following
+// a possibly corrupted pointer. Reports everything as mapped before `init`.
+pub fn is_mapped(addr: VirtAddr) -> bool {
+    let offset = PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed);
+    if offset == 0 {
+        return true;
+    }
+    let physical_memory_offset = VirtAddr::new(offset);
+    let mapper = unsafe { OffsetPageTable::new(active_level_4_table(physical_memory_offset), physical_memory_offset) };
+    mapper.translate_addr(addr).is_some()
+}
+
 // Returns a mutable reference to the active level 4 table.
 unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
     use x86_64::registers::control::Cr3;
@@ -0,0 +1,137 @@
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{
+    mapper::{MapToError, TranslateResult},
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame,
+    Size4KiB, Translate,
+};
+use x86_64::VirtAddr;
+use super::{phys_to_virt, with_memory, BootInfoFrameAllocator, PHYSICAL_MEMORY_OFFSET, USER_END, USER_START};
+use core::sync::atomic::Ordering;
+
+// level 4 entries that belong to user programs; all others are the kernel's
+const USER_ENTRIES: core::ops::Range<usize> = (USER_START >> 39) as usize..(USER_END >> 39) as usize;
+
+// A level 4 page table of its own for a user program. The kernel half is shared
+// with the boot page table, the user entries start out empty and everything mapped
+// there is freed together with the address space.
+pub struct AddressSpace {
+    level_4_frame: PhysFrame,
+}
+
+fn table(frame: PhysFrame) -> &'static mut PageTable {
+    unsafe { &mut *phys_to_virt(frame.start_address()).as_mut_ptr::<PageTable>() }
+}
+
+impl AddressSpace {
+    pub fn new() -> Result<Self, MapToError<Size4KiB>> {
+        with_memory(|memory| {
+            let frame = memory.frame_allocator.allocate_frame().ok_or(MapToError::FrameAllocationFailed)?;
+            let level_4_table = table(frame);
+            level_4_table.zero();
+            for (index, entry) in memory.mapper.level_4_table().iter().enumerate() {
+                if !USER_ENTRIES.contains(&index) {
+                    level_4_table[index] = entry.clone();
+                }
+            }
+            Ok(AddressSpace { level_4_frame: frame })
+        })
+    }
+
+    fn mapper(&self) -> OffsetPageTable<'static> {
+        let physical_memory_offset = VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed));
+        unsafe { OffsetPageTable::new(table(self.level_4_frame), physical_memory_offset) }
+    }
+
+    // Maps `pages` zeroed, user accessible pages at `start`. Pages that are mapped
+    // already keep their contents and get the union of both flag sets.
+    pub fn map_user_pages(&mut self, start: VirtAddr, pages: u64, flags: PageTableFlags) -> Result<(), MapToError<Size4KiB>> {
+        assert!(start.as_u64() >= USER_START && start.as_u64() + pages * 4096 <= USER_END, "not a user address");
+        let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        let first_page = Page::<Size4KiB>::containing_address(start);
+        let mut mapper = self.mapper();
+        with_memory(|memory| {
+            for page in Page::range(first_page, first_page + pages) {
+                if let TranslateResult::Mapped { flags: old_flags, .. } = mapper.translate(page.start_address()) {
+                    // `NO_EXECUTE` only stays when neither mapping needs to execute
+                    let merged = ((old_flags | flags) - PageTableFlags::NO_EXECUTE)
+                        | (old_flags & flags & PageTableFlags::NO_EXECUTE);
+                    // not active yet, no TLB entries to flush
+                    let _ = unsafe { mapper.update_flags(page, merged) }.map(|flush| flush.ignore());
+                    continue;
+                }
+                let frame = memory.frame_allocator.allocate_frame().ok_or(MapToError::FrameAllocationFailed)?;
+                unsafe { core::ptr::write_bytes(phys_to_virt(frame.start_address()).as_mut_ptr::<u8>(), 0, 4096) };
+                unsafe { mapper.map_to(page, frame, flags, &mut memory.frame_allocator)?.ignore() };
+            }
+            Ok(())
+        })
+    }
+
+    // Copies `data` to `addr`, which has to be mapped. Works without activating the
+    // address space, through the physical memory mapping.
+    pub fn write(&self, addr: VirtAddr, data: &[u8]) {
+        let mapper = self.mapper();
+        let mut written = 0;
+        while written < data.len() {
+            let target = addr + written as u64;
+            let phys = mapper.translate_addr(target).expect("writing to unmapped user memory");
+            let chunk = (4096 - (target.as_u64() % 4096) as usize).min(data.len() - written);
+            unsafe {
+                core::ptr::copy_nonoverlapping(data[written..].as_ptr(), phys_to_virt(phys).as_mut_ptr::<u8>(), chunk);
+            }
+            written += chunk;
+        }
+    }
+
+    /// Switches the calling CPU to this address space and returns the previous level 4
+    /// table, to be restored with `restore`.
+    ///
+    /// # Safety
+    ///
+    /// The address space has to outlive its use on this CPU, i.e. `restore` must run
+    /// before it is dropped.
+    pub unsafe fn activate(&self) -> PhysFrame {
+        let (previous, flags) = Cr3::read();
+        Cr3::write(self.level_4_frame, flags);
+        previous
+    }
+
+    /// Switches back to the level 4 table `activate` returned.
+    ///
+    /// # Safety
+    ///
+    /// `previous` has to come from `activate` on this CPU and still be a valid level 4 table.
+    pub unsafe fn restore(previous: PhysFrame) {
+        let (_, flags) = Cr3::read();
+        Cr3::write(previous, flags);
+    }
+}
+
+// Frees a page table and everything mapped through it; `level` 1 tables map frames.
+fn free_table(frame: PhysFrame, level: u8, frame_allocator: &mut BootInfoFrameAllocator) {
+    for entry in table(frame).iter().filter(|entry| !entry.is_unused()) {
+        if let Ok(child) = entry.frame() {
+            if level > 1 {
+                free_table(child, level - 1, frame_allocator);
+            } else {
+                unsafe { frame_allocator.deallocate_frame(child) };
+            }
+        }
+    }
+    unsafe { frame_allocator.deallocate_frame(frame) };
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        assert_ne!(Cr3::read().0, self.level_4_frame, "dropping the active address space");
+        with_memory(|memory| {
+            let level_4_table = table(self.level_4_frame);
+            for index in USER_ENTRIES {
+                if let Ok(frame) = level_4_table[index].frame() {
+                    free_table(frame, 3, &mut memory.frame_allocator);
+                }
+            }
+            unsafe { memory.frame_allocator.deallocate_frame(self.level_4_frame) };
+        })
+    }
+}
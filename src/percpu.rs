@@ -0,0 +1,199 @@
+use core::arch::asm;
+use core::mem::offset_of;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use x86_64::registers::model_specific::{GsBase, KernelGsBase};
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::structures::tss::TaskStateSegment;
+use x86_64::VirtAddr;
+
+pub const MAX_CPUS: usize = 16;
+
+// Data private to one CPU, found through the GS base of that CPU. The owning CPU
+// is the only writer of the counters, so they're updated without locked
+// instructions; other CPUs may still read them (e.g. for statistics).
+#[repr(C)]
+pub struct PerCpu {
+    // must stay the first field: `current` loads it from gs:0
+    self_ptr: AtomicPtr<PerCpu>,
+    index: AtomicUsize,
+    apic_id: AtomicU8,
+    online: AtomicBool,
+    // how many interrupt handlers are active on this CPU
+    interrupt_depth: AtomicU32,
+    // hardware interrupts handled by this CPU
+    interrupts: AtomicU64,
+    // id of the task running on this CPU, 0 while there is none
+    current_task: AtomicU64,
+    // this CPU's TSS, whose kernel stack changes with the running task
+    tss: AtomicPtr<TaskStateSegment>,
+    // kernel stack pointer to resume at when the running user program exits
+    user_return_rsp: AtomicU64,
+    // user stack pointer, stashed by the syscall entry while it switches stacks
+    syscall_user_rsp: AtomicU64,
+}
+
+// field offsets for assembly code addressing the per-CPU area through gs
+pub const TSS_OFFSET: usize = offset_of!(PerCpu, tss);
+pub const SYSCALL_USER_RSP_OFFSET: usize = offset_of!(PerCpu, syscall_user_rsp);
+
+impl PerCpu {
+    const fn new() -> Self {
+        PerCpu {
+            self_ptr: AtomicPtr::new(core::ptr::null_mut()),
+            index: AtomicUsize::new(0),
+            apic_id: AtomicU8::new(0),
+            online: AtomicBool::new(false),
+            interrupt_depth: AtomicU32::new(0),
+            interrupts: AtomicU64::new(0),
+            current_task: AtomicU64::new(0),
+            tss: AtomicPtr::new(core::ptr::null_mut()),
+            user_return_rsp: AtomicU64::new(0),
+            syscall_user_rsp: AtomicU64::new(0),
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        self.index.load(Ordering::Relaxed)
+    }
+
+    pub fn apic_id(&self) -> u8 {
+        self.apic_id.load(Ordering::Relaxed)
+    }
+
+    pub fn set_apic_id(&self, apic_id: u8) {
+        self.apic_id.store(apic_id, Ordering::Relaxed);
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::Acquire)
+    }
+
+    pub fn set_online(&self) {
+        self.online.store(true, Ordering::Release);
+    }
+
+    pub fn interrupt_depth(&self) -> u32 {
+        self.interrupt_depth.load(Ordering::Relaxed)
+    }
+
+    pub fn interrupts(&self) -> u64 {
+        self.interrupts.load(Ordering::Relaxed)
+    }
+
+    pub fn current_task(&self) -> u64 {
+        self.current_task.load(Ordering::Relaxed)
+    }
+
+    pub fn set_current_task(&self, id: u64) {
+        self.current_task.store(id, Ordering::Relaxed);
+    }
+
+    pub fn tss(&self) -> *mut TaskStateSegment {
+        self.tss.load(Ordering::Relaxed)
+    }
+
+    pub fn set_tss(&self, tss: *mut TaskStateSegment) {
+        self.tss.store(tss, Ordering::Relaxed);
+    }
+
+    // Where the user mode entry code saves the kernel stack pointer to return to.
+    pub fn user_return_rsp(&self) -> *mut u64 {
+        self.user_return_rsp.as_ptr()
+    }
+
+    // Marks the start of an interrupt handler on this CPU.
+    pub fn enter_interrupt(&self) {
+        let depth = self.interrupt_depth.load(Ordering::Relaxed);
+        self.interrupt_depth.store(depth + 1, Ordering::Relaxed);
+        let count = self.interrupts.load(Ordering::Relaxed);
+        self.interrupts.store(count + 1, Ordering::Relaxed);
+    }
+
+    pub fn leave_interrupt(&self) {
+        let depth = self.interrupt_depth.load(Ordering::Relaxed);
+        self.interrupt_depth.store(depth - 1, Ordering::Relaxed);
+    }
+}
+
+static CPUS: [PerCpu; MAX_CPUS] = [const { PerCpu::new() }; MAX_CPUS];
+// set once the bootstrap processor's GS base points at its area
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+// Points the GS base of the calling CPU at the per-CPU area with the given index.
+// Has to run on every CPU before it takes any interrupt.
+pub fn init(index: usize) {
+    let cpu = &CPUS[index];
+    cpu.self_ptr.store(cpu as *const PerCpu as *mut PerCpu, Ordering::Relaxed);
+    cpu.index.store(index, Ordering::Relaxed);
+    GsBase::write(VirtAddr::from_ptr(cpu));
+    // user mode GS base, swapped in by `swapgs` on the way out of the kernel
+    KernelGsBase::write(VirtAddr::new(0));
+    INITIALIZED.store(true, Ordering::Release);
+}
+
+// Per-CPU area of the calling CPU. The caller has to make sure it isn't migrated to
+// another CPU while using it (e.g. by disabling interrupts) when that matters.
+pub fn current() -> &'static PerCpu {
+    let ptr: *const PerCpu;
+    unsafe { asm!("mov {}, gs:[0]", out(reg) ptr, options(nostack, preserves_flags, readonly)) };
+    unsafe { &*ptr }
+}
+
+// Like `current`, for code that may also run before `init`, e.g. printing early
+// during boot.
+pub fn try_current() -> Option<&'static PerCpu> {
+    INITIALIZED.load(Ordering::Acquire).then(current)
+}
+
+// Per-CPU area of any CPU, by index.
+pub fn get(index: usize) -> &'static PerCpu {
+    &CPUS[index]
+}
+
+// Whether the calling CPU is currently handling an interrupt.
+pub fn in_interrupt() -> bool {
+    current().interrupt_depth() > 0
+}
+
+// Executes `swapgs` when an interrupt came from user mode, and again when dropped, so
+// the kernel's GS base is active in between. Has to be the first thing an interrupt
+// handler that can be entered from ring 3 creates.
+pub struct SwapGsGuard {
+    from_user: bool,
+}
+
+impl SwapGsGuard {
+    pub fn new(stack_frame: &InterruptStackFrame) -> Self {
+        let from_user = stack_frame.code_segment & 3 == 3;
+        if from_user {
+            unsafe { asm!("swapgs", options(nostack, preserves_flags)) };
+        }
+        SwapGsGuard { from_user }
+    }
+}
+
+impl Drop for SwapGsGuard {
+    fn drop(&mut self) {
+        if self.from_user {
+            unsafe { asm!("swapgs", options(nostack, preserves_flags)) };
+        }
+    }
+}
+
+#[test_case]
+fn test_current_is_cpu_zero() {
+    assert_eq!(current().index(), 0);
+    assert!(core::ptr::eq(current(), get(0)));
+}
+
+#[test_case]
+fn test_interrupt_depth_is_tracked() {
+    let before = current().interrupts();
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        current().enter_interrupt();
+        assert!(in_interrupt());
+        current().leave_interrupt();
+    });
+    assert!(!in_interrupt());
+    assert!(current().interrupts() > before);
+}
@@ -0,0 +1,176 @@
+use core::slice;
+use x86_64::instructions::{interrupts, port::Port, tables};
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::acpi::{self, Fadt, GenericAddress, SdtHeader};
+use crate::{hlt_loop, memory};
+
+// PM1 control register bits
+const SCI_EN: u16 = 1 << 0;
+const SLP_EN: u16 = 1 << 13;
+const SLP_TYP_SHIFT: u16 = 10;
+
+// AML opcodes needed to find the \_S5 package
+const AML_NAME_OP: u8 = 0x08;
+const AML_PACKAGE_OP: u8 = 0x12;
+const AML_BYTE_PREFIX: u8 = 0x0a;
+
+// GAS address spaces
+const ADDRESS_SPACE_MEMORY: u8 = 0;
+const ADDRESS_SPACE_IO: u8 = 1;
+
+// Reads the SLP_TYPa and SLP_TYPb values of the S5 (soft off) sleep state from the
+// DSDT's `\_S5` package, without a full AML interpreter: the package is always
+// `NameOp "_S5_" PackageOp PkgLength NumElements [BytePrefix] a [BytePrefix] b ...`.
+// `aml` is the whole table, header included.
+fn s5_sleep_types(aml: &[u8]) -> Option<(u16, u16)> {
+    let start = aml.windows(4).position(|window| window == b"_S5_")?;
+    // it has to be a name definition (optionally in the root scope), followed by a package
+    let is_name = aml.get(start.wrapping_sub(1)) == Some(&AML_NAME_OP)
+        || (aml.get(start.wrapping_sub(1)) == Some(&b'\\') && aml.get(start.wrapping_sub(2)) == Some(&AML_NAME_OP));
+    if !is_name || aml.get(start + 4) != Some(&AML_PACKAGE_OP) {
+        return None;
+    }
+
+    let mut i = start + 5;
+    // the top two bits of the first PkgLength byte tell how many bytes follow it
+    i += ((*aml.get(i)? & 0xc0) >> 6) as usize + 1;
+    // NumElements
+    i += 1;
+
+    let mut next_value = || {
+        if *aml.get(i)? == AML_BYTE_PREFIX {
+            i += 1;
+        }
+        let value = *aml.get(i)? as u16;
+        i += 1;
+        Some(value)
+    };
+    let slp_typ_a = next_value()?;
+    let slp_typ_b = next_value()?;
+    Some((slp_typ_a, slp_typ_b))
+}
+
+// Switches from legacy to ACPI mode when the firmware didn't do so already.
+fn enable_acpi(fadt: &Fadt) {
+    let mut pm1a_cnt: Port<u16> = Port::new(fadt.pm1a_cnt_blk as u16);
+    if unsafe { pm1a_cnt.read() } & SCI_EN != 0 || fadt.smi_cmd == 0 || fadt.acpi_enable == 0 {
+        return;
+    }
+    unsafe { Port::<u8>::new(fadt.smi_cmd as u16).write(fadt.acpi_enable) };
+    // the switch isn't instant, give the firmware a bounded amount of time
+    for _ in 0..1_000_000 {
+        if unsafe { pm1a_cnt.read() } & SCI_EN != 0 {
+            break;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+// Enters the S5 sleep state through the FADT's PM1 control blocks.
+fn acpi_power_off() {
+    let (fadt, dsdt) = match (acpi::fadt(), acpi::dsdt()) {
+        (Some(fadt), Some(dsdt)) => (fadt, dsdt),
+        _ => return,
+    };
+    let aml = unsafe { slice::from_raw_parts(dsdt as *const SdtHeader as *const u8, dsdt.length as usize) };
+    let (slp_typ_a, slp_typ_b) = match s5_sleep_types(aml) {
+        Some(types) => types,
+        None => return,
+    };
+    if fadt.pm1a_cnt_blk == 0 {
+        return;
+    }
+    enable_acpi(fadt);
+
+    unsafe {
+        Port::<u16>::new(fadt.pm1a_cnt_blk as u16).write(slp_typ_a << SLP_TYP_SHIFT | SLP_EN);
+        if fadt.pm1b_cnt_blk != 0 {
+            Port::<u16>::new(fadt.pm1b_cnt_blk as u16).write(slp_typ_b << SLP_TYP_SHIFT | SLP_EN);
+        }
+    }
+}
+
+// Powers the machine off: ACPI S5 first, then the shutdown ports of QEMU, Bochs
+// and VirtualBox. Halts forever if none of them worked.
+pub fn shutdown() -> ! {
+    interrupts::disable();
+    acpi_power_off();
+
+    unsafe {
+        // QEMU (newer versions, piix4/ich9 PM base)
+        Port::<u16>::new(0x604).write(0x2000);
+        // Bochs and older QEMU
+        Port::<u16>::new(0xb004).write(0x2000);
+        // VirtualBox
+        Port::<u16>::new(0x4004).write(0x3400);
+    }
+    hlt_loop();
+}
+
+// Pulses the CPU reset line through the 8042 keyboard controller.
+fn keyboard_controller_reset() {
+    let mut status: Port<u8> = Port::new(0x64);
+    // wait (bounded) until the controller's input buffer is empty
+    for _ in 0..100_000 {
+        if unsafe { status.read() } & 0x02 == 0 {
+            break;
+        }
+        core::hint::spin_loop();
+    }
+    unsafe { status.write(0xfe) };
+}
+
+// Writes the reset value into the FADT's reset register (ACPI 2.0+).
+fn acpi_reset() {
+    let (register, value): (GenericAddress, u8) = match acpi::fadt().and_then(|fadt| fadt.reset_register()) {
+        Some(reset) => reset,
+        None => return,
+    };
+    match register.address_space_id {
+        ADDRESS_SPACE_IO => unsafe { Port::<u8>::new(register.address as u16).write(value) },
+        ADDRESS_SPACE_MEMORY => unsafe {
+            let virt = memory::phys_to_virt(PhysAddr::new(register.address));
+            core::ptr::write_volatile(virt.as_mut_ptr::<u8>(), value);
+        },
+        _ => {}
+    }
+}
+
+// Causes a triple fault: with an empty IDT every exception escalates until the CPU resets.
+fn triple_fault() {
+    let empty_idt = DescriptorTablePointer { limit: 0, base: VirtAddr::new(0) };
+    unsafe { tables::lidt(&empty_idt) };
+    x86_64::instructions::interrupts::int3();
+}
+
+// Resets the machine: 8042 reset line, then the ACPI reset register, then a triple fault.
+pub fn reboot() -> ! {
+    interrupts::disable();
+    keyboard_controller_reset();
+    acpi_reset();
+    triple_fault();
+    hlt_loop();
+}
+
+#[test_case]
+fn test_s5_values_with_byte_prefix() {
+    // Name (\_S5, Package (0x04) { 0x05, 0x07, Zero, Zero }) behind some other AML
+    let aml = [
+        0x10, 0x0c, b'_', b'S', b'B', b'_', AML_NAME_OP, b'\\', b'_', b'S', b'5', b'_', AML_PACKAGE_OP, 0x08, 0x04,
+        AML_BYTE_PREFIX, 0x05, AML_BYTE_PREFIX, 0x07, 0x00, 0x00,
+    ];
+    assert_eq!(s5_sleep_types(&aml), Some((5, 7)));
+    // cut off before the second value
+    assert_eq!(s5_sleep_types(&aml[..18]), None);
+}
+
+#[test_case]
+fn test_s5_values_without_byte_prefix() {
+    // Name (_S5, Package (0x02) { Zero, One }): small constants are encoded as ZeroOp and OneOp
+    let aml = [AML_NAME_OP, b'_', b'S', b'5', b'_', AML_PACKAGE_OP, 0x04, 0x02, 0x00, 0x01];
+    assert_eq!(s5_sleep_types(&aml), Some((0, 1)));
+    // a method called _S5_ isn't the package
+    let method = [0x14, b'_', b'S', b'5', b'_', AML_PACKAGE_OP, 0x04, 0x02, 0x00, 0x01];
+    assert_eq!(s5_sleep_types(&method), None);
+}
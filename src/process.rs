@@ -0,0 +1,357 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+use crate::exec::{self, ExecError};
+use crate::memory::{self, AddressSpace};
+use crate::signal::{self, Action, Disposition, Signal, SignalError, SignalState, UserContext};
+use crate::sync::{Mutex, SpinLock, WaitQueue};
+use crate::thread::{self, ThreadId};
+use crate::usermode::{self, UserExit};
+
+mod fd;
+
+pub use fd::{Descriptor, FdTable, OpenFile};
+
+pub type Pid = u64;
+
+// parent of the processes the kernel starts itself, e.g. from the shell
+pub const KERNEL_PID: Pid = 0;
+
+// a process's heap grows from here through `sbrk`
+pub const USER_HEAP_START: u64 = 0x_0000_1000_0000_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    Running,
+    // exited, until the parent collects the status with `wait`
+    Zombie(UserExit),
+}
+
+impl ProcessState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProcessState::Running => "running",
+            ProcessState::Zombie(_) => "zombie",
+        }
+    }
+}
+
+// A user program running in a thread of its own (or, through `run`, in the calling
+// one) with its own address space, heap and open files.
+struct Process {
+    pid: Pid,
+    // None once the parent exited, the process is reaped as soon as it exits then
+    parent: Option<Pid>,
+    name: String,
+    thread: ThreadId,
+    state: ProcessState,
+    files: Arc<Mutex<FdTable>>,
+    program_break: u64,
+    signals: SignalState,
+}
+
+// What `ps` shows about a process.
+pub struct ProcessInfo {
+    pub pid: Pid,
+    pub parent: Option<Pid>,
+    pub name: String,
+    pub thread: ThreadId,
+    pub state: ProcessState,
+    pub open_files: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitError {
+    // the caller has no (matching) children to wait for
+    NoChild,
+    // a signal came in while no child had exited yet
+    Interrupted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KillError {
+    NoSuchProcess,
+}
+
+// A spin lock, as signals are sent from and delivered on the way back from interrupts.
+static PROCESSES: SpinLock<BTreeMap<Pid, Process>> = SpinLock::new(BTreeMap::new());
+static NEXT_PID: AtomicU64 = AtomicU64::new(1);
+// woken whenever a process turns into a zombie
+static EXITED: WaitQueue = WaitQueue::new();
+// the processes Ctrl-C interrupts, i.e. the pipeline the shell waits for
+static FOREGROUND: SpinLock<Vec<Pid>> = SpinLock::new(Vec::new());
+
+fn current_in(processes: &BTreeMap<Pid, Process>) -> Option<Pid> {
+    let thread = thread::current();
+    processes
+        .values()
+        .find(|process| process.thread == thread && process.state == ProcessState::Running)
+        .map(|process| process.pid)
+}
+
+// Process running in the calling thread, if any.
+pub fn current() -> Option<Pid> {
+    current_in(&PROCESSES.lock())
+}
+
+fn with_process<R>(pid: Pid, f: impl FnOnce(&mut Process) -> R) -> Option<R> {
+    PROCESSES.lock().get_mut(&pid).map(f)
+}
+
+fn with_current<R>(f: impl FnOnce(&mut Process) -> R) -> Option<R> {
+    let mut processes = PROCESSES.lock();
+    let pid = current_in(&processes)?;
+    processes.get_mut(&pid).map(f)
+}
+
+// Open files of the calling process. They have a lock of their own, system calls
+// on files don't hold up the whole process table.
+pub fn files() -> Option<Arc<Mutex<FdTable>>> {
+    with_current(|process| process.files.clone())
+}
+
+// Adds a process running in `thread` (0 if it has none yet) as a child of the
+// calling one, or of the kernel.
+fn register(name: &str, thread: ThreadId, files: FdTable) -> Pid {
+    let pid = NEXT_PID.fetch_add(1, Ordering::Relaxed);
+    let mut processes = PROCESSES.lock();
+    let parent = current_in(&processes).unwrap_or(KERNEL_PID);
+    let process = Process {
+        pid,
+        parent: Some(parent),
+        name: String::from(name),
+        thread,
+        state: ProcessState::Running,
+        files: Arc::new(Mutex::new(files)),
+        program_break: USER_HEAP_START,
+        signals: SignalState::new(),
+    };
+    processes.insert(pid, process);
+    pid
+}
+
+// Runs process `pid` in the calling thread until it exits, then frees its heap and
+// files and leaves the exit status for the parent.
+fn enter(pid: Pid, entry: VirtAddr, stack_top: VirtAddr) -> UserExit {
+    with_process(pid, |process| process.thread = thread::current());
+    thread::reset_fpu();
+    let exit = usermode::run(entry, stack_top);
+
+    let (heap_end, files) = with_process(pid, |process| (process.program_break, process.files.clone()))
+        .expect("running process vanished");
+    memory::unmap_user_pages(VirtAddr::new(USER_HEAP_START), (heap_end - USER_HEAP_START).div_ceil(4096));
+    files.lock().clear();
+
+    let mut processes = PROCESSES.lock();
+    // orphans: exited ones are gone for good, the others are reaped once they exit
+    processes.retain(|_, process| process.parent != Some(pid) || process.state == ProcessState::Running);
+    for process in processes.values_mut().filter(|process| process.parent == Some(pid)) {
+        process.parent = None;
+    }
+    let process = processes.get_mut(&pid).expect("running process vanished");
+    process.state = ProcessState::Zombie(exit);
+    match process.parent {
+        None => {
+            processes.remove(&pid);
+        }
+        Some(parent) => {
+            if let Some(parent) = processes.get_mut(&parent) {
+                parent.signals.raise(Signal::Child);
+            }
+        }
+    }
+    drop(processes);
+    EXITED.wake_all();
+    exit
+}
+
+// Runs user code already mapped into the active address space as a new process in
+// the calling thread, and reaps it once it exits.
+pub fn run(name: &str, entry: VirtAddr, stack_top: VirtAddr) -> UserExit {
+    let pid = register(name, thread::current(), FdTable::new());
+    let exit = enter(pid, entry, stack_top);
+    PROCESSES.lock().remove(&pid);
+    exit
+}
+
+// Loads the ELF executable `image` and starts it in a new thread, as a child of the
+// calling process (or of the kernel). Its name is the file name of `args[0]`. It
+// inherits the standard descriptors of a calling process, a child of the kernel
+// gets the console.
+pub fn spawn(image: &[u8], args: &[&str], env: &[&str]) -> Result<Pid, ExecError> {
+    let files = match files() {
+        Some(files) => files.lock().inherit_stdio(),
+        None => FdTable::new(),
+    };
+    spawn_with_files(image, args, env, files)
+}
+
+// Like `spawn`, with the given open files, e.g. to connect the new process to pipes.
+pub fn spawn_with_files(image: &[u8], args: &[&str], env: &[&str], files: FdTable) -> Result<Pid, ExecError> {
+    let program = exec::load(image, args, env)?;
+    let name = args.first().and_then(|path| path.rsplit('/').next()).unwrap_or("?");
+    let pid = register(name, 0, files);
+    let thread = thread::spawn(name, move || {
+        let previous = unsafe { program.space.activate() };
+        enter(pid, program.entry, program.stack_pointer);
+        // the address space can only go once it's no longer active
+        unsafe { AddressSpace::restore(previous) };
+        drop(program);
+    });
+    with_process(pid, |process| {
+        if process.state == ProcessState::Running {
+            process.thread = thread;
+        }
+    });
+    Ok(pid)
+}
+
+// Reaps an exited child of `parent` (`pid`, or any with None). Ok(None) while the
+// matching children are all still running.
+fn reap_child(parent: Pid, pid: Option<Pid>) -> Result<Option<(Pid, UserExit)>, WaitError> {
+    let mut processes = PROCESSES.lock();
+    let mut children = processes
+        .values()
+        .filter(|process| process.parent == Some(parent) && pid.is_none_or(|pid| process.pid == pid))
+        .peekable();
+    if children.peek().is_none() {
+        return Err(WaitError::NoChild);
+    }
+    let zombie = children.find_map(|process| match process.state {
+        ProcessState::Zombie(exit) => Some((process.pid, exit)),
+        ProcessState::Running => None,
+    });
+    if let Some((pid, _)) = zombie {
+        processes.remove(&pid);
+    }
+    Ok(zombie)
+}
+
+// Waits until child `pid` (or any child, with None) of the calling process exits,
+// reaps it and returns its pid and exit reason. Kernel threads wait for the
+// processes the kernel started. A process gives up when it gets a signal.
+pub fn wait(pid: Option<Pid>) -> Result<(Pid, UserExit), WaitError> {
+    let parent = current().unwrap_or(KERNEL_PID);
+    let mut result = Err(WaitError::NoChild);
+    EXITED.wait_until(|| match reap_child(parent, pid) {
+        Ok(None) if signal_pending() => {
+            result = Err(WaitError::Interrupted);
+            true
+        }
+        Ok(None) => false,
+        Ok(Some(child)) => {
+            result = Ok(child);
+            true
+        }
+        Err(err) => {
+            result = Err(err);
+            true
+        }
+    });
+    result
+}
+
+// Like `wait`, but returns Ok(None) instead of blocking.
+pub fn try_wait(pid: Option<Pid>) -> Result<Option<(Pid, UserExit)>, WaitError> {
+    reap_child(current().unwrap_or(KERNEL_PID), pid)
+}
+
+// Sends `signal` to process `pid`. Unless the process ignores it, a thread blocked
+// in an interruptible wait (e.g. for console input) or sleeping is woken to act on it.
+pub fn kill(pid: Pid, signal: Signal) -> Result<(), KillError> {
+    let thread = with_process(pid, |process| match process.state {
+        ProcessState::Running if process.signals.raise(signal) => Some(process.thread),
+        _ => None,
+    })
+    .ok_or(KillError::NoSuchProcess)?;
+    if let Some(thread) = thread {
+        thread::wake_timed_out(thread);
+    }
+    Ok(())
+}
+
+// Whether the calling process has a signal to act on, which ends interruptible waits.
+pub fn signal_pending() -> bool {
+    with_current(|process| process.signals.is_pending()) == Some(true)
+}
+
+pub fn set_signal_action(signal: Signal, action: Action) -> Option<Result<Action, SignalError>> {
+    with_current(|process| process.signals.set_action(signal, action))
+}
+
+// Acts on the calling process's pending signals on its way back to user mode at
+// `context`: ends the process, or redirects `context` into its handlers. Must be
+// called with the kernel's GS base active.
+pub fn deliver_signals(context: &mut UserContext) {
+    while let Some((signal, disposition)) = with_current(|process| process.signals.take()).flatten() {
+        match disposition {
+            Disposition::Handle(handler) if signal::push_frame(context, signal, handler) => {}
+            // also when there's no room for the handler's frame
+            _ => usermode::exit(UserExit::Signaled(signal)),
+        }
+    }
+}
+
+// Makes `pids` the foreground job, the one Ctrl-C interrupts. Empty when the shell
+// itself reads the keyboard.
+pub fn set_foreground(pids: &[Pid]) {
+    *FOREGROUND.lock() = pids.to_vec();
+}
+
+// Sends SIGINT to the foreground job; false if there is none. Used from the keyboard
+// interrupt handler.
+pub fn interrupt_foreground() -> bool {
+    let foreground = FOREGROUND.lock().clone();
+    let mut interrupted = false;
+    for pid in foreground {
+        interrupted |= kill(pid, Signal::Interrupt).is_ok();
+    }
+    interrupted
+}
+
+// Moves the calling process's heap end by `increment` bytes, mapping or unmapping
+// pages as needed. Returns the previous end.
+pub fn sbrk(increment: i64) -> Option<u64> {
+    let old = with_current(|process| process.program_break)?;
+    let new = old.checked_add_signed(increment)?;
+    if !(USER_HEAP_START..=memory::USER_END).contains(&new) {
+        return None;
+    }
+    let (old_pages, new_pages) = (old.div_ceil(4096), new.div_ceil(4096));
+    if new_pages > old_pages {
+        let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        memory::map_user_pages(VirtAddr::new(old_pages * 4096), new_pages - old_pages, flags).ok()?;
+    } else if new_pages < old_pages {
+        memory::unmap_user_pages(VirtAddr::new(new_pages * 4096), old_pages - new_pages);
+    }
+    with_current(|process| process.program_break = new);
+    Some(old)
+}
+
+// Snapshot of all processes, ordered by pid.
+pub fn processes() -> Vec<ProcessInfo> {
+    // the file tables are locked one by one, without holding the process table
+    let processes: Vec<(ProcessInfo, Arc<Mutex<FdTable>>)> = PROCESSES
+        .lock()
+        .values()
+        .map(|process| {
+            let info = ProcessInfo {
+                pid: process.pid,
+                parent: process.parent,
+                name: process.name.clone(),
+                thread: process.thread,
+                state: process.state,
+                open_files: 0,
+            };
+            (info, process.files.clone())
+        })
+        .collect();
+    processes
+        .into_iter()
+        .map(|(info, files)| ProcessInfo { open_files: files.lock().count(), ..info })
+        .collect()
+}
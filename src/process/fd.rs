@@ -0,0 +1,94 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crate::ipc::{MessageQueue, PipeReader, PipeWriter};
+use crate::syscall::{Errno, STDERR};
+
+// console descriptors included
+const MAX_OPEN_FILES: usize = 19;
+
+// A file opened by a user program.
+#[derive(Clone)]
+pub struct OpenFile {
+    // absolute, the shell's current directory may change
+    pub path: String,
+    pub offset: usize,
+    pub append: bool,
+}
+
+// What a file descriptor refers to. A duplicated file (e.g. a child's standard
+// output) keeps its own offset.
+#[derive(Clone)]
+pub enum Descriptor {
+    Console,
+    File(OpenFile),
+    PipeReader(PipeReader),
+    PipeWriter(PipeWriter),
+    // reads and writes receive and send whole messages
+    MessageQueue(Arc<MessageQueue>),
+}
+
+// The files a process has open, indexed by descriptor. Descriptors 0 to 2 (standard
+// input, output and error) start out connected to the console.
+pub struct FdTable {
+    files: Vec<Option<Descriptor>>,
+}
+
+impl FdTable {
+    pub fn new() -> Self {
+        FdTable { files: alloc::vec![Some(Descriptor::Console), Some(Descriptor::Console), Some(Descriptor::Console)] }
+    }
+
+    // A table with copies of this one's standard descriptors, for a child process.
+    pub fn inherit_stdio(&self) -> Self {
+        FdTable { files: self.files.iter().take(STDERR as usize + 1).cloned().collect() }
+    }
+
+    // Stores `descriptor` under the lowest free descriptor and returns that.
+    pub fn insert(&mut self, descriptor: Descriptor) -> Result<u64, Errno> {
+        let index = match self.files.iter().position(Option::is_none) {
+            Some(index) => index,
+            None if self.files.len() < MAX_OPEN_FILES => {
+                self.files.push(None);
+                self.files.len() - 1
+            }
+            None => return Err(Errno::TooManyFiles),
+        };
+        self.files[index] = Some(descriptor);
+        Ok(index as u64)
+    }
+
+    // Puts `descriptor` at `fd`, closing whatever was there, e.g. to redirect
+    // standard output into a pipe.
+    pub fn set(&mut self, fd: u64, descriptor: Descriptor) {
+        let index = fd as usize;
+        assert!(index < MAX_OPEN_FILES, "file descriptor {} out of range", fd);
+        if self.files.len() <= index {
+            self.files.resize(index + 1, None);
+        }
+        self.files[index] = Some(descriptor);
+    }
+
+    pub fn get_mut(&mut self, fd: u64) -> Result<&mut Descriptor, Errno> {
+        self.files.get_mut(fd as usize).and_then(Option::as_mut).ok_or(Errno::BadFileDescriptor)
+    }
+
+    pub fn remove(&mut self, fd: u64) -> Result<Descriptor, Errno> {
+        self.files.get_mut(fd as usize).and_then(Option::take).ok_or(Errno::BadFileDescriptor)
+    }
+
+    // Number of open descriptors.
+    pub fn count(&self) -> usize {
+        self.files.iter().flatten().count()
+    }
+
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+}
+
+impl Default for FdTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,23 @@
+use alloc::string::String;
+use crate::interrupts;
+
+// mount point of the generated files
+pub const PROC_DIR: &str = "/proc";
+
+// Generates the current content of a file on every read.
+type Generator = fn() -> String;
+
+const ENTRIES: &[(&str, Generator)] = &[
+    ("interrupts", interrupts::stats_report),
+];
+
+// Reads a procfs file given as "/proc/<name>".
+pub fn read(path: &str) -> Option<String> {
+    let name = path.strip_prefix(PROC_DIR)?.strip_prefix('/')?;
+    ENTRIES.iter().find(|(entry, _)| *entry == name).map(|(_, generate)| generate())
+}
+
+// Names of all procfs files.
+pub fn list() -> impl Iterator<Item = &'static str> {
+    ENTRIES.iter().map(|(name, _)| *name)
+}
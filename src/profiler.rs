@@ -0,0 +1,106 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use x86_64::structures::idt::InterruptStackFrame;
+use crate::irq::{self, HandlerId, IrqError, IrqReturn};
+use crate::memory::{USER_END, USER_START};
+use crate::sync::SpinLock;
+use crate::{backtrace, time};
+
+// Sampling profiler. While running, the timer interrupt records the instruction
+// pointer of whatever it interrupted into a ring buffer, so over a few seconds the
+// samples add up to where the CPU spends its time. It only sees the processor that
+// gets the timer interrupt, and nothing that runs with interrupts disabled.
+
+// number of samples kept, the oldest are overwritten; about 40 s at 100 Hz
+pub const CAPACITY: usize = 4096;
+// how long sampling fills the buffer for
+pub const MAX_SECONDS: u64 = CAPACITY as u64 / time::TICK_HZ;
+
+static SAMPLES: [AtomicU64; CAPACITY] = [const { AtomicU64::new(0) }; CAPACITY];
+// samples taken since `start`, including overwritten ones
+static TAKEN: AtomicUsize = AtomicUsize::new(0);
+// the timer handler while running
+static HANDLER: SpinLock<Option<HandlerId>> = SpinLock::new(None);
+
+// A function and how many samples hit it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hotspot {
+    // None without a symbol table or for addresses outside the kernel, e.g. in
+    // user programs; `address` is then the sampled address itself
+    pub function: Option<&'static str>,
+    pub address: u64,
+    pub samples: usize,
+}
+
+// Throws away the previous samples and starts taking new ones on every timer tick.
+// Starting it while it runs starts over.
+pub fn start() -> Result<(), IrqError> {
+    let mut handler = HANDLER.lock();
+    if let Some(id) = handler.take() {
+        irq::unregister(id);
+    }
+    TAKEN.store(0, Ordering::Relaxed);
+    *handler = Some(irq::register(time::TIMER_IRQ, "profiler", &timer_tick)?);
+    Ok(())
+}
+
+// Stops taking samples; false if the profiler wasn't running.
+pub fn stop() -> bool {
+    match HANDLER.lock().take() {
+        Some(id) => {
+            irq::unregister(id);
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn is_running() -> bool {
+    HANDLER.lock().is_some()
+}
+
+// Number of samples taken since the last `start`, including those that were
+// overwritten.
+pub fn taken() -> usize {
+    TAKEN.load(Ordering::Relaxed)
+}
+
+// The samples in the buffer, oldest first. Only meaningful once stopped.
+pub fn samples() -> Vec<u64> {
+    let taken = taken();
+    let first = taken.saturating_sub(CAPACITY);
+    (first..taken).map(|index| SAMPLES[index % CAPACITY].load(Ordering::Relaxed)).collect()
+}
+
+// Counts the samples per function, most samples first. Samples in the same function
+// are added up when a symbol table is embedded; without one every address counts
+// on its own.
+pub fn histogram(samples: &[u64]) -> Vec<Hotspot> {
+    // resolving walks the whole symbol table, so do it once per distinct address
+    let mut per_address: BTreeMap<u64, usize> = BTreeMap::new();
+    for &address in samples {
+        *per_address.entry(address).or_insert(0) += 1;
+    }
+    let mut per_function: BTreeMap<u64, Hotspot> = BTreeMap::new();
+    for (address, samples) in per_address {
+        // user programs aren't in the kernel's symbol table
+        let symbol = if (USER_START..USER_END).contains(&address) { None } else { backtrace::resolve(address) };
+        let (function, address) = match symbol {
+            Some((name, offset)) => (Some(name), address - offset),
+            None => (None, address),
+        };
+        per_function.entry(address).or_insert(Hotspot { function, address, samples: 0 }).samples += samples;
+    }
+    let mut hotspots: Vec<Hotspot> = per_function.into_values().collect();
+    // stable, so ties stay ordered by address
+    hotspots.sort_by_key(|hotspot| Reverse(hotspot.samples));
+    hotspots
+}
+
+fn timer_tick(stack_frame: &InterruptStackFrame) -> IrqReturn {
+    let index = TAKEN.fetch_add(1, Ordering::Relaxed);
+    SAMPLES[index % CAPACITY].store(stack_frame.instruction_pointer.as_u64(), Ordering::Relaxed);
+    IrqReturn::Handled
+}
@@ -0,0 +1,26 @@
+use alloc::format;
+use alloc::vec::Vec;
+use crate::fs::FILE_SYSTEM;
+
+// User programs of the workspace (user/programs), built by build.rs
+macro_rules! program {
+    ($name:literal) => {
+        ($name, include_bytes!(concat!(env!("OUT_DIR"), "/user/", $name)) as &[u8])
+    };
+}
+
+pub static PROGRAMS: &[(&str, &[u8])] = &[program!("hello"), program!("cat")];
+
+// directory the programs are installed to
+pub const BIN_DIRECTORY: &str = "/bin";
+
+// Puts the user programs into BIN_DIRECTORY, replacing older copies, so they can be
+// started with `exec /bin/<name>`.
+pub fn install() {
+    let mut file_system = FILE_SYSTEM.lock();
+    file_system.create_directory_at(BIN_DIRECTORY).expect("failed to create the program directory");
+    for (name, image) in PROGRAMS {
+        let file = file_system.create_file_at(&format!("{}/{}", BIN_DIRECTORY, name)).expect("failed to install a program");
+        file.data = Vec::from(*image);
+    }
+}
@@ -1,22 +1,198 @@
-use crate::fs::FileSystem;
+use crate::fs::FILE_SYSTEM;
+use crate::{acpi, ipc, keyboard, process, procfs, profiler, serial_println, smp, syscall, thread, time, vga_buffer, watchdog};
+use crate::sync::spinlock;
+use crate::process::{Descriptor, FdTable};
+use crate::signal::Signal;
+use alloc::collections::BTreeMap;
+use crate::interrupts::{exception_name, stats_report};
 use crate::println;
 use alloc::string::String;
 // use crate::println;
 use crate::print;
 use alloc::vec::Vec;
+use alloc::format;
 use crate::alloc::string::ToString;
-use x86_64::instructions::{interrupts, hlt};
+use crate::power;
+use crate::usermode::UserExit;
 
 
 pub fn shutdown() -> ! {
     println!("Shutting down system...");
-    interrupts::disable();
+    power::shutdown();
+}
+
+pub fn reboot() -> ! {
+    println!("Rebooting system...");
+    power::reboot();
+}
+
+fn list_acpi_tables() {
+    let rsdp = match acpi::find_rsdp() {
+        Some(rsdp) => rsdp,
+        None => {
+            println!("No ACPI tables found.");
+            return;
+        }
+    };
+    let revision = rsdp.revision;
+    // the println! macro treats three or more arguments as colors, so format first
+    println!("{}", format!("RSDP revision {} ({})", revision, if revision >= 2 { "XSDT" } else { "RSDT" }));
+    let print_table = |table: &'static acpi::SdtHeader| {
+        let (length, table_revision) = (table.length, table.revision);
+        println!("{}", format!("  {} at {:#010x}  length {:>5}  rev {}  OEM {}", acpi::signature_str(table),
+            acpi::table_address(table), length, table_revision, acpi::oem_id_str(table)));
+    };
+    acpi::tables().for_each(print_table);
+    if let Some(dsdt) = acpi::dsdt() {
+        print_table(dsdt);
+    }
+    if let Some(madt) = acpi::madt() {
+        println!("CPUs (APIC IDs): {}", madt.processor_apic_ids().count());
+        for entry in madt.entries() {
+            println!("  {:?}", entry);
+        }
+    }
+    if let Some(mcfg) = acpi::mcfg() {
+        for entry in mcfg.entries() {
+            let (base, segment, start, end) = (entry.base_address, entry.segment_group, entry.start_bus, entry.end_bus);
+            println!("{}", format!("  PCIe segment {} buses {}-{} at {:#x}", segment, start, end, base));
+        }
+    }
+}
+
+// Shows how much CPU time each thread used during the last second, redrawn every
+// second until a key is pressed.
+fn top() {
+    let mut previous: BTreeMap<thread::ThreadId, u64> = thread::threads().iter().map(|info| (info.id, info.ticks)).collect();
+    let mut previous_ticks = time::ticks();
     loop {
-        hlt();
+        for _ in 0..10 {
+            thread::sleep(100);
+            if keyboard::fetch_from_buffer().is_some() {
+                return;
+            }
+        }
+        let now = time::ticks();
+        let elapsed = (now - previous_ticks).max(1);
+        let threads = thread::threads();
+
+        vga_buffer::clear_screen();
+        println!("{}", format!("top - up {} s, {} threads - press any key to quit", time::uptime_ms() / 1000, threads.len()));
+        println!();
+        println!("   ID  PRI     STATE      CPU%    TIME  NAME");
+        // the header takes three rows, the prompt one
+        for info in threads.iter().take(vga_buffer::BUFFER_HEIGHT - 4) {
+            let used = info.ticks - previous.get(&info.id).copied().unwrap_or(0);
+            println!("{}", format!(
+                "{:>5}  {:<6}  {:<8}  {:>4}%  {:>5} s  {}",
+                info.id,
+                info.priority.name(),
+                info.state.name(),
+                used * 100 / elapsed,
+                info.ticks / time::TICK_HZ,
+                info.name
+            ));
+        }
+        previous = threads.iter().map(|info| (info.id, info.ticks)).collect();
+        previous_ticks = now;
     }
 }
 
-pub fn start_shell(file_system: &mut FileSystem) {
+// Samples for `seconds` (or until a key is pressed), then prints the functions the
+// most samples were taken in, or with `raw` sends every sample to serial. Longer
+// than the profiler keeps samples for makes no sense, so that's the limit.
+fn profile(seconds: u64, raw: bool) {
+    let seconds = seconds.min(profiler::MAX_SECONDS);
+    if let Err(error) = profiler::start() {
+        println!("Can't start the profiler: {:?}", error);
+        return;
+    }
+    println!("Profiling for {} s - press any key to stop early", seconds);
+    for _ in 0..seconds * 10 {
+        thread::sleep(100);
+        if keyboard::fetch_from_buffer().is_some() {
+            break;
+        }
+    }
+    profiler::stop();
+    let samples = profiler::samples();
+    let dropped = profiler::taken() - samples.len();
+    println!("{}", format!("{} samples, {} overwritten", samples.len(), dropped));
+    if samples.is_empty() {
+        return;
+    }
+
+    if raw {
+        for sample in &samples {
+            serial_println!("{:#x}", sample);
+        }
+        println!("Samples sent to serial");
+        return;
+    }
+    println!("SAMPLES      %  FUNCTION");
+    // keep the lines above and the prompt on screen
+    for hotspot in profiler::histogram(&samples).iter().take(vga_buffer::BUFFER_HEIGHT - 4) {
+        let percent = hotspot.samples * 100 / samples.len();
+        match hotspot.function {
+            Some(name) => println!("{}", format!("{:>7}  {:>3}%  {}", hotspot.samples, percent, name)),
+            None => println!("{}", format!("{:>7}  {:>3}%  {:#x}", hotspot.samples, percent, hotspot.address)),
+        }
+    }
+}
+
+// Starts the programs of a pipeline, each one's standard output connected to the
+// next one's input, and waits for them unless they run in the background.
+fn run_pipeline(stages: &[(Vec<u8>, Vec<&str>)], background: bool) {
+    let mut pids = Vec::new();
+    let mut input = None;
+    for (index, (image, args)) in stages.iter().enumerate() {
+        let mut files = FdTable::new();
+        if let Some(reader) = input.take() {
+            files.set(syscall::STDIN, Descriptor::PipeReader(reader));
+        }
+        if index + 1 < stages.len() {
+            let (reader, writer) = ipc::pipe();
+            files.set(syscall::STDOUT, Descriptor::PipeWriter(writer));
+            input = Some(reader);
+        }
+        match process::spawn_with_files(image, args, &[], files) {
+            Ok(pid) => pids.push(pid),
+            Err(err) => {
+                // the programs started so far see their pipes close
+                println!("exec: {:?}", err);
+                break;
+            }
+        }
+    }
+    drop(input);
+    if background {
+        let pids: Vec<String> = pids.iter().map(|pid| pid.to_string()).collect();
+        println!("[{}]", pids.join(" "));
+        return;
+    }
+    // Ctrl-C interrupts the pipeline until all of it is done
+    process::set_foreground(&pids);
+    for pid in pids {
+        if let Ok((_, exit)) = process::wait(Some(pid)) {
+            report_exit(exit);
+        }
+    }
+    process::set_foreground(&[]);
+}
+
+fn report_exit(exit: UserExit) {
+    match exit {
+        UserExit::Exited(0) => {}
+        UserExit::Exited(status) => println!("Program exited with status {}", status),
+        // the fault was reported when the program was killed
+        UserExit::Fault(_) => {}
+        // Ctrl-C was echoed already
+        UserExit::Signaled(Signal::Interrupt) => {}
+        UserExit::Signaled(signal) => println!("Terminated by {}", signal.name()),
+    }
+}
+
+pub fn start_shell() {
     use crate::keyboard::read_keyboard;
 
     println!("Entering interactive shell. Type `help` for commands, or `exit` to quit.");
@@ -24,8 +200,17 @@ pub fn start_shell(file_system: &mut FileSystem) {
     let mut buffer = String::new();
 
     loop {
+        // background programs that finished since the last command
+        while let Ok(Some((pid, exit))) = process::try_wait(None) {
+            let status = match exit {
+                UserExit::Exited(status) => format!("Done ({})", status),
+                UserExit::Fault(vector) => format!("Killed by {}", exception_name(vector)),
+                UserExit::Signaled(signal) => format!("Terminated by {}", signal.name()),
+            };
+            println!("{}", format!("[{}] {}", pid, status));
+        }
         // Display prompt
-        print!("{} >> ", file_system.current_directory);
+        print!("{} >> ", FILE_SYSTEM.lock().current_directory);
         buffer.clear(); // Clear the buffer before reading new input
 
         // Read keyboard input and append to the buffer
@@ -33,6 +218,8 @@ pub fn start_shell(file_system: &mut FileSystem) {
         // Process the input when 'Enter' is pressed
         if buffer.ends_with('\n') && buffer.len() > 1 {
             let cmd = buffer.trim().to_string(); // Get command by trimming the buffer
+            // only locked while a command runs, user programs use the file system too
+            let mut file_system = FILE_SYSTEM.lock();
             match cmd.as_str() {
                 "help" => {
                     println!("Magenta", "black", "Available commands:");
@@ -46,7 +233,18 @@ pub fn start_shell(file_system: &mut FileSystem) {
                     println!("yellow", "black", "  mkdir <directory_name> - Create a new directory");
                     println!("yellow", "black", "  cd <directory_name> - Change the current directory");
                     println!("yellow", "black", "  shutdown - Poweroff");
+                    println!("yellow", "black", "  reboot - Restart the machine");
                     println!("yellow", "black", "  pwd - Get current working directory");
+                    println!("yellow", "black", "  irqstat - Show interrupt counters");
+                    println!("yellow", "black", "  acpi - List the ACPI tables");
+                    println!("yellow", "black", "  cpus - List the processors");
+                    println!("yellow", "black", "  ps - List the threads and processes");
+                    println!("yellow", "black", "  top - Show the CPU usage of the threads");
+                    println!("yellow", "black", "  exec <file> [args] [| <file> [args]] [&] - Run ELF executables, & in the background");
+                    println!("yellow", "black", "  kill [-<signal>] <pid> - Send a signal (SIGTERM) to a process");
+                    println!("yellow", "black", "  watchdog [<ms>|off] - Show or set the lockup detector's timeout");
+                    println!("yellow", "black", "  profile <seconds> [raw] - Show where the CPU spends its time");
+                    println!("yellow", "black", "  ls /proc - List generated kernel files");
                     buffer.clear();
                 }
                 "exit" => {
@@ -63,10 +261,119 @@ pub fn start_shell(file_system: &mut FileSystem) {
                     println!("Shutting down...");
                     shutdown(); // Call the shutdown function
                 }
+                "reboot" => {
+                    reboot();
+                }
                 "ls" => {
                     file_system.list_files();
                     buffer.clear();
                 }
+                "irqstat" => {
+                    print!("{}", stats_report());
+                    buffer.clear();
+                }
+                "acpi" => {
+                    list_acpi_tables();
+                    buffer.clear();
+                }
+                "cpus" => {
+                    for (index, cpu) in smp::cpus().enumerate() {
+                        let state = if cpu.is_online() { "online" } else { "offline" };
+                        println!("{}", format!("CPU{}  APIC ID {:>3}  {:<7}  {} IRQs", index, cpu.apic_id(), state, cpu.interrupts()));
+                    }
+                    buffer.clear();
+                }
+                "ps" => {
+                    let processes = process::processes();
+                    println!("   ID    PID  PRI     STATE     TICKS  NAME");
+                    for info in thread::threads() {
+                        let pid = processes
+                            .iter()
+                            .find(|process| process.thread == info.id && process.state == process::ProcessState::Running)
+                            .map_or(String::from("-"), |process| process.pid.to_string());
+                        println!("{}", format!(
+                            "{:>5}  {:>5}  {:<6}  {:<8} {:>6}  {}",
+                            info.id,
+                            pid,
+                            info.priority.name(),
+                            info.state.name(),
+                            info.ticks,
+                            info.name
+                        ));
+                    }
+                    // exited processes have no thread left, until their parent waits for them
+                    for zombie in processes.iter().filter(|process| process.state != process::ProcessState::Running) {
+                        println!("{}", format!("    -  {:>5}  -       zombie         -  {}", zombie.pid, zombie.name));
+                    }
+                    buffer.clear();
+                }
+                cmd if cmd.starts_with("kill ") => {
+                    // kill [-<signal>] <pid>, SIGTERM by default
+                    let args: Vec<&str> = cmd[5..].split_whitespace().collect();
+                    let (signal, pid) = match args[..] {
+                        [pid] => (Some(Signal::Terminate), pid),
+                        [signal, pid] => (signal.strip_prefix('-').and_then(Signal::parse), pid),
+                        _ => (None, ""),
+                    };
+                    match (signal, pid.parse()) {
+                        (Some(signal), Ok(pid)) => {
+                            if process::kill(pid, signal).is_err() {
+                                println!("kill: no process {}", pid);
+                            }
+                        }
+                        _ => println!("Usage: kill [-INT|-KILL|-TERM|-CHLD] <pid>"),
+                    }
+                    buffer.clear();
+                }
+                "top" => {
+                    drop(file_system);
+                    top();
+                    buffer.clear();
+                }
+                cmd if cmd == "watchdog" || cmd.starts_with("watchdog ") => {
+                    match cmd[8..].trim() {
+                        "" => {}
+                        "off" => watchdog::set_timeout(None),
+                        ms => match ms.parse() {
+                            Ok(ms) if ms > 0 => watchdog::set_timeout(Some(ms)),
+                            _ => println!("Usage: watchdog [<ms>|off]"),
+                        },
+                    }
+                    match watchdog::timeout() {
+                        Some(ms) => println!("Watchdog timeout: {} ms", ms),
+                        None => println!("Watchdog off"),
+                    }
+                    println!("Lockups detected: {}", watchdog::lockups());
+                    if let Some((cycles, location)) = spinlock::longest_hold() {
+                        println!("{}", format!("Longest spin lock hold: {} cycles, taken at {}", cycles, location));
+                    }
+                    buffer.clear();
+                }
+                cmd if cmd.starts_with("profile ") => {
+                    // background programs may use the file system while sampling
+                    drop(file_system);
+                    let mut args = cmd[8..].split_whitespace();
+                    match (args.next().map(str::parse), args.next(), args.next()) {
+                        (Some(Ok(seconds)), mode @ (None | Some("raw")), None) if seconds > 0 => {
+                            profile(seconds, mode.is_some())
+                        }
+                        _ => println!("Usage: profile <seconds> [raw]"),
+                    }
+                    buffer.clear();
+                }
+                "ls /proc" => {
+                    for name in procfs::list() {
+                        println!("green", "black", "{}", name);
+                    }
+                    buffer.clear();
+                }
+                cmd if cmd.starts_with("cat /proc/") => {
+                    match procfs::read(&cmd[4..]) {
+                        Some(data) => print!("{}", data),
+                        None => println!("File '{}' not found.", &cmd[4..]),
+                    }
+                    buffer.clear();
+                }
                 cmd if cmd.starts_with("cat ") => {
                     let filename = &cmd[4..];
                     if let Some(data) = file_system.read_file(filename) {
@@ -95,6 +402,32 @@ pub fn start_shell(file_system: &mut FileSystem) {
                     }
                         buffer.clear();
                 }
+                cmd if cmd.starts_with("exec ") => {
+                    let mut line = cmd[5..].trim();
+                    let background = line.ends_with('&');
+                    if background {
+                        line = line[..line.len() - 1].trim_end();
+                    }
+                    // `exec a | b` feeds the output of a into b
+                    let mut stages = Vec::new();
+                    for stage in line.split('|') {
+                        let args: Vec<&str> = stage.split_whitespace().collect();
+                        match args.first().and_then(|path| file_system.file(path)) {
+                            Some(file) => stages.push((file.data.clone(), args)),
+                            None => {
+                                println!("File '{}' not found.", stage.trim());
+                                stages.clear();
+                                break;
+                            }
+                        }
+                    }
+                    // the programs' system calls need the file system
+                    drop(file_system);
+                    if !stages.is_empty() {
+                        run_pipeline(&stages, background);
+                    }
+                    buffer.clear();
+                }
                 cmd if cmd.starts_with("mkdir ") => {
                     let dirname = &cmd[6..];
                     file_system.create_directory(dirname.to_string());
@@ -0,0 +1,180 @@
+use x86_64::VirtAddr;
+use crate::memory::{self, USER_END, USER_START};
+
+// POSIX-like signals for processes. Sending one only marks it pending; it's acted on
+// the next time the process returns to user mode, at the end of a system call or
+// of an interrupt that came from user mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Signal {
+    Interrupt = 2,
+    Kill = 9,
+    Terminate = 15,
+    // a child process exited
+    Child = 17,
+}
+
+impl Signal {
+    pub const ALL: [Signal; 4] = [Signal::Interrupt, Signal::Kill, Signal::Terminate, Signal::Child];
+
+    pub fn from_number(number: u64) -> Option<Signal> {
+        Signal::ALL.into_iter().find(|signal| signal.number() as u64 == number)
+    }
+
+    // Accepts a number or a name, with or without the "SIG" prefix.
+    pub fn parse(name: &str) -> Option<Signal> {
+        if let Ok(number) = name.parse() {
+            return Signal::from_number(number);
+        }
+        let name = name.strip_prefix("SIG").unwrap_or(name);
+        Signal::ALL.into_iter().find(|signal| &signal.name()[3..] == name)
+    }
+
+    pub fn number(self) -> u8 {
+        self as u8
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Signal::Interrupt => "SIGINT",
+            Signal::Kill => "SIGKILL",
+            Signal::Terminate => "SIGTERM",
+            Signal::Child => "SIGCHLD",
+        }
+    }
+
+    fn bit(self) -> u32 {
+        1 << self.number()
+    }
+
+    // What happens without a handler: the process ends, except for SIGCHLD.
+    fn terminates_by_default(self) -> bool {
+        self != Signal::Child
+    }
+}
+
+// How a process deals with a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Default,
+    Ignore,
+    // user code entered with the signal frame described at `push_frame`
+    Handler(VirtAddr),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalError {
+    // SIGKILL can be neither caught nor ignored
+    Uncatchable,
+    BadHandler,
+}
+
+// What the kernel acts on for a pending signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disposition {
+    Terminate,
+    Handle(VirtAddr),
+}
+
+// Pending signals and actions of one process.
+pub struct SignalState {
+    pending: u32,
+    actions: [Action; 32],
+}
+
+impl SignalState {
+    pub const fn new() -> Self {
+        SignalState { pending: 0, actions: [Action::Default; 32] }
+    }
+
+    fn action(&self, signal: Signal) -> Action {
+        self.actions[signal.number() as usize]
+    }
+
+    fn is_ignored(&self, signal: Signal) -> bool {
+        match self.action(signal) {
+            Action::Ignore => true,
+            Action::Default => !signal.terminates_by_default(),
+            Action::Handler(_) => false,
+        }
+    }
+
+    // Marks `signal` pending, unless it would be ignored anyway. Returns whether it was.
+    pub fn raise(&mut self, signal: Signal) -> bool {
+        if self.is_ignored(signal) {
+            return false;
+        }
+        self.pending |= signal.bit();
+        true
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending != 0
+    }
+
+    pub fn set_action(&mut self, signal: Signal, action: Action) -> Result<Action, SignalError> {
+        if signal == Signal::Kill {
+            return Err(SignalError::Uncatchable);
+        }
+        if let Action::Handler(address) = action {
+            if !(USER_START..USER_END).contains(&address.as_u64()) {
+                return Err(SignalError::BadHandler);
+            }
+        }
+        let previous = core::mem::replace(&mut self.actions[signal.number() as usize], action);
+        if self.is_ignored(signal) {
+            self.pending &= !signal.bit();
+        }
+        Ok(previous)
+    }
+
+    // Takes the most urgent pending signal (SIGKILL first, then by number) with what
+    // to do about it.
+    pub fn take(&mut self) -> Option<(Signal, Disposition)> {
+        let signal = if self.pending & Signal::Kill.bit() != 0 {
+            Signal::Kill
+        } else {
+            Signal::from_number(self.pending.trailing_zeros() as u64)?
+        };
+        self.pending &= !signal.bit();
+        match self.action(signal) {
+            Action::Handler(address) if signal != Signal::Kill => Some((signal, Disposition::Handle(address))),
+            _ => Some((signal, Disposition::Terminate)),
+        }
+    }
+}
+
+impl Default for SignalState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// bytes below the stack pointer user code may use without moving it (System V)
+const RED_ZONE: u64 = 128;
+
+// Where a process resumes in user mode. Redirecting it enters a signal handler.
+#[derive(Debug, Clone, Copy)]
+pub struct UserContext {
+    pub rip: u64,
+    pub rsp: u64,
+    pub rflags: u64,
+}
+
+// Makes the process enter `handler` instead of resuming at `context`, with all other
+// registers unchanged. Below the red zone the handler finds the signal number at the
+// stack pointer, followed by the flags and the address to resume at; it saves the
+// registers it uses, restores the flags and returns with `ret 128`, which gets the
+// stack pointer back to where it was. False if the stack has no room.
+pub fn push_frame(context: &mut UserContext, signal: Signal, handler: VirtAddr) -> bool {
+    let frame = [signal.number() as u64, context.rflags, context.rip];
+    let Some(rsp) = context.rsp.checked_sub(RED_ZONE + 8 * frame.len() as u64) else { return false };
+    if !memory::check_user_range(rsp, 8 * frame.len() as u64, true) {
+        return false;
+    }
+    // the user's stack pointer need not be aligned
+    unsafe { core::ptr::copy_nonoverlapping(frame.as_ptr() as *const u8, rsp as *mut u8, 8 * frame.len()) };
+    context.rsp = rsp;
+    context.rip = handler.as_u64();
+    true
+}
@@ -0,0 +1,282 @@
+use core::arch::global_asm;
+use core::mem::{self, offset_of};
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::registers::control::{Cr3, Cr4, Cr4Flags};
+use x86_64::structures::paging::{mapper::MapToError, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::VirtAddr;
+use crate::percpu::{self, PerCpu, MAX_CPUS};
+use crate::{acpi, apic, fpu, gdt, interrupts, memory, println, syscall, time, watchdog};
+
+// pages of kernel stack every application processor starts with
+const AP_STACK_PAGES: u64 = 16;
+
+// how long to wait for an application processor to come up after its SIPIs
+const AP_BOOT_TIMEOUT_US: u64 = 100_000;
+
+// number of CPUs found in the MADT (at least the BSP)
+static CPU_COUNT: AtomicUsize = AtomicUsize::new(1);
+
+// Parameters the trampoline reads once the application processor runs it. Lives at
+// the end of the trampoline, so its fields are reachable from real mode as well.
+#[repr(C, packed)]
+struct TrampolineData {
+    // null, 32 bit code (0x08), 32 bit data (0x10), 64 bit code (0x18)
+    gdt: [u64; 4],
+    gdt_limit: u16,
+    gdt_base: u32,
+    // far pointers (offset, selector) for the jumps into protected and long mode
+    protected_mode_offset: u32,
+    protected_mode_selector: u16,
+    long_mode_offset: u32,
+    long_mode_selector: u16,
+    cr3: u64,
+    cr4: u64,
+    stack_top: u64,
+    entry: u64,
+    cpu_index: u64,
+}
+
+// Real mode startup code for the application processors. A SIPI starts them at
+// `cs = page << 8, ip = 0`, so the code only uses addresses relative to its own
+// start: ebx holds the physical load address from the first instructions on.
+global_asm!(
+    ".pushsection .rodata.ap_trampoline, \"a\"",
+    ".code16",
+    ".global ap_trampoline_start",
+    "ap_trampoline_start:",
+    "    cli",
+    "    cld",
+    "    mov %cs, %ax",
+    "    mov %ax, %ds",
+    "    movzwl %ax, %ebx",
+    "    shl $4, %ebx",
+    "    lgdtl (ap_trampoline_data - ap_trampoline_start + {gdt_pointer})",
+    "    mov %cr0, %eax",
+    "    or $1, %eax",
+    "    mov %eax, %cr0",
+    "    ljmpl *(ap_trampoline_data - ap_trampoline_start + {protected_mode})",
+    ".code32",
+    ".global ap_trampoline_protected",
+    "ap_trampoline_protected:",
+    "    mov $0x10, %ax",
+    "    mov %ax, %ds",
+    "    mov %ax, %es",
+    "    mov %ax, %ss",
+    "    mov (ap_trampoline_data - ap_trampoline_start + {cr4})(%ebx), %eax",
+    "    mov %eax, %cr4",
+    "    mov (ap_trampoline_data - ap_trampoline_start + {cr3})(%ebx), %eax",
+    "    mov %eax, %cr3",
+    // EFER: long mode enable and no-execute enable
+    "    mov $0xc0000080, %ecx",
+    "    rdmsr",
+    "    or $0x900, %eax",
+    "    wrmsr",
+    // paging and write protect
+    "    mov %cr0, %eax",
+    "    or $0x80010000, %eax",
+    "    mov %eax, %cr0",
+    "    ljmpl *(ap_trampoline_data - ap_trampoline_start + {long_mode})(%ebx)",
+    ".code64",
+    ".global ap_trampoline_long",
+    "ap_trampoline_long:",
+    "    xor %eax, %eax",
+    "    mov %ax, %ds",
+    "    mov %ax, %es",
+    "    mov %ax, %ss",
+    "    mov %ax, %fs",
+    "    mov %ax, %gs",
+    "    mov (ap_trampoline_data - ap_trampoline_start + {stack_top})(%rbx), %rsp",
+    "    mov (ap_trampoline_data - ap_trampoline_start + {cpu_index})(%rbx), %rdi",
+    "    mov (ap_trampoline_data - ap_trampoline_start + {entry})(%rbx), %rax",
+    // terminate backtraces here
+    "    xor %ebp, %ebp",
+    "    call *%rax",
+    "ap_trampoline_halt:",
+    "    hlt",
+    "    jmp ap_trampoline_halt",
+    ".balign 8",
+    ".global ap_trampoline_data",
+    "ap_trampoline_data:",
+    "    .skip {data_size}",
+    ".global ap_trampoline_end",
+    "ap_trampoline_end:",
+    ".popsection",
+    gdt_pointer = const offset_of!(TrampolineData, gdt_limit),
+    protected_mode = const offset_of!(TrampolineData, protected_mode_offset),
+    long_mode = const offset_of!(TrampolineData, long_mode_offset),
+    cr3 = const offset_of!(TrampolineData, cr3),
+    cr4 = const offset_of!(TrampolineData, cr4),
+    stack_top = const offset_of!(TrampolineData, stack_top),
+    entry = const offset_of!(TrampolineData, entry),
+    cpu_index = const offset_of!(TrampolineData, cpu_index),
+    data_size = const mem::size_of::<TrampolineData>(),
+    options(att_syntax),
+);
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_protected: u8;
+    static ap_trampoline_long: u8;
+    static ap_trampoline_data: u8;
+    static ap_trampoline_end: u8;
+}
+
+// Offset of a trampoline label from the trampoline's start.
+fn trampoline_offset(label: &u8) -> u64 {
+    label as *const u8 as u64 - unsafe { &ap_trampoline_start as *const u8 as u64 }
+}
+
+// Rust entry point of the application processors, called by the trampoline on the
+// stack allocated for them.
+extern "C" fn ap_main(cpu_index: u64) -> ! {
+    percpu::init(cpu_index as usize);
+    gdt::init_ap(cpu_index as usize);
+    fpu::init();
+    syscall::init();
+    interrupts::init_idt();
+    apic::enable();
+    percpu::current().set_online();
+    // the first one keeps an eye on the bootstrap processor
+    if cpu_index == 1 {
+        watchdog::init_ap();
+    }
+    // legacy PIC interrupts are only delivered to the BSP, so there's nothing to do yet
+    x86_64::instructions::interrupts::enable();
+    crate::hlt_loop();
+}
+
+// Copies the trampoline into `frame` and identity maps it, so execution continues
+// once the application processors enable paging. Returns whether the mapping was
+// created (and must be removed again) by this call.
+fn install_trampoline(frame: PhysFrame) -> Result<bool, MapToError<Size4KiB>> {
+    let start = unsafe { &ap_trampoline_start as *const u8 };
+    let size = trampoline_offset(unsafe { &ap_trampoline_end }) as usize;
+    let base = frame.start_address().as_u64();
+    unsafe { ptr::copy_nonoverlapping(start, memory::phys_to_virt(frame.start_address()).as_mut_ptr::<u8>(), size) };
+
+    let data = trampoline_data(frame);
+    data.gdt = [0, 0x00cf_9a00_0000_ffff, 0x00cf_9200_0000_ffff, 0x00af_9a00_0000_ffff];
+    data.gdt_limit = (mem::size_of::<[u64; 4]>() - 1) as u16;
+    data.gdt_base = (base + trampoline_offset(unsafe { &ap_trampoline_data })) as u32;
+    data.protected_mode_offset = (base + trampoline_offset(unsafe { &ap_trampoline_protected })) as u32;
+    data.protected_mode_selector = 0x08;
+    data.long_mode_offset = (base + trampoline_offset(unsafe { &ap_trampoline_long })) as u32;
+    data.long_mode_selector = 0x18;
+    data.cr3 = Cr3::read().0.start_address().as_u64();
+    // PCIDE can only be set once long mode is active
+    data.cr4 = (Cr4::read() - Cr4Flags::PCID).bits();
+    data.entry = ap_main as *const () as u64;
+
+    let page = Page::<Size4KiB>::containing_address(VirtAddr::new(base));
+    memory::with_memory(|memory| {
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        match unsafe { memory.mapper.map_to(page, frame, flags, &mut memory.frame_allocator) } {
+            Ok(flush) => {
+                flush.flush();
+                Ok(true)
+            }
+            // the bootloader may have identity mapped low memory already
+            Err(MapToError::PageAlreadyMapped(mapped)) if mapped == frame => Ok(false),
+            Err(err) => Err(err),
+        }
+    })
+}
+
+fn trampoline_data(frame: PhysFrame) -> &'static mut TrampolineData {
+    let offset = trampoline_offset(unsafe { &ap_trampoline_data });
+    unsafe { &mut *memory::phys_to_virt(frame.start_address() + offset).as_mut_ptr::<TrampolineData>() }
+}
+
+// Boots one application processor with INIT-SIPI-SIPI and waits until it is online.
+fn boot_ap(frame: PhysFrame, cpu_index: usize, apic_id: u8) -> Result<(), &'static str> {
+    let stack_top = memory::alloc_stack(AP_STACK_PAGES).map_err(|_| "allocating the stack failed")?;
+    let data = trampoline_data(frame);
+    data.stack_top = stack_top.as_u64();
+    data.cpu_index = cpu_index as u64;
+    percpu::get(cpu_index).set_apic_id(apic_id);
+
+    let vector = (frame.start_address().as_u64() >> 12) as u8;
+    apic::send_init(apic_id);
+    time::busy_wait_us(10_000);
+    apic::send_startup(apic_id, vector);
+    time::busy_wait_us(200);
+    // the second SIPI is only needed if the first one got lost
+    if !percpu::get(cpu_index).is_online() {
+        apic::send_startup(apic_id, vector);
+    }
+
+    let mut waited = 0;
+    while !percpu::get(cpu_index).is_online() {
+        if waited >= AP_BOOT_TIMEOUT_US {
+            return Err("timed out");
+        }
+        time::busy_wait_us(1000);
+        waited += 1000;
+    }
+    Ok(())
+}
+
+// Enumerates the processors in the MADT and boots every application processor.
+// Needs the memory manager installed and a running system tick.
+pub fn init() {
+    if let Err(err) = apic::init() {
+        println!("SMP: no local APIC ({}), running on the BSP only", err);
+        return;
+    }
+    let bsp_id = apic::id();
+    percpu::current().set_apic_id(bsp_id);
+
+    let madt = acpi::madt().expect("the local APIC was found through the MADT");
+    let ap_ids = madt.processor_apic_ids().filter(|&id| id != bsp_id);
+    let frame = match memory::with_memory(|memory| memory.frame_allocator.low_memory_frame()) {
+        Some(frame) => frame,
+        None => {
+            println!("SMP: no free memory below 1 MiB for the trampoline");
+            return;
+        }
+    };
+    // the trampoline loads CR3 in 32 bit mode
+    if Cr3::read().0.start_address().as_u64() > u32::MAX as u64 {
+        println!("SMP: page table above 4 GiB, running on the BSP only");
+        return;
+    }
+    let mapped = match install_trampoline(frame) {
+        Ok(mapped) => mapped,
+        Err(err) => {
+            println!("SMP: mapping the trampoline failed: {:?}", err);
+            return;
+        }
+    };
+
+    let mut count = 1;
+    for apic_id in ap_ids {
+        if count == MAX_CPUS {
+            println!("SMP: more than {} CPUs, ignoring the rest", MAX_CPUS);
+            break;
+        }
+        match boot_ap(frame, count, apic_id) {
+            Ok(()) => count += 1,
+            Err(err) => println!("SMP: CPU with APIC ID {apic_id} failed to start: {err}"),
+        }
+    }
+    CPU_COUNT.store(count, Ordering::Release);
+
+    if mapped {
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(frame.start_address().as_u64()));
+        memory::with_memory(|memory| {
+            if let Ok((_, flush)) = memory.mapper.unmap(page) {
+                flush.flush();
+            }
+        });
+    }
+}
+
+// All CPUs that were brought up, the BSP first.
+pub fn cpus() -> impl Iterator<Item = &'static PerCpu> {
+    (0..CPU_COUNT.load(Ordering::Acquire)).map(percpu::get)
+}
+
+pub fn online_count() -> usize {
+    cpus().filter(|cpu| cpu.is_online()).count()
+}
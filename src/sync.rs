@@ -0,0 +1,18 @@
+// Locks for kernel code. `SpinLock` is for data shared with interrupt handlers and
+// keeps interrupts disabled while held. The others put a thread that has to wait to
+// sleep on a `WaitQueue` instead of spinning, so they must not be used from
+// interrupt handlers.
+
+pub mod condvar;
+pub mod mutex;
+pub mod rwlock;
+pub mod semaphore;
+pub mod spinlock;
+pub mod wait_queue;
+
+pub use condvar::Condvar;
+pub use mutex::{Mutex, MutexGuard};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::Semaphore;
+pub use spinlock::{SpinLock, SpinLockGuard};
+pub use wait_queue::WaitQueue;
@@ -0,0 +1,51 @@
+use super::{MutexGuard, WaitQueue};
+
+// A condition variable, to wait for a change of data protected by a `Mutex`.
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Condvar { waiters: WaitQueue::new() }
+    }
+
+    // Releases the lock, sleeps until notified and takes the lock again. A
+    // notification sent after the lock was released is not missed, but the wait may
+    // also end spuriously, so check the condition in a loop or use `wait_while`.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex();
+        self.waiters.wait_with(|| drop(guard));
+        mutex.lock()
+    }
+
+    // Like `wait`, but wakes up after `ms` milliseconds at the latest. The flag is
+    // true if the wait timed out.
+    pub fn wait_timeout<'a, T>(&self, guard: MutexGuard<'a, T>, ms: u64) -> (MutexGuard<'a, T>, bool) {
+        let mutex = guard.mutex();
+        let notified = self.waiters.wait_with_timeout(ms, || drop(guard));
+        (mutex.lock(), !notified)
+    }
+
+    // Waits as long as `condition` holds for the protected data.
+    pub fn wait_while<'a, T>(&self, mut guard: MutexGuard<'a, T>, mut condition: impl FnMut(&mut T) -> bool) -> MutexGuard<'a, T> {
+        while condition(&mut guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    pub fn notify_one(&self) {
+        self.waiters.wake_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.waiters.wake_all();
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,110 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use super::{SpinLock, WaitQueue};
+
+// Any number of readers or a single writer. Waiting writers don't hold off new
+// readers, so a steady stream of readers can starve them.
+pub struct RwLock<T> {
+    // number of readers, or None while a writer holds the lock
+    state: SpinLock<Option<usize>>,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for RwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> Self {
+        RwLock { state: SpinLock::new(Some(0)), waiters: WaitQueue::new(), data: UnsafeCell::new(value) }
+    }
+
+    fn acquire_read(&self) -> bool {
+        match &mut *self.state.lock() {
+            Some(readers) => {
+                *readers += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn acquire_write(&self) -> bool {
+        let mut state = self.state.lock();
+        if *state == Some(0) {
+            *state = None;
+            return true;
+        }
+        false
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.waiters.wait_until(|| self.acquire_read());
+        RwLockReadGuard { lock: self }
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        self.waiters.wait_until(|| self.acquire_write());
+        RwLockWriteGuard { lock: self }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        self.acquire_read().then_some(RwLockReadGuard { lock: self })
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        self.acquire_write().then_some(RwLockWriteGuard { lock: self })
+    }
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        let last = {
+            let mut state = self.lock.state.lock();
+            let readers = state.as_mut().expect("read guard without readers");
+            *readers -= 1;
+            *readers == 0
+        };
+        // only a writer can be waiting for readers to leave
+        if last {
+            self.lock.waiters.wake_all();
+        }
+    }
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        *self.lock.state.lock() = Some(0);
+        self.lock.waiters.wake_all();
+    }
+}
@@ -0,0 +1,40 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use super::WaitQueue;
+
+// A counting semaphore: `acquire` takes one of the available permits, sleeping
+// until one is released if there's none left.
+pub struct Semaphore {
+    permits: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Semaphore { permits: AtomicUsize::new(permits), waiters: WaitQueue::new() }
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        self.permits.fetch_update(Ordering::Acquire, Ordering::Relaxed, |permits| permits.checked_sub(1)).is_ok()
+    }
+
+    pub fn acquire(&self) {
+        if !self.try_acquire() {
+            self.waiters.wait_until(|| self.try_acquire());
+        }
+    }
+
+    // Like `acquire`, but gives up after `ms` milliseconds. Returns whether a permit
+    // was taken.
+    pub fn acquire_timeout(&self, ms: u64) -> bool {
+        self.try_acquire() || self.waiters.wait_until_timeout(ms, || self.try_acquire())
+    }
+
+    pub fn release(&self) {
+        self.permits.fetch_add(1, Ordering::Release);
+        self.waiters.wake_one();
+    }
+
+    pub fn available(&self) -> usize {
+        self.permits.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,299 @@
+use core::arch::x86_64::_rdtsc;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::panic::Location;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+use x86_64::instructions::interrupts;
+use crate::percpu;
+use crate::serial_println;
+use crate::thread::ThreadId;
+
+// A spin lock that disables interrupts on the holding CPU, so an interrupt handler
+// taking the same lock can't spin forever on a holder it interrupted. It remembers
+// who holds it, for the lockup diagnostics of the watchdog.
+pub struct SpinLock<T> {
+    inner: spin::Mutex<T>,
+    owner: Owner,
+    // false for locks made with `new_interruptible`
+    disables_interrupts: bool,
+}
+
+pub struct SpinLockGuard<'a, T> {
+    guard: ManuallyDrop<spin::MutexGuard<'a, T>>,
+    owner: &'a Owner,
+    // whether interrupts were enabled before locking
+    interrupts_enabled: bool,
+}
+
+// TSC cycles a CPU spins on a lock before it reports a possible deadlock on serial,
+// about a second on current machines
+const SPIN_REPORT_CYCLES: u64 = 3_000_000_000;
+// how many held locks the watchdog can list
+const MAX_TRACKED: usize = 32;
+
+// Who holds a lock, since when and where it was taken.
+struct Owner {
+    thread: AtomicU64,
+    cpu: AtomicUsize,
+    since: AtomicU64,
+    location: AtomicPtr<Location<'static>>,
+    // index into HELD, MAX_TRACKED if not listed there
+    slot: AtomicUsize,
+}
+
+// A held lock as listed by `held_locks`.
+pub struct HeldLock {
+    pub address: usize,
+    pub thread: ThreadId,
+    pub cpu: usize,
+    // TSC cycles since it was taken
+    pub cycles: u64,
+    pub location: &'static Location<'static>,
+}
+
+// the locks held right now
+static HELD: [AtomicPtr<Owner>; MAX_TRACKED] = [const { AtomicPtr::new(ptr::null_mut()) }; MAX_TRACKED];
+// the longest any lock was held, in TSC cycles, and where it was taken
+static LONGEST_HOLD: AtomicU64 = AtomicU64::new(0);
+static LONGEST_HOLD_AT: AtomicPtr<Location<'static>> = AtomicPtr::new(ptr::null_mut());
+
+fn cycles() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+fn cpu_index() -> usize {
+    percpu::try_current().map_or(0, |cpu| cpu.index())
+}
+
+impl Owner {
+    const fn new() -> Self {
+        Owner {
+            thread: AtomicU64::new(0),
+            cpu: AtomicUsize::new(0),
+            since: AtomicU64::new(0),
+            location: AtomicPtr::new(ptr::null_mut()),
+            slot: AtomicUsize::new(MAX_TRACKED),
+        }
+    }
+
+    fn acquired(&self, location: &'static Location<'static>) {
+        // early during boot there's no per-CPU area yet, only the bootstrap processor
+        let (thread, cpu) = percpu::try_current().map_or((0, 0), |cpu| (cpu.current_task(), cpu.index()));
+        self.thread.store(thread, Ordering::Relaxed);
+        self.cpu.store(cpu, Ordering::Relaxed);
+        self.location.store(location as *const _ as *mut _, Ordering::Relaxed);
+        self.since.store(cycles(), Ordering::Relaxed);
+        let this = self as *const Owner as *mut Owner;
+        let slot = HELD
+            .iter()
+            .position(|held| held.compare_exchange(ptr::null_mut(), this, Ordering::AcqRel, Ordering::Relaxed).is_ok());
+        self.slot.store(slot.unwrap_or(MAX_TRACKED), Ordering::Relaxed);
+    }
+
+    fn released(&self) {
+        let held = cycles().saturating_sub(self.since.load(Ordering::Relaxed));
+        if LONGEST_HOLD.fetch_max(held, Ordering::Relaxed) < held {
+            LONGEST_HOLD_AT.store(self.location.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+        let slot = self.slot.swap(MAX_TRACKED, Ordering::Relaxed);
+        if slot < MAX_TRACKED {
+            HELD[slot].store(ptr::null_mut(), Ordering::Release);
+        }
+    }
+
+    fn held_lock(&self) -> Option<HeldLock> {
+        let location = self.location.load(Ordering::Relaxed);
+        if location.is_null() {
+            return None;
+        }
+        Some(HeldLock {
+            address: self as *const Owner as usize,
+            thread: self.thread.load(Ordering::Relaxed),
+            cpu: self.cpu.load(Ordering::Relaxed),
+            cycles: cycles().saturating_sub(self.since.load(Ordering::Relaxed)),
+            location: unsafe { &*location },
+        })
+    }
+}
+
+impl HeldLock {
+    // Writes the lock to serial, after `prefix`.
+    pub fn report(&self, prefix: &str) {
+        serial_println!(
+            "{}lock {:#x} held by thread {} on CPU {} for {} cycles, taken at {}",
+            prefix,
+            self.address,
+            self.thread,
+            self.cpu,
+            self.cycles,
+            self.location
+        );
+    }
+}
+
+// Calls `f` for each spin lock held right now, without allocating, so interrupt
+// handlers may use it. The values are read without locking and may be inconsistent
+// for a lock that is being released meanwhile.
+pub fn held_locks(mut f: impl FnMut(HeldLock)) {
+    for held in HELD.iter() {
+        let owner = held.load(Ordering::Acquire);
+        if let Some(lock) = unsafe { owner.as_ref() }.and_then(Owner::held_lock) {
+            f(lock);
+        }
+    }
+}
+
+// The longest any spin lock was held since boot, in TSC cycles, and where it was taken.
+pub fn longest_hold() -> Option<(u64, &'static Location<'static>)> {
+    let location = LONGEST_HOLD_AT.load(Ordering::Relaxed);
+    unsafe { location.as_ref() }.map(|location| (LONGEST_HOLD.load(Ordering::Relaxed), location))
+}
+
+impl<T> SpinLock<T> {
+    pub const fn new(value: T) -> Self {
+        SpinLock { inner: spin::Mutex::new(value), owner: Owner::new(), disables_interrupts: true }
+    }
+
+    // A lock that leaves interrupts alone, for one that is held for long (e.g. the
+    // file system while the shell runs a command) and never taken by interrupt
+    // handlers. It's still tracked like any other.
+    pub const fn new_interruptible(value: T) -> Self {
+        SpinLock { inner: spin::Mutex::new(value), owner: Owner::new(), disables_interrupts: false }
+    }
+
+    // Disables interrupts if this lock does; whether they have to be enabled again
+    // on unlocking.
+    fn disable_interrupts(&self) -> bool {
+        if !self.disables_interrupts {
+            return false;
+        }
+        let enabled = interrupts::are_enabled();
+        interrupts::disable();
+        enabled
+    }
+
+    #[track_caller]
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        let interrupts_enabled = self.disable_interrupts();
+        let guard = match self.inner.try_lock() {
+            Some(guard) => guard,
+            None => self.spin(),
+        };
+        self.owner.acquired(Location::caller());
+        SpinLockGuard { guard: ManuallyDrop::new(guard), owner: &self.owner, interrupts_enabled }
+    }
+
+    // Waits for the holder to release the lock, reporting once if that takes too long.
+    #[track_caller]
+    fn spin(&self) -> spin::MutexGuard<'_, T> {
+        let start = cycles();
+        let mut reported = false;
+        loop {
+            if let Some(guard) = self.inner.try_lock() {
+                if reported {
+                    serial_println!("spinlock: CPU {} got the lock after all", cpu_index());
+                }
+                return guard;
+            }
+            if !reported && cycles() - start > SPIN_REPORT_CYCLES {
+                reported = true;
+                serial_println!(
+                    "spinlock: possible deadlock, thread {} on CPU {} is waiting at {}",
+                    percpu::try_current().map_or(0, |cpu| cpu.current_task()),
+                    cpu_index(),
+                    Location::caller()
+                );
+                if let Some(lock) = self.owner.held_lock() {
+                    lock.report("spinlock: ");
+                }
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        let interrupts_enabled = self.disable_interrupts();
+        match self.inner.try_lock() {
+            Some(guard) => {
+                self.owner.acquired(Location::caller());
+                Some(SpinLockGuard { guard: ManuallyDrop::new(guard), owner: &self.owner, interrupts_enabled })
+            }
+            None => {
+                if interrupts_enabled {
+                    interrupts::enable();
+                }
+                None
+            }
+        }
+    }
+}
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.owner.released();
+        // unlock before an interrupt can come in
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.interrupts_enabled {
+            interrupts::enable();
+        }
+    }
+}
+
+#[test_case]
+fn test_spinlock_disables_interrupts() {
+    let lock = SpinLock::new(0);
+    {
+        let mut guard = lock.lock();
+        *guard += 1;
+        assert!(!interrupts::are_enabled());
+        assert!(lock.try_lock().is_none());
+        // a failed try_lock leaves interrupts disabled for the holder
+        assert!(!interrupts::are_enabled());
+    }
+    assert!(interrupts::are_enabled());
+    assert_eq!(*lock.lock(), 1);
+}
+
+#[test_case]
+fn test_interruptible_spinlock_keeps_interrupts_enabled() {
+    let lock = SpinLock::new_interruptible(0);
+    {
+        let _guard = lock.lock();
+        assert!(interrupts::are_enabled());
+        assert!(lock.try_lock().is_none());
+    }
+    assert!(interrupts::are_enabled());
+}
+
+#[test_case]
+fn test_spinlock_tracks_its_holder() {
+    let lock = SpinLock::new(());
+    let address = &lock.owner as *const Owner as usize;
+    let is_listed = || {
+        let mut listed = false;
+        held_locks(|held| listed |= held.address == address);
+        listed
+    };
+    {
+        let _guard = lock.lock();
+        assert!(is_listed());
+    }
+    assert!(!is_listed());
+    assert!(longest_hold().is_some());
+}
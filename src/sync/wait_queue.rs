@@ -0,0 +1,116 @@
+use alloc::collections::VecDeque;
+use x86_64::instructions::interrupts;
+use super::SpinLock;
+use crate::thread::{self, ThreadId};
+use crate::time;
+use crate::timer::{self, Action};
+
+// Threads waiting for something, e.g. a lock to be released. Waiters check their
+// condition and go to sleep with interrupts disabled, and threads only run on one
+// CPU, so a wakeup can't get lost in between.
+pub struct WaitQueue {
+    waiters: SpinLock<VecDeque<ThreadId>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        WaitQueue { waiters: SpinLock::new(VecDeque::new()) }
+    }
+
+    // Without threads there's nobody to wake, `thread::block` just waits for an interrupt.
+    fn enqueue_current(&self) {
+        let id = thread::current();
+        if id != 0 {
+            self.waiters.lock().push_back(id);
+        }
+    }
+
+    // Blocks until `condition` returns true. It's checked before sleeping and after
+    // every wakeup, so it may also take what it waits for (e.g. a lock).
+    pub fn wait_until(&self, mut condition: impl FnMut() -> bool) {
+        interrupts::without_interrupts(|| {
+            while !condition() {
+                self.enqueue_current();
+                thread::block();
+            }
+        })
+    }
+
+    // Like `wait_until`, but gives up after `ms` milliseconds. Returns whether the
+    // condition was met.
+    pub fn wait_until_timeout(&self, ms: u64, mut condition: impl FnMut() -> bool) -> bool {
+        let deadline = time::ticks().saturating_add(time::ms_to_ticks(ms));
+        interrupts::without_interrupts(|| {
+            let timer = timer::add(deadline, Action::Wake(thread::current()));
+            let met = loop {
+                if condition() {
+                    break true;
+                }
+                if time::ticks() >= deadline {
+                    break false;
+                }
+                self.enqueue_current();
+                thread::block();
+                self.dequeue_current();
+            };
+            timer::cancel(timer);
+            met
+        })
+    }
+
+    // Like `wait_with`, but wakes up after `ms` milliseconds at the latest. Returns
+    // false if the wait timed out.
+    pub fn wait_with_timeout(&self, ms: u64, before_sleep: impl FnOnce()) -> bool {
+        let deadline = time::ticks().saturating_add(time::ms_to_ticks(ms));
+        interrupts::without_interrupts(|| {
+            self.enqueue_current();
+            let timer = timer::add(deadline, Action::Wake(thread::current()));
+            before_sleep();
+            thread::block();
+            self.dequeue_current();
+            // still pending unless it was the timer that woke the thread
+            timer::cancel(timer)
+        })
+    }
+
+    // Drops the running thread's entry if the thread was woken some other way, e.g.
+    // by a timer, so that a later `wake_one` doesn't go to it instead of a waiter.
+    fn dequeue_current(&self) {
+        let id = thread::current();
+        self.waiters.lock().retain(|&waiter| waiter != id);
+    }
+
+    // Queues the running thread, calls `before_sleep` and blocks until woken. Nothing
+    // done by `before_sleep` (e.g. releasing a lock) can wake the thread before it's
+    // asleep. May return spuriously, so callers check their condition again.
+    pub fn wait_with(&self, before_sleep: impl FnOnce()) {
+        interrupts::without_interrupts(|| {
+            self.enqueue_current();
+            before_sleep();
+            thread::block();
+        })
+    }
+
+    // Wakes the longest waiting thread; false if none was waiting.
+    pub fn wake_one(&self) -> bool {
+        loop {
+            let Some(id) = self.waiters.lock().pop_front() else { return false };
+            // skips threads that were woken some other way meanwhile
+            if thread::wake(id) {
+                return true;
+            }
+        }
+    }
+
+    // Wakes every waiting thread and returns how many there were.
+    pub fn wake_all(&self) -> usize {
+        let waiters = core::mem::take(&mut *self.waiters.lock());
+        waiters.into_iter().filter(|&id| thread::wake(id)).count()
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,449 @@
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::arch::global_asm;
+use core::{slice, str};
+use spin::Mutex;
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+use x86_64::VirtAddr;
+use crate::exec::ExecError;
+use crate::fs::FILE_SYSTEM;
+use crate::ipc::message_queue::{self, MAX_MESSAGE_SIZE};
+use crate::process::{self, Descriptor, KillError, OpenFile, WaitError};
+use crate::signal::{Action, Signal, UserContext};
+use crate::usermode::{self, UserExit};
+use crate::{gdt, ipc, keyboard, memory, percpu, print, thread};
+
+// System call numbers, passed in rax. Arguments go in rdi, rsi, rdx, r10, r8 and r9,
+// the result comes back in rax: a value >= 0 on success, a negated `Errno` on failure.
+pub const SYS_READ: u64 = 0;
+pub const SYS_WRITE: u64 = 1;
+pub const SYS_OPEN: u64 = 2;
+pub const SYS_CLOSE: u64 = 3;
+pub const SYS_EXIT: u64 = 4;
+pub const SYS_SBRK: u64 = 5;
+pub const SYS_GETPID: u64 = 6;
+pub const SYS_SLEEP: u64 = 7;
+pub const SYS_SPAWN: u64 = 8;
+pub const SYS_WAIT: u64 = 9;
+pub const SYS_KILL: u64 = 10;
+pub const SYS_PIPE: u64 = 11;
+pub const SYS_MQ_OPEN: u64 = 12;
+pub const SYS_SIGACTION: u64 = 13;
+
+// flags of SYS_OPEN
+pub const OPEN_CREATE: u64 = 1 << 0;
+pub const OPEN_TRUNCATE: u64 = 1 << 1;
+pub const OPEN_APPEND: u64 = 1 << 2;
+
+// actions of SYS_SIGACTION besides a handler's address
+pub const SIG_DEFAULT: u64 = 0;
+pub const SIG_IGNORE: u64 = 1;
+
+// standard file descriptors, connected to the console unless redirected
+pub const STDIN: u64 = 0;
+pub const STDOUT: u64 = 1;
+pub const STDERR: u64 = 2;
+
+const MAX_PATH_LEN: u64 = 256;
+// the arguments of SYS_SPAWN, separated by whitespace
+const MAX_ARGS_LEN: u64 = 1024;
+// console output between checks for signals
+const CONSOLE_CHUNK: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i64)]
+pub enum Errno {
+    NoEntry = 2,
+    NoSuchProcess = 3,
+    Interrupted = 4,
+    ArgumentListTooLong = 7,
+    ExecFormat = 8,
+    BadFileDescriptor = 9,
+    NoChild = 10,
+    NoMemory = 12,
+    BadAddress = 14,
+    InvalidArgument = 22,
+    TooManyFiles = 24,
+    BrokenPipe = 32,
+    NoSys = 38,
+    MessageTooLong = 90,
+}
+
+type SyscallResult = Result<u64, Errno>;
+
+// Registers of the calling program, as saved by `syscall_entry`.
+#[repr(C)]
+#[derive(Debug)]
+pub struct SyscallFrame {
+    pub rax: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub r10: u64,
+    pub r8: u64,
+    pub r9: u64,
+    // return address and flags, saved by `syscall` in rcx and r11
+    pub rip: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+}
+
+// Entry point of the syscall instruction. Switches to the kernel GS base and to the
+// kernel stack of the TSS (the same one interrupts from user mode use), saves the
+// registers the program expects to survive and calls `syscall_dispatch`.
+global_asm!(
+    ".global syscall_entry",
+    "syscall_entry:",
+    "    swapgs",
+    "    mov gs:[{user_rsp}], rsp",
+    "    mov rsp, gs:[{tss}]",
+    // privilege_stack_table[0] follows the TSS's first reserved u32
+    "    mov rsp, [rsp + 4]",
+    "    push qword ptr gs:[{user_rsp}]",
+    "    push r11",
+    "    push rcx",
+    "    push r9",
+    "    push r8",
+    "    push r10",
+    "    push rdx",
+    "    push rsi",
+    "    push rdi",
+    "    push rax",
+    "    mov rdi, rsp",
+    "    push rbp",
+    "    mov rbp, rsp",
+    "    and rsp, -16",
+    "    call {dispatch}",
+    "    mov rsp, rbp",
+    "    pop rbp",
+    "    pop rax",
+    "    pop rdi",
+    "    pop rsi",
+    "    pop rdx",
+    "    pop r10",
+    "    pop r8",
+    "    pop r9",
+    "    pop rcx",
+    "    pop r11",
+    "    pop rsp",
+    "    swapgs",
+    "    sysretq",
+    user_rsp = const percpu::SYSCALL_USER_RSP_OFFSET,
+    tss = const percpu::TSS_OFFSET,
+    dispatch = sym syscall_dispatch,
+);
+
+extern "C" {
+    fn syscall_entry();
+}
+
+// Enables the syscall instruction on the calling CPU.
+pub fn init() {
+    let selectors = gdt::selectors();
+    Star::write(selectors.user_code, selectors.user_data, selectors.kernel_code, selectors.kernel_data)
+        .expect("GDT layout doesn't fit sysret");
+    LStar::write(VirtAddr::new(syscall_entry as *const () as u64));
+    // entered with interrupts off, the dispatcher enables them once on the kernel stack
+    SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::TRAP_FLAG | RFlags::DIRECTION_FLAG | RFlags::ALIGNMENT_CHECK);
+    unsafe { Efer::update(|flags| *flags |= EferFlags::SYSTEM_CALL_EXTENSIONS) };
+}
+
+extern "C" fn syscall_dispatch(frame: &mut SyscallFrame) {
+    x86_64::instructions::interrupts::enable();
+    let (a0, a1, a2, a3) = (frame.rdi, frame.rsi, frame.rdx, frame.r10);
+    let result = match frame.rax {
+        SYS_READ => sys_read(a0, a1, a2),
+        SYS_WRITE => sys_write(a0, a1, a2),
+        SYS_OPEN => sys_open(a0, a1, a2),
+        SYS_CLOSE => sys_close(a0),
+        SYS_EXIT => usermode::exit(UserExit::Exited(a0 as i32)),
+        SYS_SBRK => process::sbrk(a0 as i64).ok_or(Errno::NoMemory),
+        SYS_GETPID => process::current().ok_or(Errno::NoSuchProcess),
+        SYS_SLEEP => sys_sleep(a0),
+        SYS_SPAWN => sys_spawn(a0, a1, a2, a3),
+        SYS_WAIT => sys_wait(a0, a1),
+        SYS_KILL => sys_kill(a0, a1),
+        SYS_PIPE => sys_pipe(a0),
+        SYS_MQ_OPEN => sys_mq_open(a0, a1),
+        SYS_SIGACTION => sys_sigaction(a0, a1),
+        _ => Err(Errno::NoSys),
+    };
+    frame.rax = match result {
+        Ok(value) => value,
+        Err(errno) => (-(errno as i64)) as u64,
+    };
+    let mut context = UserContext { rip: frame.rip, rsp: frame.rsp, rflags: frame.rflags };
+    process::deliver_signals(&mut context);
+    (frame.rip, frame.rsp, frame.rflags) = (context.rip, context.rsp, context.rflags);
+    x86_64::instructions::interrupts::disable();
+    // sysret with a non-canonical rip would fault in ring 0
+    if VirtAddr::try_new(frame.rip).is_err() {
+        usermode::exit(UserExit::Fault(13));
+    }
+}
+
+// Validates a user buffer the kernel reads from.
+fn user_slice(ptr: u64, len: u64) -> Result<&'static [u8], Errno> {
+    if !memory::check_user_range(ptr, len, false) {
+        return Err(Errno::BadAddress);
+    }
+    Ok(unsafe { slice::from_raw_parts(ptr as *const u8, len as usize) })
+}
+
+// Validates a user buffer the kernel writes to.
+fn user_slice_mut(ptr: u64, len: u64) -> Result<&'static mut [u8], Errno> {
+    if !memory::check_user_range(ptr, len, true) {
+        return Err(Errno::BadAddress);
+    }
+    Ok(unsafe { slice::from_raw_parts_mut(ptr as *mut u8, len as usize) })
+}
+
+// console input that was read but not consumed yet
+static CONSOLE_INPUT: Mutex<VecDeque<u8>> = Mutex::new(VecDeque::new());
+
+// Reads one line from the keyboard, echoing it, once the previous one is used up.
+// A signal (e.g. Ctrl-C) ends the wait for the line.
+fn console_read(buffer: &mut [u8]) -> SyscallResult {
+    let mut input = CONSOLE_INPUT.lock();
+    if input.is_empty() {
+        let mut line = String::new();
+        if !keyboard::read_keyboard_unless(&mut line, process::signal_pending) {
+            return Err(Errno::Interrupted);
+        }
+        input.extend(line.bytes());
+    }
+    let count = buffer.len().min(input.len());
+    for (byte, input) in buffer.iter_mut().zip(input.drain(..count)) {
+        *byte = input;
+    }
+    Ok(count as u64)
+}
+
+// Prints `buffer` in pieces, stopping early once a signal comes in, so Ctrl-C cuts
+// long output short. Returns how much was printed.
+fn console_write(buffer: &[u8]) -> u64 {
+    let mut written = 0;
+    for chunk in buffer.chunks(CONSOLE_CHUNK) {
+        match str::from_utf8(chunk) {
+            Ok(text) => print!("{}", text),
+            Err(_) => print!("{}", String::from_utf8_lossy(chunk)),
+        }
+        written += chunk.len() as u64;
+        if process::signal_pending() {
+            break;
+        }
+    }
+    written
+}
+
+fn sys_read(fd: u64, ptr: u64, len: u64) -> SyscallResult {
+    let buffer = user_slice_mut(ptr, len)?;
+    let files = process::files().ok_or(Errno::BadFileDescriptor)?;
+    let mut files = files.lock();
+    let descriptor = match files.get_mut(fd)? {
+        Descriptor::File(file) => return read_file(file, buffer),
+        // the others may block, which they do without holding the table
+        descriptor => descriptor.clone(),
+    };
+    drop(files);
+    match descriptor {
+        Descriptor::Console => console_read(buffer),
+        Descriptor::PipeReader(reader) => {
+            let count = reader.read_unless(buffer, process::signal_pending).ok_or(Errno::Interrupted)?;
+            Ok(count as u64)
+        }
+        Descriptor::MessageQueue(queue) => {
+            if buffer.len() < MAX_MESSAGE_SIZE {
+                return Err(Errno::MessageTooLong);
+            }
+            let message = queue.receive_unless(process::signal_pending).ok_or(Errno::Interrupted)?;
+            buffer[..message.len()].copy_from_slice(&message);
+            Ok(message.len() as u64)
+        }
+        Descriptor::File(_) | Descriptor::PipeWriter(_) => Err(Errno::BadFileDescriptor),
+    }
+}
+
+fn read_file(file: &mut OpenFile, buffer: &mut [u8]) -> SyscallResult {
+    let file_system = FILE_SYSTEM.lock();
+    let data = &file_system.file(&file.path).ok_or(Errno::NoEntry)?.data;
+    let count = buffer.len().min(data.len().saturating_sub(file.offset));
+    buffer[..count].copy_from_slice(&data[file.offset..file.offset + count]);
+    file.offset += count;
+    Ok(count as u64)
+}
+
+fn sys_write(fd: u64, ptr: u64, len: u64) -> SyscallResult {
+    let buffer = user_slice(ptr, len)?;
+    let files = process::files().ok_or(Errno::BadFileDescriptor)?;
+    let mut files = files.lock();
+    let descriptor = match files.get_mut(fd)? {
+        Descriptor::File(file) => return write_file(file, buffer),
+        descriptor => descriptor.clone(),
+    };
+    drop(files);
+    match descriptor {
+        Descriptor::Console => Ok(console_write(buffer)),
+        Descriptor::PipeWriter(writer) => writer.write(buffer).map(|count| count as u64).map_err(|_| Errno::BrokenPipe),
+        Descriptor::MessageQueue(queue) => queue.send(buffer).map(|()| len).map_err(|_| Errno::MessageTooLong),
+        Descriptor::File(_) | Descriptor::PipeReader(_) => Err(Errno::BadFileDescriptor),
+    }
+}
+
+fn write_file(file: &mut OpenFile, buffer: &[u8]) -> SyscallResult {
+    let mut file_system = FILE_SYSTEM.lock();
+    let data = &mut file_system.file_mut(&file.path).ok_or(Errno::NoEntry)?.data;
+    if file.append {
+        file.offset = data.len();
+    }
+    let end = file.offset + buffer.len();
+    if data.len() < end {
+        data.resize(end, 0);
+    }
+    data[file.offset..end].copy_from_slice(buffer);
+    file.offset = end;
+    Ok(buffer.len() as u64)
+}
+
+// Validates a user string of at most `max_len` bytes.
+fn user_str(ptr: u64, len: u64, max_len: u64) -> Result<&'static str, Errno> {
+    if len > max_len {
+        return Err(Errno::InvalidArgument);
+    }
+    str::from_utf8(user_slice(ptr, len)?).map_err(|_| Errno::InvalidArgument)
+}
+
+fn sys_open(path_ptr: u64, path_len: u64, flags: u64) -> SyscallResult {
+    let path = user_str(path_ptr, path_len, MAX_PATH_LEN)?;
+    let mut file_system = FILE_SYSTEM.lock();
+    let file = if flags & OPEN_CREATE != 0 {
+        file_system.create_file_at(path)
+    } else {
+        file_system.file_mut(path)
+    };
+    let file = file.ok_or(Errno::NoEntry)?;
+    if flags & OPEN_TRUNCATE != 0 {
+        file.data.clear();
+    }
+    // remember the absolute path, the shell's current directory may change
+    let path = String::from("/") + &file_system.resolve(path).join("/");
+    drop(file_system);
+
+    let open_file = OpenFile { path, offset: 0, append: flags & OPEN_APPEND != 0 };
+    process::files().ok_or(Errno::BadFileDescriptor)?.lock().insert(Descriptor::File(open_file))
+}
+
+fn sys_close(fd: u64) -> SyscallResult {
+    process::files().ok_or(Errno::BadFileDescriptor)?.lock().remove(fd)?;
+    Ok(0)
+}
+
+fn sys_kill(pid: u64, signal: u64) -> SyscallResult {
+    let signal = Signal::from_number(signal).ok_or(Errno::InvalidArgument)?;
+    process::kill(pid, signal)?;
+    Ok(0)
+}
+
+// Sets what the calling process does on `signal`: SIG_DEFAULT, SIG_IGNORE or the
+// address of a handler entered as described at `signal::push_frame`.
+fn sys_sigaction(signal: u64, action: u64) -> SyscallResult {
+    let signal = Signal::from_number(signal).ok_or(Errno::InvalidArgument)?;
+    let action = match action {
+        SIG_DEFAULT => Action::Default,
+        SIG_IGNORE => Action::Ignore,
+        address => Action::Handler(VirtAddr::try_new(address).map_err(|_| Errno::InvalidArgument)?),
+    };
+    match process::set_signal_action(signal, action) {
+        Some(Ok(_)) => Ok(0),
+        Some(Err(_)) => Err(Errno::InvalidArgument),
+        None => Err(Errno::NoSuchProcess),
+    }
+}
+
+// Creates a pipe and stores its read and write descriptors, as two u32, at `fds_ptr`.
+fn sys_pipe(fds_ptr: u64) -> SyscallResult {
+    let fds = user_slice_mut(fds_ptr, 8)?;
+    let files = process::files().ok_or(Errno::BadFileDescriptor)?;
+    let mut files = files.lock();
+    let (reader, writer) = ipc::pipe();
+    let read_fd = files.insert(Descriptor::PipeReader(reader))?;
+    let write_fd = match files.insert(Descriptor::PipeWriter(writer)) {
+        Ok(fd) => fd,
+        Err(errno) => {
+            files.remove(read_fd)?;
+            return Err(errno);
+        }
+    };
+    fds[..4].copy_from_slice(&(read_fd as u32).to_le_bytes());
+    fds[4..].copy_from_slice(&(write_fd as u32).to_le_bytes());
+    Ok(0)
+}
+
+// Opens the message queue called `name`, creating it if needed. Each write on the
+// descriptor sends one message, each read receives one.
+fn sys_mq_open(name_ptr: u64, name_len: u64) -> SyscallResult {
+    let name = user_str(name_ptr, name_len, MAX_PATH_LEN)?;
+    let queue = message_queue::open(name);
+    process::files().ok_or(Errno::BadFileDescriptor)?.lock().insert(Descriptor::MessageQueue(queue))
+}
+
+fn sys_sleep(ms: u64) -> SyscallResult {
+    if !thread::sleep_unless(ms, process::signal_pending) {
+        return Err(Errno::Interrupted);
+    }
+    Ok(0)
+}
+
+// Starts the executable at `path` as a child process. Its arguments are the path
+// followed by the whitespace separated words of `args`.
+fn sys_spawn(path_ptr: u64, path_len: u64, args_ptr: u64, args_len: u64) -> SyscallResult {
+    let path = user_str(path_ptr, path_len, MAX_PATH_LEN)?;
+    let args = user_str(args_ptr, args_len, MAX_ARGS_LEN)?;
+    let image = FILE_SYSTEM.lock().file(path).ok_or(Errno::NoEntry)?.data.clone();
+    let args: Vec<&str> = core::iter::once(path).chain(args.split_whitespace()).collect();
+    process::spawn(&image, &args, &[]).map_err(Errno::from)
+}
+
+// Waits for child `pid` (any child if 0) to exit and stores its status (see
+// `UserExit::status`) at `status_ptr`, unless that is 0. Returns the child's pid.
+fn sys_wait(pid: u64, status_ptr: u64) -> SyscallResult {
+    let status = match status_ptr {
+        0 => None,
+        _ => Some(user_slice_mut(status_ptr, 4)?),
+    };
+    let (pid, exit) = process::wait(if pid == 0 { None } else { Some(pid) })?;
+    if let Some(status) = status {
+        status.copy_from_slice(&exit.status().to_le_bytes());
+    }
+    Ok(pid)
+}
+
+impl From<ExecError> for Errno {
+    fn from(err: ExecError) -> Self {
+        match err {
+            ExecError::NotFound => Errno::NoEntry,
+            ExecError::Elf(_) | ExecError::BadAddress => Errno::ExecFormat,
+            ExecError::OutOfMemory => Errno::NoMemory,
+            ExecError::ArgumentsTooLarge => Errno::ArgumentListTooLong,
+        }
+    }
+}
+
+impl From<WaitError> for Errno {
+    fn from(err: WaitError) -> Self {
+        match err {
+            WaitError::NoChild => Errno::NoChild,
+            WaitError::Interrupted => Errno::Interrupted,
+        }
+    }
+}
+
+impl From<KillError> for Errno {
+    fn from(err: KillError) -> Self {
+        match err {
+            KillError::NoSuchProcess => Errno::NoSuchProcess,
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use crate::time;
+use crate::timer::{self, Action, TimerId};
+
+// A future that completes once a point in time has passed, woken by a kernel timer
+// instead of being polled in between.
+pub struct Delay {
+    // tick to complete at
+    deadline: u64,
+    timer: Option<TimerId>,
+}
+
+impl Delay {
+    // Completes after at least `ms` milliseconds.
+    pub fn new(ms: u64) -> Self {
+        Delay::until(time::ticks().saturating_add(time::ms_to_ticks(ms)))
+    }
+
+    // Completes once the tick count reaches `deadline`.
+    pub fn until(deadline: u64) -> Self {
+        Delay { deadline, timer: None }
+    }
+
+    fn cancel_timer(&mut self) {
+        if let Some(timer) = self.timer.take() {
+            timer::cancel(timer);
+        }
+    }
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if time::ticks() >= self.deadline {
+            self.cancel_timer();
+            return Poll::Ready(());
+        }
+        // the task may be polled with another waker than last time
+        self.cancel_timer();
+        let deadline = self.deadline;
+        self.timer = Some(timer::add(deadline, Action::Waker(cx.waker().clone())));
+        Poll::Pending
+    }
+}
+
+impl Drop for Delay {
+    fn drop(&mut self) {
+        self.cancel_timer();
+    }
+}
+
+// Waits at least `ms` milliseconds without blocking the thread, like `thread::sleep`
+// for tasks.
+pub async fn sleep(ms: u64) {
+    Delay::new(ms).await
+}
@@ -0,0 +1,131 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use crossbeam_queue::ArrayQueue;
+use x86_64::instructions::interrupts;
+use super::{Task, TaskId};
+
+// upper bound of tasks that can be woken and not polled yet
+const QUEUE_SIZE: usize = 100;
+
+// Runs tasks whenever their waker says they can make progress. Wakers only push the
+// task's id into a lock-free queue, so they may be called from interrupt handlers.
+pub struct Executor {
+    tasks: BTreeMap<TaskId, Task>,
+    task_queue: Arc<ArrayQueue<TaskId>>,
+    waker_cache: BTreeMap<TaskId, Arc<TaskWaker>>,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Executor {
+            tasks: BTreeMap::new(),
+            task_queue: Arc::new(ArrayQueue::new(QUEUE_SIZE)),
+            waker_cache: BTreeMap::new(),
+        }
+    }
+
+    // Adds the task, which gets polled for the first time on the next run.
+    pub fn spawn(&mut self, task: Task) {
+        let task_id = task.id;
+        if self.tasks.insert(task_id, task).is_some() {
+            panic!("task with same ID already in tasks");
+        }
+        self.task_queue.push(task_id).expect("task queue full");
+    }
+
+    // Number of tasks that haven't completed yet.
+    pub fn task_count(&self) -> usize {
+        self.tasks.len()
+    }
+
+    fn run_ready_tasks(&mut self) {
+        // destructure `self` to borrow the fields separately in the loop
+        let Self { tasks, task_queue, waker_cache } = self;
+
+        while let Some(task_id) = task_queue.pop() {
+            let task = match tasks.get_mut(&task_id) {
+                Some(task) => task,
+                None => continue, // task no longer exists
+            };
+            let task_waker = waker_cache.entry(task_id).or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
+            // wakes from now on queue the task again
+            task_waker.queued.store(false, Ordering::Release);
+            let waker = Waker::from(task_waker.clone());
+            let mut context = Context::from_waker(&waker);
+            match task.poll(&mut context) {
+                Poll::Ready(()) => {
+                    // task done -> remove it and its cached waker
+                    tasks.remove(&task_id);
+                    waker_cache.remove(&task_id);
+                }
+                Poll::Pending => {}
+            }
+        }
+    }
+
+    // Polls tasks until none is ready anymore; waiting ones stay spawned.
+    pub fn run_until_idle(&mut self) {
+        self.run_ready_tasks();
+    }
+
+    // Runs the tasks forever, halting the CPU whenever all of them wait.
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.run_ready_tasks();
+            self.sleep_if_idle();
+        }
+    }
+
+    fn sleep_if_idle(&self) {
+        // an interrupt between the check and `hlt` could wake a task we'd then sleep
+        // through, so check with interrupts disabled and enable them atomically with hlt
+        interrupts::disable();
+        if self.task_queue.is_empty() {
+            interrupts::enable_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct TaskWaker {
+    task_id: TaskId,
+    task_queue: Arc<ArrayQueue<TaskId>>,
+    // whether the task is in the queue already, waking it again changes nothing
+    queued: AtomicBool,
+}
+
+impl TaskWaker {
+    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Arc<Self> {
+        Arc::new(TaskWaker { task_id, task_queue, queued: AtomicBool::new(false) })
+    }
+
+    // May run in an interrupt handler, so it mustn't panic. Every task is queued once
+    // at most, the queue only fills up with more than QUEUE_SIZE tasks woken; the
+    // wake is dropped then.
+    fn wake_task(&self) {
+        if !self.queued.swap(true, Ordering::AcqRel) && self.task_queue.push(self.task_id).is_err() {
+            self.queued.store(false, Ordering::Release);
+        }
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
+
@@ -0,0 +1,65 @@
+use alloc::string::String;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures_util::stream::{Stream, StreamExt};
+use futures_util::task::AtomicWaker;
+use crate::keyboard;
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+// Called by `keyboard::add_to_buffer` when a key comes in.
+pub(crate) fn wake() {
+    WAKER.wake();
+}
+
+// The characters typed on the keyboard. Reads the same input buffer as
+// `keyboard::read_keyboard`, so there should be one reader at a time; only the most
+// recently polled stream gets woken.
+pub struct KeyStream {
+    _private: (),
+}
+
+impl KeyStream {
+    pub fn new() -> Self {
+        KeyStream { _private: () }
+    }
+}
+
+impl Default for KeyStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for KeyStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        // fast path, without registering a waker
+        if let Some(character) = keyboard::fetch_from_buffer() {
+            return Poll::Ready(Some(character));
+        }
+        WAKER.register(cx.waker());
+        // a key might have arrived after the first check but before registering
+        match keyboard::fetch_from_buffer() {
+            Some(character) => {
+                WAKER.take();
+                Poll::Ready(Some(character))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+// Reads and echoes a line like `keyboard::read_keyboard`, but waits for keys without
+// blocking the CPU, so other tasks keep running.
+pub async fn read_line() -> String {
+    let mut keys = KeyStream::new();
+    let mut line = String::new();
+    while let Some(character) = keys.next().await {
+        if keyboard::edit_line(&mut line, character) {
+            break;
+        }
+    }
+    line
+}
@@ -0,0 +1,437 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::idt::InterruptStackFrame;
+use crate::irq::{self, IrqReturn};
+use crate::sync::SpinLock;
+use crate::fpu::{self, FpuState};
+use crate::timer::{self, Action};
+use crate::{gdt, memory, percpu, time};
+
+mod context;
+mod scheduler;
+
+pub use scheduler::{Priority, State};
+use scheduler::{Scheduler, Thread};
+
+pub type ThreadId = u64;
+
+// kernel stack size of spawned threads
+const STACK_PAGES: u64 = 16;
+// timer ticks a thread runs before the next ready one gets its turn
+const TIME_SLICE_TICKS: u64 = 2;
+
+// Threads only run on the bootstrap processor, the only one the PIC delivers the
+// timer interrupt to; the application processors stay idle.
+static SCHEDULER: SpinLock<Option<Scheduler>> = SpinLock::new(None);
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+// set by the timer when the running thread's time slice is used up, or when a thread
+// of higher priority became ready
+static NEED_RESCHED: AtomicBool = AtomicBool::new(false);
+static SLICE_TICKS: AtomicU64 = AtomicU64::new(0);
+// thread whose FPU state is in the CPU's registers, 0 for none
+static FPU_OWNER: AtomicU64 = AtomicU64::new(0);
+
+// What `ps` shows about a thread.
+pub struct ThreadInfo {
+    pub id: ThreadId,
+    pub name: String,
+    pub state: State,
+    pub priority: Priority,
+    // CPU time, in timer ticks
+    pub ticks: u64,
+    pub switches: u64,
+}
+
+fn with_scheduler<R>(f: impl FnOnce(&mut Scheduler) -> R) -> R {
+    interrupts::without_interrupts(|| f(SCHEDULER.lock().as_mut().expect("threads not initialized")))
+}
+
+fn is_initialized() -> bool {
+    interrupts::without_interrupts(|| SCHEDULER.lock().is_some())
+}
+
+// Turns the running code into the "main" thread, creates the idle thread and starts
+// preempting on the timer interrupt. Needs the heap and `memory::install`.
+pub fn init() {
+    let cpu = percpu::current();
+    let main = Thread {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        name: String::from("main"),
+        state: State::Running,
+        priority: Priority::Normal,
+        rsp: 0,
+        stack_top: None,
+        level_4_frame: Cr3::read().0,
+        kernel_stack: unsafe { (*cpu.tss()).privilege_stack_table[0] },
+        user_return_rsp: 0,
+        fpu: Box::new(FpuState::new()),
+        ticks: 0,
+        last_switch: time::ticks(),
+        ready_since: time::ticks(),
+        switches: 1,
+    };
+    cpu.set_current_task(main.id);
+    interrupts::without_interrupts(|| *SCHEDULER.lock() = Some(Scheduler::new(main)));
+
+    // never queued, its priority doesn't matter
+    let idle = create("idle", Priority::Low, Box::new(idle_loop));
+    with_scheduler(|scheduler| scheduler.idle = idle);
+    irq::register(time::TIMER_IRQ, "scheduler", &timer_tick).expect("scheduler IRQ registration failed");
+}
+
+fn idle_loop() {
+    loop {
+        interrupts::enable_and_hlt();
+    }
+}
+
+// Sets up a thread running `f`, not queued yet.
+fn create(name: &str, priority: Priority, f: Box<dyn FnOnce() + Send>) -> ThreadId {
+    let stack_top = match with_scheduler(|scheduler| scheduler.free_stacks.pop()) {
+        Some(stack_top) => stack_top,
+        None => memory::alloc_stack(STACK_PAGES).expect("out of memory for a thread stack"),
+    };
+    // the trampoline hands this to `thread_start`
+    let closure = Box::into_raw(Box::new(f));
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let rsp = unsafe { context::prepare_stack(stack_top, closure as u64) };
+    with_scheduler(|scheduler| {
+        let thread = Thread {
+            id,
+            name: String::from(name),
+            state: State::Ready,
+            priority,
+            rsp,
+            stack_top: Some(stack_top),
+            level_4_frame: scheduler.kernel_level_4_frame,
+            kernel_stack: stack_top,
+            user_return_rsp: 0,
+            fpu: Box::new(FpuState::new()),
+            ticks: 0,
+            last_switch: time::ticks(),
+            ready_since: time::ticks(),
+            switches: 0,
+        };
+        scheduler.threads.insert(id, Box::new(thread));
+    });
+    id
+}
+
+// Starts a kernel thread running `f` at normal priority. It ends when `f` returns or
+// calls `exit`.
+pub fn spawn<F>(name: &str, f: F) -> ThreadId
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_with_priority(name, Priority::Normal, f)
+}
+
+pub fn spawn_with_priority<F>(name: &str, priority: Priority, f: F) -> ThreadId
+where
+    F: FnOnce() + Send + 'static,
+{
+    let id = create(name, priority, Box::new(f));
+    with_scheduler(|scheduler| {
+        scheduler.make_ready(id);
+        if scheduler.outranks_current(id) {
+            NEED_RESCHED.store(true, Ordering::Relaxed);
+        }
+    });
+    id
+}
+
+// Changes the priority of thread `id`; false if there's no such thread.
+pub fn set_priority(id: ThreadId, priority: Priority) -> bool {
+    with_scheduler(|scheduler| {
+        if !scheduler.threads.contains_key(&id) {
+            return false;
+        }
+        scheduler.set_priority(id, priority);
+        true
+    })
+}
+
+// First code of every new thread, called by `context::thread_trampoline`.
+extern "C" fn thread_start(closure: u64) -> ! {
+    let closure = unsafe { Box::from_raw(closure as *mut Box<dyn FnOnce() + Send>) };
+    // switched to from `schedule`, which runs with interrupts disabled
+    interrupts::enable();
+    closure();
+    exit()
+}
+
+// Id of the thread running on this CPU.
+pub fn current() -> ThreadId {
+    percpu::current().current_task()
+}
+
+// Switches to the next thread to run, if that isn't the running one. Has to be
+// called with interrupts disabled. The running thread is queued again if it's still
+// in the Running state, otherwise it waits for whatever its state says.
+fn schedule() {
+    let cpu = percpu::current();
+    let mut guard = SCHEDULER.lock();
+    let scheduler = guard.as_mut().expect("threads not initialized");
+    scheduler.reap();
+    let current = scheduler.current;
+    if scheduler.thread(current).state == State::Running {
+        scheduler.make_ready(current);
+    }
+    let next = scheduler.pick_next();
+    if next == current {
+        scheduler.thread(current).state = State::Running;
+        return;
+    }
+
+    let (cr3, cr3_flags) = Cr3::read();
+    let now = time::ticks();
+    let old = scheduler.thread(current);
+    old.last_switch = now;
+    old.level_4_frame = cr3;
+    old.kernel_stack = unsafe { (*cpu.tss()).privilege_stack_table[0] };
+    old.user_return_rsp = unsafe { *cpu.user_return_rsp() };
+    let old_rsp = &raw mut old.rsp;
+
+    let new = scheduler.thread(next);
+    new.state = State::Running;
+    new.switches += 1;
+    new.last_switch = now;
+    if new.level_4_frame != cr3 {
+        unsafe { Cr3::write(new.level_4_frame, cr3_flags) };
+    }
+    gdt::set_kernel_stack(new.kernel_stack);
+    unsafe { *cpu.user_return_rsp() = new.user_return_rsp };
+    let new_rsp = new.rsp;
+    // the registers stay as they are until `fpu_trap` finds another thread using them
+    fpu::set_trap(FPU_OWNER.load(Ordering::Relaxed) != next);
+
+    scheduler.current = next;
+    cpu.set_current_task(next);
+    SLICE_TICKS.store(0, Ordering::Relaxed);
+    // the thread table is boxed, `old_rsp` stays valid after unlocking
+    drop(guard);
+    unsafe { context::switch(old_rsp, new_rsp) };
+}
+
+// Gives the CPU to the next ready thread, if there is one.
+pub fn yield_now() {
+    if is_initialized() {
+        interrupts::without_interrupts(schedule);
+    }
+}
+
+// Blocks the running thread for at least `ms` milliseconds.
+pub fn sleep(ms: u64) {
+    sleep_unless(ms, || false);
+}
+
+// Like `sleep`, but wakes up early as soon as `interrupted` returns true, which is
+// checked whenever the thread is woken (e.g. by a signal). Returns whether it slept
+// the whole time.
+pub fn sleep_unless(ms: u64, mut interrupted: impl FnMut() -> bool) -> bool {
+    let until = time::ticks().saturating_add(time::ms_to_ticks(ms));
+    if ms == 0 {
+        yield_now();
+        return true;
+    }
+    if !is_initialized() {
+        while time::ticks() < until {
+            x86_64::instructions::hlt();
+        }
+        return true;
+    }
+    interrupts::without_interrupts(|| loop {
+        if time::ticks() >= until {
+            return true;
+        }
+        if interrupted() {
+            return false;
+        }
+        let current = with_scheduler(|scheduler| {
+            let current = scheduler.current;
+            scheduler.thread(current).state = State::Sleeping(until);
+            current
+        });
+        let timer = timer::add(until, Action::Wake(current));
+        schedule();
+        // woken early, the timer mustn't wake whatever the thread waits for next
+        timer::cancel(timer);
+    })
+}
+
+// Waits until thread `id` has finished. Returns right away for unknown threads,
+// including ones that finished a while ago.
+pub fn join(id: ThreadId) {
+    assert_ne!(id, current(), "a thread can't join itself");
+    interrupts::without_interrupts(|| loop {
+        let finished = with_scheduler(|scheduler| {
+            match scheduler.threads.get(&id).map(|thread| thread.state) {
+                None | Some(State::Finished) => true,
+                Some(_) => {
+                    let current = scheduler.current;
+                    scheduler.thread(current).state = State::Joining(id);
+                    false
+                }
+            }
+        });
+        if finished {
+            return;
+        }
+        schedule();
+    })
+}
+
+// Puts the running thread to sleep until `wake` is called for it. Has to be called
+// with interrupts disabled, after the thread was made findable for its waker (e.g.
+// queued on a `sync::WaitQueue`). Without threads it waits for the next interrupt.
+pub fn block() {
+    if !is_initialized() {
+        interrupts::enable_and_hlt();
+        interrupts::disable();
+        return;
+    }
+    with_scheduler(|scheduler| {
+        let current = scheduler.current;
+        scheduler.thread(current).state = State::Blocked;
+    });
+    schedule();
+}
+
+// Makes a blocked thread ready again; false if `id` isn't blocked. May be called
+// from interrupt handlers.
+pub fn wake(id: ThreadId) -> bool {
+    wake_if(id, |state| state == State::Blocked)
+}
+
+// For timers and signals: makes a sleeping thread, or a blocked one whose wait timed
+// out or got interrupted, ready again.
+pub(crate) fn wake_timed_out(id: ThreadId) -> bool {
+    wake_if(id, |state| matches!(state, State::Blocked | State::Sleeping(_)))
+}
+
+fn wake_if(id: ThreadId, condition: impl FnOnce(State) -> bool) -> bool {
+    if !is_initialized() {
+        return false;
+    }
+    with_scheduler(|scheduler| {
+        match scheduler.threads.get(&id).map(|thread| thread.state) {
+            Some(state) if condition(state) => {
+                scheduler.make_ready(id);
+                if scheduler.outranks_current(id) {
+                    NEED_RESCHED.store(true, Ordering::Relaxed);
+                }
+                true
+            }
+            _ => false,
+        }
+    })
+}
+
+// Ends the running thread.
+pub fn exit() -> ! {
+    interrupts::disable();
+    with_scheduler(|scheduler| {
+        let current = scheduler.current;
+        scheduler.thread(current).state = State::Finished;
+        scheduler.wake_joiners(current);
+    });
+    let _ = FPU_OWNER.compare_exchange(current(), 0, Ordering::Relaxed, Ordering::Relaxed);
+    schedule();
+    unreachable!("finished thread was scheduled again");
+}
+
+// Called by the #NM handler when the running thread uses the FPU while the registers
+// may belong to another thread: saves them for their owner and loads the running
+// thread's.
+pub fn fpu_trap() {
+    fpu::set_trap(false);
+    if !is_initialized() {
+        return;
+    }
+    with_scheduler(|scheduler| {
+        let current = scheduler.current;
+        let owner = FPU_OWNER.swap(current, Ordering::Relaxed);
+        if owner == current {
+            return;
+        }
+        if let Some(previous) = scheduler.threads.get_mut(&owner) {
+            unsafe { previous.fpu.save() };
+        }
+        unsafe { scheduler.thread(current).fpu.restore() };
+    });
+}
+
+// Gives the running thread a fresh FPU state, so that a user program doesn't see
+// what the previous one left in the registers.
+pub fn reset_fpu() {
+    if !is_initialized() {
+        return;
+    }
+    with_scheduler(|scheduler| {
+        let current = scheduler.current;
+        *scheduler.thread(current).fpu = FpuState::new();
+        if FPU_OWNER.compare_exchange(current, 0, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+            fpu::set_trap(true);
+        }
+    });
+}
+
+// Snapshot of all threads, ordered by id.
+pub fn threads() -> Vec<ThreadInfo> {
+    with_scheduler(|scheduler| {
+        scheduler
+            .threads
+            .values()
+            .map(|thread| ThreadInfo {
+                id: thread.id,
+                name: thread.name.clone(),
+                state: thread.state,
+                priority: thread.priority,
+                ticks: thread.ticks,
+                switches: thread.switches,
+            })
+            .collect()
+    })
+}
+
+// Calls `f` with the id, name, state, last switch tick and the tick it last became
+// ready at (see `Thread`) of every thread but the idle one, without allocating, so
+// interrupt handlers may use it.
+pub fn for_each_thread(mut f: impl FnMut(ThreadId, &str, State, u64, u64)) {
+    if !is_initialized() {
+        return;
+    }
+    with_scheduler(|scheduler| {
+        for thread in scheduler.threads.values().filter(|thread| thread.id != scheduler.idle) {
+            f(thread.id, &thread.name, thread.state, thread.last_switch, thread.ready_since);
+        }
+    })
+}
+
+// Charges the tick to the running thread and asks for a switch once the time slice
+// is up. Sleepers were woken by their timers already.
+fn timer_tick(_stack_frame: &InterruptStackFrame) -> IrqReturn {
+    if let Some(scheduler) = SCHEDULER.lock().as_mut() {
+        let current = scheduler.current;
+        scheduler.thread(current).ticks += 1;
+        let slice = SLICE_TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+        if slice >= TIME_SLICE_TICKS {
+            NEED_RESCHED.store(true, Ordering::Relaxed);
+        }
+    }
+    IrqReturn::Handled
+}
+
+// Called by the IRQ dispatcher once an interrupt is acknowledged, still with
+// interrupts disabled. Switches threads if the timer asked for it and this isn't a
+// nested interrupt; the interrupted thread resumes in its handler later on.
+pub fn irq_exit() {
+    if percpu::current().interrupt_depth() == 0 && NEED_RESCHED.swap(false, Ordering::Relaxed) {
+        schedule();
+    }
+}
+
@@ -0,0 +1,64 @@
+use core::arch::global_asm;
+use x86_64::VirtAddr;
+
+// `switch_context(old_rsp, new_rsp)` pushes the callee-saved registers, stores the
+// stack pointer in `*old_rsp`, switches to `new_rsp` and pops the registers saved
+// there, returning into the thread that saved them. Everything else was already
+// saved by the caller, as with any function call.
+global_asm!(
+    ".global switch_context",
+    "switch_context:",
+    "    push rbp",
+    "    push rbx",
+    "    push r12",
+    "    push r13",
+    "    push r14",
+    "    push r15",
+    "    mov [rdi], rsp",
+    "    mov rsp, rsi",
+    "    pop r15",
+    "    pop r14",
+    "    pop r13",
+    "    pop r12",
+    "    pop rbx",
+    "    pop rbp",
+    "    ret",
+    // first return of a new thread, with its start argument in r12
+    ".global thread_trampoline",
+    "thread_trampoline:",
+    "    mov rdi, r12",
+    "    call {thread_start}",
+    "    ud2",
+    thread_start = sym super::thread_start,
+);
+
+extern "C" {
+    fn switch_context(old_rsp: *mut u64, new_rsp: u64);
+    fn thread_trampoline();
+}
+
+// Saves the running thread's registers and stack pointer to `*old_rsp` and resumes
+// the thread whose stack pointer is `new_rsp`. Returns once something switches back.
+pub unsafe fn switch(old_rsp: *mut u64, new_rsp: u64) {
+    switch_context(old_rsp, new_rsp);
+}
+
+// Lays out a new thread's stack as `switch_context` left it, so that switching to
+// it "returns" into `thread_trampoline`, which calls `thread_start(argument)`.
+// Returns the stack pointer to switch to.
+pub unsafe fn prepare_stack(stack_top: VirtAddr, argument: u64) -> u64 {
+    // 16 byte aligned again once the trampoline's call has pushed a return address
+    let top = stack_top.align_down(16u64).as_mut_ptr::<u64>();
+    let frame: [u64; 7] = [
+        0,        // r15
+        0,        // r14
+        0,        // r13
+        argument, // r12
+        0,        // rbx
+        0,        // rbp, ends the frame pointer chain for backtraces
+        thread_trampoline as *const () as u64,
+    ];
+    let rsp = top.sub(frame.len());
+    rsp.copy_from_nonoverlapping(frame.as_ptr(), frame.len());
+    rsp as u64
+}
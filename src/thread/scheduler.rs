@@ -0,0 +1,180 @@
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::vec::Vec;
+use x86_64::structures::paging::PhysFrame;
+use x86_64::VirtAddr;
+use crate::fpu::FpuState;
+use crate::time;
+use super::ThreadId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Running,
+    Ready,
+    // until the given tick, when a timer wakes it
+    Sleeping(u64),
+    // waiting for the given thread to finish
+    Joining(ThreadId),
+    // waiting on a wait queue, see `sync`
+    Blocked,
+    // waiting to be reaped; its stack may still be in use until the next switch
+    Finished,
+}
+
+impl State {
+    pub fn name(&self) -> &'static str {
+        match self {
+            State::Running => "running",
+            State::Ready => "ready",
+            State::Sleeping(_) => "sleeping",
+            State::Joining(_) => "joining",
+            State::Blocked => "blocked",
+            State::Finished => "finished",
+        }
+    }
+}
+
+// Ready threads of a higher priority always run first; within a priority they take
+// turns. Lower priorities only get the CPU when no higher one is ready.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low = 0,
+    Normal = 1,
+    High = 2,
+}
+
+pub const PRIORITY_LEVELS: usize = 3;
+
+impl Priority {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+        }
+    }
+}
+
+pub struct Thread {
+    pub id: ThreadId,
+    pub name: String,
+    pub state: State,
+    pub priority: Priority,
+    // saved stack pointer while not running
+    pub rsp: u64,
+    // None for the boot thread, which runs on the bootloader's stack
+    pub stack_top: Option<VirtAddr>,
+    // per thread CPU state beyond the registers: the address space, the TSS kernel
+    // stack and where the thread's user program returns to
+    pub level_4_frame: PhysFrame,
+    pub kernel_stack: VirtAddr,
+    pub user_return_rsp: u64,
+    // saved x87/SSE registers, only current while another thread owns the FPU
+    pub fpu: Box<FpuState>,
+    // timer ticks spent running
+    pub ticks: u64,
+    // tick the thread was last switched to or from, or was created at
+    pub last_switch: u64,
+    // tick the thread last became ready at, after being created, preempted, woken up
+    pub ready_since: u64,
+    // how often the thread was switched to
+    pub switches: u64,
+}
+
+// The thread table and a round robin queue of ready threads per priority. The idle
+// thread is never queued, it runs when nothing else is ready.
+pub struct Scheduler {
+    pub threads: BTreeMap<ThreadId, Box<Thread>>,
+    ready: [VecDeque<ThreadId>; PRIORITY_LEVELS],
+    pub current: ThreadId,
+    pub idle: ThreadId,
+    // stacks of reaped threads, reused by new ones
+    pub free_stacks: Vec<VirtAddr>,
+    // the boot page table, which new threads start out with
+    pub kernel_level_4_frame: PhysFrame,
+}
+
+impl Scheduler {
+    pub fn new(boot_thread: Thread) -> Self {
+        let current = boot_thread.id;
+        let kernel_level_4_frame = boot_thread.level_4_frame;
+        let mut threads = BTreeMap::new();
+        threads.insert(current, Box::new(boot_thread));
+        Scheduler {
+            threads,
+            ready: [const { VecDeque::new() }; PRIORITY_LEVELS],
+            current,
+            idle: current,
+            free_stacks: Vec::new(),
+            kernel_level_4_frame,
+        }
+    }
+
+    pub fn thread(&mut self, id: ThreadId) -> &mut Thread {
+        self.threads.get_mut(&id).expect("no such thread")
+    }
+
+    pub fn make_ready(&mut self, id: ThreadId) {
+        let thread = self.thread(id);
+        thread.state = State::Ready;
+        thread.ready_since = time::ticks();
+        let priority = thread.priority;
+        if id != self.idle {
+            self.ready[priority as usize].push_back(id);
+        }
+    }
+
+    // Whether a ready thread should take the CPU from the running one right away.
+    pub fn outranks_current(&mut self, id: ThreadId) -> bool {
+        let current = self.current;
+        let priority = self.thread(id).priority;
+        current == self.idle || priority > self.thread(current).priority
+    }
+
+    // Changes the priority, moving the thread to its new queue if it's ready.
+    pub fn set_priority(&mut self, id: ThreadId, priority: Priority) {
+        let thread = self.thread(id);
+        let (old, state) = (thread.priority, thread.state);
+        thread.priority = priority;
+        if state == State::Ready && id != self.idle {
+            self.ready[old as usize].retain(|&ready| ready != id);
+            self.ready[priority as usize].push_back(id);
+        }
+    }
+
+    // Makes the threads waiting for `id` to finish ready again.
+    pub fn wake_joiners(&mut self, id: ThreadId) {
+        let joiners: Vec<ThreadId> = self
+            .threads
+            .values()
+            .filter(|thread| thread.state == State::Joining(id))
+            .map(|thread| thread.id)
+            .collect();
+        for joiner in joiners {
+            self.make_ready(joiner);
+        }
+    }
+
+    // Frees finished threads other than the running one, whose stack is still in use.
+    pub fn reap(&mut self) {
+        let current = self.current;
+        let finished: Vec<ThreadId> = self
+            .threads
+            .values()
+            .filter(|thread| thread.state == State::Finished && thread.id != current)
+            .map(|thread| thread.id)
+            .collect();
+        for id in finished {
+            if let Some(stack_top) = self.threads.remove(&id).and_then(|thread| thread.stack_top) {
+                self.free_stacks.push(stack_top);
+            }
+        }
+    }
+
+    // Picks the thread to run next: the first ready one of the highest priority, else
+    // the idle thread. The running thread has to be queued first if it may go on.
+    pub fn pick_next(&mut self) -> ThreadId {
+        self.ready.iter_mut().rev().find_map(|queue| queue.pop_front()).unwrap_or(self.idle)
+    }
+}
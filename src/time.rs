@@ -0,0 +1,131 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::structures::paging::{FrameAllocator, Mapper, Size4KiB};
+use crate::{cmdline, hpet, println, timer};
+use crate::irq::{self, IrqReturn};
+
+// frequency of the system tick, independent of the hardware that generates it
+pub const TICK_HZ: u64 = 100;
+
+// both the PIT and the HPET in legacy replacement mode raise IRQ0
+pub const TIMER_IRQ: u8 = 0;
+
+// the PIT input clock runs at ~1.193182 MHz
+const PIT_FREQUENCY: u64 = 1_193_182;
+
+// Hardware timer driving IRQ0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TickSource {
+    Pit,
+    Hpet,
+}
+
+impl TickSource {
+    // The source the `tick=pit` or `tick=hpet` command line option asks for, the
+    // HPET without one.
+    pub fn from_cmdline() -> TickSource {
+        match cmdline::value("tick").as_deref() {
+            None | Some("hpet") => TickSource::Hpet,
+            Some("pit") => TickSource::Pit,
+            Some(other) => {
+                println!("Unknown tick source {:?}, using the HPET", other);
+                TickSource::Hpet
+            }
+        }
+    }
+}
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+static SOURCE: Mutex<TickSource> = Mutex::new(TickSource::Pit);
+
+// Programs PIT channel 0 as a rate generator firing at `hz`.
+fn init_pit(hz: u64) {
+    let divisor = (PIT_FREQUENCY / hz) as u16;
+    let mut command: Port<u8> = Port::new(0x43);
+    let mut channel0: Port<u8> = Port::new(0x40);
+    unsafe {
+        // channel 0, lobyte/hibyte access, mode 2 (rate generator)
+        command.write(0x34);
+        channel0.write((divisor & 0xff) as u8);
+        channel0.write((divisor >> 8) as u8);
+    }
+}
+
+// Sets up the requested tick source at TICK_HZ. When the HPET is requested but
+// missing or unusable, the PIT is used instead. Returns the source actually in use.
+pub fn init(
+    requested: TickSource,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> TickSource {
+    let mut source = TickSource::Pit;
+    if requested == TickSource::Hpet {
+        let started = hpet::init(mapper, frame_allocator).and_then(|_| {
+            hpet::HPET.lock().as_mut().unwrap().start_periodic(TICK_HZ)
+        });
+        match started {
+            Ok(()) => source = TickSource::Hpet,
+            Err(err) => {
+                // a halted HPET is of no use, not even as a clock
+                *hpet::HPET.lock() = None;
+                println!("HPET unavailable ({:?}), falling back to the PIT", err);
+            }
+        }
+    }
+    if source == TickSource::Pit {
+        init_pit(TICK_HZ);
+    }
+    *SOURCE.lock() = source;
+    source
+}
+
+// Attaches the tick counter to the timer IRQ line. Until `init` programs a
+// tick source, the PIT keeps running at its power-on rate of ~18.2 Hz.
+pub fn init_irq() {
+    irq::register(TIMER_IRQ, "timer", &timer_interrupt_handler).expect("timer IRQ registration failed");
+}
+
+fn timer_interrupt_handler(_stack_frame: &InterruptStackFrame) -> IrqReturn {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    timer::tick(now);
+    IrqReturn::Handled
+}
+
+// Number of ticks since boot.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+// Number of ticks that last at least `ms` milliseconds, saturating for durations
+// that last forever anyway.
+pub fn ms_to_ticks(ms: u64) -> u64 {
+    ms.saturating_mul(TICK_HZ).div_ceil(1000)
+}
+
+// Milliseconds since boot, with tick granularity.
+pub fn uptime_ms() -> u64 {
+    ticks() * 1000 / TICK_HZ
+}
+
+// Spins for at least `us` microseconds. Without an HPET this has tick granularity
+// and needs interrupts enabled.
+pub fn busy_wait_us(us: u64) {
+    let hpet_start = hpet::HPET.lock().as_ref().map(|hpet| hpet.nanos());
+    if let Some(start) = hpet_start {
+        while hpet::HPET.lock().as_ref().unwrap().nanos() - start < us * 1000 {
+            core::hint::spin_loop();
+        }
+        return;
+    }
+    // a partial tick might already have passed, so wait for one more
+    let end = ticks() + (us * TICK_HZ).div_ceil(1_000_000) + 1;
+    while ticks() < end {
+        core::hint::spin_loop();
+    }
+}
+
+pub fn tick_source() -> TickSource {
+    *SOURCE.lock()
+}
@@ -0,0 +1,65 @@
+use core::task::Waker;
+use crate::sync::SpinLock;
+use crate::thread::{self, ThreadId};
+use crate::time;
+
+mod wheel;
+
+pub use wheel::{TimerId, TimerWheel};
+
+// Kernel timers, kept in a timing wheel that the timer interrupt advances every
+// tick. Expired timers act in interrupt context, so what they do has to be safe
+// there.
+pub enum Action {
+    // makes a sleeping or blocked thread ready again
+    Wake(ThreadId),
+    // wakes an async task, see `task::delay`
+    Waker(Waker),
+    // calls the function with the argument
+    Call(fn(usize), usize),
+}
+
+static TIMERS: SpinLock<TimerWheel<Action>> = SpinLock::new(TimerWheel::new());
+
+// Starts a timer that acts at tick `expires`, or on the next tick if that passed.
+pub fn add(expires: u64, action: Action) -> TimerId {
+    TIMERS.lock().add(expires, action)
+}
+
+// Starts a timer that acts once at least `ms` milliseconds have passed.
+pub fn add_ms(ms: u64, action: Action) -> TimerId {
+    add(time::ticks().saturating_add(time::ms_to_ticks(ms)), action)
+}
+
+// Stops a timer before it acts; false if it already did or was cancelled.
+pub fn cancel(id: TimerId) -> bool {
+    // a waker is dropped once unlocked
+    let action = TIMERS.lock().cancel(id);
+    action.is_some()
+}
+
+// Moves a timer that hasn't acted yet to tick `expires`.
+pub fn reschedule(id: TimerId, expires: u64) -> bool {
+    TIMERS.lock().reschedule(id, expires)
+}
+
+// Number of timers that haven't acted yet.
+pub fn pending() -> usize {
+    TIMERS.lock().len()
+}
+
+// Called by the timer interrupt handler once the tick count went up.
+pub(crate) fn tick(now: u64) {
+    TIMERS.lock().advance(now);
+    loop {
+        // not locked while acting, the action may start a timer itself
+        let Some(action) = TIMERS.lock().pop_expired() else { return };
+        match action {
+            Action::Wake(id) => {
+                thread::wake_timed_out(id);
+            }
+            Action::Waker(waker) => waker.wake(),
+            Action::Call(function, argument) => function(argument),
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use alloc::vec::Vec;
+
+// A hierarchical timing wheel. Level 0 has a slot per tick for the next 64 ticks,
+// each slot of level 1 covers 64 ticks, of level 2 64 * 64 and so on. Whenever
+// level 0 has gone round once, the next slot of level 1 is cascaded: its timers are
+// spread over level 0 (and likewise for the levels above), so every timer gets
+// looked at a few times at most. Timers live in a slab and the slots are
+// intrusive doubly linked lists, which makes adding, cancelling and rescheduling
+// O(1).
+
+const LEVELS: usize = 4;
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+// timers further out than this are parked in the last level and placed again
+// whenever they are cascaded
+const RANGE: u64 = 1 << (SLOT_BITS * LEVELS as u32);
+const NIL: u32 = u32::MAX;
+
+// Identifies a timer in its wheel. Stays invalid once the timer fired or was
+// cancelled, even if the slab entry is reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerId {
+    index: u32,
+    generation: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum List {
+    Free,
+    Slot(usize, usize),
+    Expired,
+}
+
+struct Entry<T> {
+    expires: u64,
+    value: Option<T>,
+    generation: u32,
+    list: List,
+    prev: u32,
+    next: u32,
+}
+
+pub struct TimerWheel<T> {
+    entries: Vec<Entry<T>>,
+    // unused entries, linked through `next`
+    free: u32,
+    slots: [[u32; SLOTS]; LEVELS],
+    // timers that are due, oldest first, until `pop_expired` takes them
+    expired: u32,
+    expired_tail: u32,
+    // the next tick to process
+    now: u64,
+    len: usize,
+}
+
+impl<T> TimerWheel<T> {
+    pub const fn new() -> Self {
+        TimerWheel {
+            entries: Vec::new(),
+            free: NIL,
+            slots: [[NIL; SLOTS]; LEVELS],
+            expired: NIL,
+            expired_tail: NIL,
+            now: 0,
+            len: 0,
+        }
+    }
+
+    // Number of timers that haven't been taken by `pop_expired` or cancelled.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Adds a timer that expires at tick `expires`; one in the past expires with the
+    // next `advance`.
+    pub fn add(&mut self, expires: u64, value: T) -> TimerId {
+        let index = match self.free {
+            NIL => {
+                self.entries.push(Entry { expires, value: None, generation: 0, list: List::Free, prev: NIL, next: NIL });
+                (self.entries.len() - 1) as u32
+            }
+            index => {
+                self.free = self.entries[index as usize].next;
+                index
+            }
+        };
+        let entry = &mut self.entries[index as usize];
+        entry.expires = expires;
+        entry.value = Some(value);
+        let generation = entry.generation;
+        self.place(index);
+        self.len += 1;
+        TimerId { index, generation }
+    }
+
+    // Removes a timer that hasn't been taken yet, giving back its value.
+    pub fn cancel(&mut self, id: TimerId) -> Option<T> {
+        if !self.is_valid(id) {
+            return None;
+        }
+        self.unlink(id.index);
+        Some(self.release(id.index))
+    }
+
+    // Moves a timer that hasn't been taken yet to a new expiry tick; false if it
+    // isn't there anymore.
+    pub fn reschedule(&mut self, id: TimerId, expires: u64) -> bool {
+        if !self.is_valid(id) {
+            return false;
+        }
+        self.unlink(id.index);
+        self.entries[id.index as usize].expires = expires;
+        self.place(id.index);
+        true
+    }
+
+    // Processes every tick up to and including `now`, moving the timers that
+    // expire to the expired list.
+    pub fn advance(&mut self, now: u64) {
+        while self.now <= now {
+            let slot = self.now as usize & (SLOTS - 1);
+            if slot == 0 {
+                for level in 1..LEVELS {
+                    let slot = (self.now >> (SLOT_BITS * level as u32)) as usize & (SLOTS - 1);
+                    self.cascade(level, slot);
+                    // the level above only moves on when this one went round
+                    if slot != 0 {
+                        break;
+                    }
+                }
+            }
+            while self.slots[0][slot] != NIL {
+                let index = self.slots[0][slot];
+                self.unlink(index);
+                self.push_expired(index);
+            }
+            self.now += 1;
+        }
+    }
+
+    // Takes the next expired timer's value.
+    pub fn pop_expired(&mut self) -> Option<T> {
+        let index = self.expired;
+        if index == NIL {
+            return None;
+        }
+        self.unlink(index);
+        Some(self.release(index))
+    }
+
+    fn is_valid(&self, id: TimerId) -> bool {
+        self.entries
+            .get(id.index as usize)
+            .is_some_and(|entry| entry.generation == id.generation && entry.list != List::Free)
+    }
+
+    // Links a timer into the slot (or the expired list) its expiry tick belongs to.
+    fn place(&mut self, index: u32) {
+        let expires = self.entries[index as usize].expires;
+        if expires < self.now {
+            self.push_expired(index);
+            return;
+        }
+        let delta = expires - self.now;
+        let level = (0..LEVELS - 1).find(|&level| delta < 1 << (SLOT_BITS * (level as u32 + 1))).unwrap_or(LEVELS - 1);
+        let target = if delta < RANGE { expires } else { self.now + RANGE - 1 };
+        let slot = (target >> (SLOT_BITS * level as u32)) as usize & (SLOTS - 1);
+
+        let head = self.slots[level][slot];
+        let entry = &mut self.entries[index as usize];
+        entry.list = List::Slot(level, slot);
+        entry.prev = NIL;
+        entry.next = head;
+        if head != NIL {
+            self.entries[head as usize].prev = index;
+        }
+        self.slots[level][slot] = index;
+    }
+
+    fn push_expired(&mut self, index: u32) {
+        let tail = self.expired_tail;
+        let entry = &mut self.entries[index as usize];
+        entry.list = List::Expired;
+        entry.prev = tail;
+        entry.next = NIL;
+        match tail {
+            NIL => self.expired = index,
+            tail => self.entries[tail as usize].next = index,
+        }
+        self.expired_tail = index;
+    }
+
+    fn unlink(&mut self, index: u32) {
+        let Entry { prev, next, list, .. } = self.entries[index as usize];
+        match prev {
+            NIL => match list {
+                List::Slot(level, slot) => self.slots[level][slot] = next,
+                List::Expired => self.expired = next,
+                List::Free => unreachable!("unlinking a free timer"),
+            },
+            prev => self.entries[prev as usize].next = next,
+        }
+        match next {
+            NIL if list == List::Expired => self.expired_tail = prev,
+            NIL => {}
+            next => self.entries[next as usize].prev = prev,
+        }
+        let entry = &mut self.entries[index as usize];
+        entry.list = List::Free;
+        entry.prev = NIL;
+        entry.next = NIL;
+    }
+
+    // Frees an unlinked entry and returns its value.
+    fn release(&mut self, index: u32) -> T {
+        let entry = &mut self.entries[index as usize];
+        entry.generation = entry.generation.wrapping_add(1);
+        entry.next = self.free;
+        self.free = index;
+        self.len -= 1;
+        entry.value.take().expect("timer without a value")
+    }
+
+    // Places the timers of a higher level slot again, now that they're closer.
+    fn cascade(&mut self, level: usize, slot: usize) {
+        while self.slots[level][slot] != NIL {
+            let index = self.slots[level][slot];
+            self.unlink(index);
+            self.place(index);
+        }
+    }
+}
+
+impl<T> Default for TimerWheel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,122 @@
+use core::arch::global_asm;
+use x86_64::VirtAddr;
+use crate::interrupts;
+
+// Complete register state of the interrupted code, as saved by the trap entry
+// stubs below. Unlike `InterruptStackFrame` it includes the general purpose
+// registers, and every field may be modified before returning.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TrapFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub vector: u64,
+    // pushed by the CPU
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+// Entry stubs for the exceptions that need the full register state. Each pushes
+// its vector number, then the common part saves all general purpose registers and
+// calls `trap_dispatch` with a pointer to the resulting TrapFrame. rbp is left
+// untouched so backtraces continue into the interrupted code. Traps from user mode
+// switch to the kernel's GS base with `swapgs` on the way in and out.
+global_asm!(
+    ".global trap_entry_debug",
+    "trap_entry_debug:",
+    "    push 1",
+    "    jmp trap_common",
+    ".global trap_entry_breakpoint",
+    "trap_entry_breakpoint:",
+    "    push 3",
+    "    jmp trap_common",
+    "trap_common:",
+    // the saved cs is above the vector number and rip
+    "    test byte ptr [rsp + 16], 3",
+    "    jz trap_common_save",
+    "    swapgs",
+    "trap_common_save:",
+    "    push rax",
+    "    push rbx",
+    "    push rcx",
+    "    push rdx",
+    "    push rsi",
+    "    push rdi",
+    "    push rbp",
+    "    push r8",
+    "    push r9",
+    "    push r10",
+    "    push r11",
+    "    push r12",
+    "    push r13",
+    "    push r14",
+    "    push r15",
+    "    mov rdi, rsp",
+    // the frame is an odd number of quadwords, realign for the call
+    "    mov r12, rsp",
+    "    and rsp, -16",
+    "    call {dispatch}",
+    "    mov rsp, r12",
+    "    pop r15",
+    "    pop r14",
+    "    pop r13",
+    "    pop r12",
+    "    pop r11",
+    "    pop r10",
+    "    pop r9",
+    "    pop r8",
+    "    pop rbp",
+    "    pop rdi",
+    "    pop rsi",
+    "    pop rdx",
+    "    pop rcx",
+    "    pop rbx",
+    "    pop rax",
+    // drop the vector number
+    "    add rsp, 8",
+    "    test byte ptr [rsp + 8], 3",
+    "    jz trap_common_return",
+    "    swapgs",
+    "trap_common_return:",
+    "    iretq",
+    dispatch = sym trap_dispatch,
+);
+
+extern "C" {
+    fn trap_entry_debug();
+    fn trap_entry_breakpoint();
+}
+
+extern "C" fn trap_dispatch(frame: &mut TrapFrame) {
+    match frame.vector {
+        1 => interrupts::debug_handler(frame),
+        3 => interrupts::breakpoint_handler(frame),
+        vector => panic!("trap entry for unexpected vector {}", vector),
+    }
+}
+
+// Address of the #DB entry stub, for the IDT.
+pub fn debug_entry() -> VirtAddr {
+    VirtAddr::new(trap_entry_debug as *const () as u64)
+}
+
+// Address of the #BP entry stub, for the IDT.
+pub fn breakpoint_entry() -> VirtAddr {
+    VirtAddr::new(trap_entry_breakpoint as *const () as u64)
+}
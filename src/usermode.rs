@@ -0,0 +1,159 @@
+use alloc::format;
+use core::arch::{asm, global_asm};
+use x86_64::instructions::interrupts;
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::VirtAddr;
+use crate::interrupts::exception_name;
+use crate::signal::Signal;
+use crate::{gdt, percpu, println};
+
+// How a user program gave control back to the kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserExit {
+    // through the exit system call, with its status
+    Exited(i32),
+    // killed by a CPU exception
+    Fault(u8),
+    // ended by a signal, see `process::kill`
+    Signaled(Signal),
+}
+
+// exit reasons travel through `user_enter`'s return value
+const EXITED: u64 = 0;
+const FAULT: u64 = 1 << 32;
+const SIGNALED: u64 = 2 << 32;
+
+impl UserExit {
+    // The status a waiting parent sees: the program's own, or 128 plus the fault's
+    // vector or the signal's number, like a shell reports signals.
+    pub fn status(self) -> i32 {
+        match self {
+            UserExit::Exited(status) => status,
+            UserExit::Fault(vector) => 128 + vector as i32,
+            UserExit::Signaled(signal) => 128 + signal.number() as i32,
+        }
+    }
+
+    fn encode(self) -> u64 {
+        match self {
+            UserExit::Exited(status) => EXITED | status as u32 as u64,
+            UserExit::Fault(vector) => FAULT | vector as u64,
+            UserExit::Signaled(signal) => SIGNALED | signal.number() as u64,
+        }
+    }
+
+    fn decode(value: u64) -> Self {
+        match value & !0xffff_ffff {
+            EXITED => UserExit::Exited(value as u32 as i32),
+            FAULT => UserExit::Fault(value as u8),
+            SIGNALED => UserExit::Signaled(Signal::from_number(value & 0xff).expect("invalid signal")),
+            _ => unreachable!("invalid user exit {:#x}", value),
+        }
+    }
+}
+
+// `user_enter(entry, stack_top, return_rsp, kernel_stack, ss, cs)` saves the
+// callee-saved registers, stores the resulting stack pointer in `*return_rsp` and as
+// the TSS kernel stack (`*kernel_stack`), then drops to ring 3 with `iretq`.
+// `user_return(rsp, value)` unwinds back to that point, making `user_enter` return
+// `value`. Interrupts from user mode arrive right below the saved registers.
+global_asm!(
+    ".global user_enter",
+    "user_enter:",
+    "    cli",
+    "    push rbp",
+    "    push rbx",
+    "    push r12",
+    "    push r13",
+    "    push r14",
+    "    push r15",
+    "    mov [rdx], rsp",
+    "    mov [rcx], rsp",
+    // interrupt frame: ss, rsp, rflags (interrupts enabled), cs, rip
+    "    push r8",
+    "    push rsi",
+    "    push 0x202",
+    "    push r9",
+    "    push rdi",
+    // don't leak kernel values into user mode
+    "    xor eax, eax",
+    "    xor ebx, ebx",
+    "    xor ecx, ecx",
+    "    xor edx, edx",
+    "    xor esi, esi",
+    "    xor edi, edi",
+    "    xor ebp, ebp",
+    "    xor r8d, r8d",
+    "    xor r9d, r9d",
+    "    xor r10d, r10d",
+    "    xor r11d, r11d",
+    "    xor r12d, r12d",
+    "    xor r13d, r13d",
+    "    xor r14d, r14d",
+    "    xor r15d, r15d",
+    "    swapgs",
+    "    iretq",
+    ".global user_return",
+    "user_return:",
+    "    mov rsp, rdi",
+    "    mov rax, rsi",
+    "    pop r15",
+    "    pop r14",
+    "    pop r13",
+    "    pop r12",
+    "    pop rbx",
+    "    pop rbp",
+    "    ret",
+);
+
+extern "C" {
+    fn user_enter(entry: u64, stack_top: u64, return_rsp: *mut u64, kernel_stack: *mut VirtAddr, ss: u64, cs: u64) -> u64;
+    fn user_return(rsp: u64, value: u64) -> !;
+}
+
+// Runs user code at `entry` on the user stack `stack_top` until it exits. Both have
+// to be mapped user accessible. Processes (see `process`) are built on top of this.
+pub fn run(entry: VirtAddr, stack_top: VirtAddr) -> UserExit {
+    let selectors = gdt::selectors();
+    let interrupts_enabled = interrupts::are_enabled();
+    let cpu = percpu::current();
+    let value = unsafe {
+        let kernel_stack = &raw mut (*cpu.tss()).privilege_stack_table[0];
+        user_enter(
+            entry.as_u64(),
+            stack_top.as_u64(),
+            cpu.user_return_rsp(),
+            kernel_stack,
+            selectors.user_data.0 as u64,
+            selectors.user_code.0 as u64,
+        )
+    };
+    // back through `exit`, which may have been called with interrupts disabled
+    if interrupts_enabled {
+        interrupts::enable();
+    }
+    UserExit::decode(value)
+}
+
+// Abandons the running user program and returns from its `run` call. Must be called
+// with the kernel's GS base active.
+pub fn exit(reason: UserExit) -> ! {
+    let rsp = unsafe { *percpu::current().user_return_rsp() };
+    unsafe { user_return(rsp, reason.encode()) }
+}
+
+pub fn is_from_user(stack_frame: &InterruptStackFrame) -> bool {
+    stack_frame.code_segment & 3 == 3
+}
+
+// Kills the running user program when an exception came from user mode, and
+// returns otherwise, so the exception handler can deal with kernel faults.
+pub fn handle_fault(vector: u8, stack_frame: &InterruptStackFrame) {
+    if !is_from_user(stack_frame) {
+        return;
+    }
+    // the handler was entered with the user's GS base, and never returns to reset it
+    unsafe { asm!("swapgs", options(nostack, preserves_flags)) };
+    println!("{}", format!("User program killed: {} at {:?}", exception_name(vector), stack_frame.instruction_pointer));
+    exit(UserExit::Fault(vector));
+}
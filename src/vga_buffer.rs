@@ -1,10 +1,10 @@
 use core::fmt;
 use lazy_static::lazy_static;
-use spin::Mutex;
+use crate::sync::SpinLock;
 use volatile::Volatile;
 
 lazy_static! {
-    pub static ref WRITER: Mutex<Writer> = Mutex::new (Writer {
+    pub static ref WRITER: SpinLock<Writer> = SpinLock::new(Writer {
         column_position: 0,
         color_code: ColorCode::new(Color::White, Color::Black),
         buffer: unsafe {&mut *(0xb8000 as *mut Buffer)},
@@ -55,7 +55,7 @@ struct ScreenChar {
     color_code: ColorCode,
 }
 
-const BUFFER_HEIGHT: usize = 25;
+pub const BUFFER_HEIGHT: usize = 25;
 const BUFFER_WIDTH: usize = 80;
 
 #[repr(transparent)]
@@ -118,6 +118,14 @@ impl Writer {
         self.column_position = 0;
     }
 
+    // blanks the whole screen and starts over at the beginning of the last row
+    pub fn clear_screen(&mut self) {
+        for row in 0..BUFFER_HEIGHT {
+            self.clear_row(row);
+        }
+        self.column_position = 0;
+    }
+
     // to clear the last row after moving all the above lines up
     fn clear_row(&mut self, row: usize) {
         let blank = ScreenChar {
@@ -230,6 +238,16 @@ pub fn _print_with_color(args: fmt::Arguments, color_code: ColorCode) {
     });
 }
 
+pub fn clear_screen() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.color_code = Writer::default_color_code();
+        writer.clear_screen();
+    });
+}
+
 #[macro_export]
 macro_rules! print {
     // If both foreground and background are specified
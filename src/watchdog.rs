@@ -0,0 +1,146 @@
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use x86_64::structures::idt::InterruptStackFrame;
+use crate::irq::{self, IrqReturn};
+use crate::sync::spinlock;
+use crate::thread::{self, State, ThreadId};
+use crate::{apic, backtrace, gdbstub, percpu, serial_println, time};
+
+// Software watchdog. On every timer tick it checks that no thread has been ready to
+// run for longer than the timeout without getting the CPU, which means whatever
+// runs doesn't give it up: a thread of higher priority spinning, or the scheduler
+// not getting to switch. It then dumps the interrupted code, the threads and the
+// spin locks held to serial, once per stall. A CPU stuck with interrupts disabled
+// gets no timer ticks, so with several processors the first application processor
+// checks on its own timer that the system tick keeps going; if the stuck CPU spins on
+// a lock, `SpinLock` itself reports that too.
+
+pub const DEFAULT_TIMEOUT_MS: u64 = 5000;
+// how often the application processor checks on the system tick
+pub const TICK_CHECK_MS: u64 = 100;
+
+// 0 while disabled
+static TIMEOUT_TICKS: AtomicU64 = AtomicU64::new(0);
+// whether the current stall was reported already
+static REPORTED: AtomicBool = AtomicBool::new(false);
+static LOCKUPS: AtomicU64 = AtomicU64::new(0);
+// the system tick count at the last check, checks since it last moved, and whether
+// that stall was reported already
+static LAST_TICKS: AtomicU64 = AtomicU64::new(0);
+static STALLED_CHECKS: AtomicU64 = AtomicU64::new(0);
+static TICK_STALL_REPORTED: AtomicBool = AtomicBool::new(false);
+static TICK_STALLS: AtomicU64 = AtomicU64::new(0);
+
+// Starts checking on the timer interrupt, with the default timeout. Needs threads.
+pub fn init() {
+    set_timeout(Some(DEFAULT_TIMEOUT_MS));
+    irq::register(time::TIMER_IRQ, "watchdog", &timer_tick).expect("watchdog IRQ registration failed");
+}
+
+// Starts checking the system tick on the calling application processor's timer.
+pub fn init_ap() {
+    apic::start_timer(TICK_CHECK_MS * 1000);
+}
+
+// Sets how long a thread may wait for the CPU in milliseconds, None to disable the
+// watchdog.
+pub fn set_timeout(ms: Option<u64>) {
+    let ticks = ms.map_or(0, |ms| time::ms_to_ticks(ms).max(1));
+    TIMEOUT_TICKS.store(ticks, Ordering::Relaxed);
+}
+
+pub fn timeout() -> Option<u64> {
+    match TIMEOUT_TICKS.load(Ordering::Relaxed) {
+        0 => None,
+        ticks => Some(ticks.saturating_mul(1000) / time::TICK_HZ),
+    }
+}
+
+// Number of stalls detected since boot.
+pub fn lockups() -> u64 {
+    LOCKUPS.load(Ordering::Relaxed)
+}
+
+// Number of times the system tick stopped for longer than the timeout since boot.
+pub fn tick_stalls() -> u64 {
+    TICK_STALLS.load(Ordering::Relaxed)
+}
+
+// Called every TICK_CHECK_MS by the application processor's timer: reports once when
+// the system tick hasn't moved for the timeout, meaning the bootstrap processor has
+// had interrupts disabled that long.
+pub fn check_system_tick() {
+    let Some(timeout) = timeout() else { return };
+    // a debugger holding the CPU isn't a lockup
+    if gdbstub::is_attached() {
+        return;
+    }
+    let ticks = time::ticks();
+    if LAST_TICKS.swap(ticks, Ordering::Relaxed) != ticks {
+        STALLED_CHECKS.store(0, Ordering::Relaxed);
+        TICK_STALL_REPORTED.store(false, Ordering::Relaxed);
+        return;
+    }
+    let stalled = (STALLED_CHECKS.fetch_add(1, Ordering::Relaxed) + 1) * TICK_CHECK_MS;
+    if stalled >= timeout && !TICK_STALL_REPORTED.swap(true, Ordering::Relaxed) {
+        TICK_STALLS.fetch_add(1, Ordering::Relaxed);
+        serial_println!(
+            "watchdog: no system tick for {} ms, CPU 0 runs with interrupts disabled (seen from CPU {})",
+            stalled,
+            percpu::current().index()
+        );
+        serial_println!("watchdog: spin locks held:");
+        spinlock::held_locks(|lock| lock.report("watchdog:   "));
+    }
+}
+
+fn timer_tick(stack_frame: &InterruptStackFrame) -> IrqReturn {
+    let timeout = TIMEOUT_TICKS.load(Ordering::Relaxed);
+    if timeout == 0 {
+        return IrqReturn::NotHandled;
+    }
+    let now = time::ticks();
+    // the ready thread that has waited longest
+    let mut starved: Option<(ThreadId, u64)> = None;
+    thread::for_each_thread(|id, _, state, _, ready_since| {
+        // since it became ready, a thread that slept or blocked for long didn't starve
+        let waited = now.saturating_sub(ready_since);
+        if state == State::Ready && waited >= timeout && starved.is_none_or(|(_, longest)| waited > longest) {
+            starved = Some((id, waited));
+        }
+    });
+    match starved {
+        None => REPORTED.store(false, Ordering::Relaxed),
+        Some((id, waited)) => {
+            if !REPORTED.swap(true, Ordering::Relaxed) {
+                LOCKUPS.fetch_add(1, Ordering::Relaxed);
+                report(id, waited, stack_frame.instruction_pointer.as_u64());
+            }
+        }
+    }
+    IrqReturn::Handled
+}
+
+// Dumps what the CPU is doing to serial. Doesn't allocate, the allocator's lock
+// might be held by the interrupted code.
+fn report(starved: ThreadId, waited: u64, rip: u64) {
+    serial_println!(
+        "watchdog: thread {} has been waiting {} ms for CPU {}",
+        starved,
+        waited * 1000 / time::TICK_HZ,
+        percpu::current().index()
+    );
+    match backtrace::resolve(rip) {
+        Some((name, offset)) => {
+            serial_println!("watchdog: interrupted at {:#x} {}+{:#x}", rip, name, offset);
+        }
+        None => {
+            serial_println!("watchdog: interrupted at {:#x}", rip);
+        }
+    }
+    serial_println!("watchdog: threads (id, state, last switch tick, ready since tick, name):");
+    thread::for_each_thread(|id, name, state, last_switch, ready_since| {
+        serial_println!("watchdog:   {:>5} {:<8} {:>10} {:>10} {}", id, state.name(), last_switch, ready_since, name);
+    });
+    serial_println!("watchdog: spin locks held:");
+    spinlock::held_locks(|lock| lock.report("watchdog:   "));
+}
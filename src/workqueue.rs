@@ -0,0 +1,174 @@
+use alloc::collections::VecDeque;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use crate::sync::{SpinLock, WaitQueue};
+use crate::thread::{self, Priority, ThreadId};
+use crate::timer::{self, Action, TimerId};
+use crate::percpu;
+
+// Deferred work, the "bottom half" of interrupt handling. A handler does only what
+// can't wait, e.g. reading a device register, and schedules a `Work` item for the
+// rest, which the "kworker" thread runs later with interrupts enabled. Until `init`
+// started that thread, pending work runs on the way out of the next interrupt.
+
+// A function to run later, declared as a static:
+//     static WORK: Work = Work::new("name", function);
+// It's queued at most once at a time, scheduling it again while pending does nothing.
+pub struct Work {
+    name: &'static str,
+    function: fn(),
+    // queued or waiting for its delay to pass; cleared right before it runs, so the
+    // function may schedule its own work item again
+    pending: AtomicBool,
+    // while waiting for its delay
+    timer: SpinLock<Option<TimerId>>,
+    runs: AtomicU64,
+}
+
+impl Work {
+    pub const fn new(name: &'static str, function: fn()) -> Self {
+        Work { name, function, pending: AtomicBool::new(false), timer: SpinLock::new(None), runs: AtomicU64::new(0) }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.load(Ordering::Acquire)
+    }
+
+    // Number of times the function ran.
+    pub fn runs(&self) -> u64 {
+        self.runs.load(Ordering::Relaxed)
+    }
+
+    // Queues the work to run as soon as possible; false if it was pending already.
+    // May be called from interrupt handlers.
+    pub fn schedule(&'static self) -> bool {
+        if self.pending.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+        QUEUE.lock().push_back(self);
+        READY.wake_one();
+        true
+    }
+
+    // Queues the work once at least `ms` milliseconds have passed; false if it was
+    // pending already.
+    pub fn schedule_delayed(&'static self, ms: u64) -> bool {
+        if ms == 0 {
+            return self.schedule();
+        }
+        if self.pending.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+        let mut timer = self.timer.lock();
+        *timer = Some(timer::add_ms(ms, Action::Call(queue_delayed, self as *const Work as usize)));
+        true
+    }
+
+    // Takes back pending work before it runs; false if it wasn't pending. Work that
+    // already started running finishes.
+    pub fn cancel(&'static self) -> bool {
+        // locked, which keeps interrupts and with them the timer out, so nothing
+        // queues the work again halfway through
+        let mut queue = QUEUE.lock();
+        if !self.pending.swap(false, Ordering::AcqRel) {
+            return false;
+        }
+        // a stale entry would run the work as soon as it's scheduled again
+        queue.retain(|work| !ptr::eq(*work, self));
+        if let Some(timer) = self.timer.lock().take() {
+            timer::cancel(timer);
+        }
+        true
+    }
+
+    fn run(&self) -> bool {
+        if !self.pending.swap(false, Ordering::AcqRel) {
+            return false;
+        }
+        (self.function)();
+        self.runs.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+}
+
+// work to run, in the order it was scheduled
+static QUEUE: SpinLock<VecDeque<&'static Work>> = SpinLock::new(VecDeque::new());
+// the worker waiting for work
+static READY: WaitQueue = WaitQueue::new();
+// the worker thread, 0 until `init`
+static WORKER: AtomicU64 = AtomicU64::new(0);
+
+// Starts the worker thread. Needs threads.
+pub fn init() {
+    // above normal threads, so input is handled right away even under load
+    let worker = thread::spawn_with_priority("kworker", Priority::High, || loop {
+        READY.wait_until(|| !QUEUE.lock().is_empty());
+        run_pending();
+    });
+    WORKER.store(worker, Ordering::Release);
+    // work scheduled before the worker was around
+    READY.wake_one();
+}
+
+// The worker thread, if it was started.
+pub fn worker() -> Option<ThreadId> {
+    match WORKER.load(Ordering::Acquire) {
+        0 => None,
+        id => Some(id),
+    }
+}
+
+// Runs the queued work in the calling thread until the queue is empty. Returns how
+// many items ran.
+pub fn run_pending() -> usize {
+    let mut ran = 0;
+    loop {
+        // not locked while the work runs, it may schedule more
+        let Some(work) = QUEUE.lock().pop_front() else { return ran };
+        if work.run() {
+            ran += 1;
+        }
+    }
+}
+
+// Timer action of delayed work: queues the `Work` at the address.
+fn queue_delayed(work: usize) {
+    let work = unsafe { &*(work as *const Work) };
+    work.timer.lock().take();
+    let mut queue = QUEUE.lock();
+    if work.is_pending() {
+        queue.push_back(work);
+    }
+    drop(queue);
+    READY.wake_one();
+}
+
+// Called by the IRQ dispatcher after the end of interrupt was sent, with interrupts
+// still disabled. Without a worker thread the pending work runs right here.
+pub fn irq_exit() {
+    if worker().is_none() && percpu::current().interrupt_depth() == 0 {
+        run_pending();
+    }
+}
+
+#[test_case]
+fn test_work_runs_once_when_scheduled_twice() {
+    static RAN: AtomicU64 = AtomicU64::new(0);
+    static WORK: Work = Work::new("test", || {
+        RAN.fetch_add(1, Ordering::Relaxed);
+    });
+
+    assert!(WORK.schedule());
+    assert!(!WORK.schedule());
+    assert!(WORK.is_pending());
+    // no worker thread in the library tests, the next interrupt runs it
+    while WORK.is_pending() {
+        x86_64::instructions::hlt();
+    }
+    assert_eq!(RAN.load(Ordering::Relaxed), 1);
+    assert_eq!(WORK.runs(), 1);
+}
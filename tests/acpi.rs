@@ -0,0 +1,52 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rustos::acpi;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustos::memory;
+    use x86_64::VirtAddr;
+
+    rustos::init();
+    unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustos::test_panic_handler(info)
+}
+
+#[test_case]
+fn rsdp_is_found() {
+    assert!(acpi::find_rsdp().is_some());
+}
+
+#[test_case]
+fn madt_lists_the_boot_processor() {
+    let madt = acpi::madt().expect("no MADT");
+    assert!(madt.processor_apic_ids().count() >= 1);
+    assert_ne!(madt.local_apic_address(), 0);
+}
+
+#[test_case]
+fn fadt_points_to_dsdt() {
+    assert!(acpi::fadt().is_some());
+    assert!(acpi::dsdt().is_some());
+}
+
+#[test_case]
+fn hpet_table_is_found() {
+    let hpet = acpi::hpet().expect("no HPET table");
+    let base = hpet.base_address.address;
+    assert_ne!(base, 0);
+}
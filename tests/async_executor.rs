@@ -0,0 +1,116 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use core::task::Poll;
+use futures_util::StreamExt;
+use rustos::keyboard;
+use rustos::task::executor::Executor;
+use rustos::task::keyboard::{read_line, KeyStream};
+use rustos::task::{self, Task};
+use spin::Mutex;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustos::allocator;
+    use rustos::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    rustos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustos::test_panic_handler(info)
+}
+
+#[test_case]
+fn tasks_run_to_completion() {
+    static STEPS: AtomicU32 = AtomicU32::new(0);
+
+    async fn count_twice() {
+        STEPS.fetch_add(1, Ordering::Relaxed);
+        task::yield_now().await;
+        STEPS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(count_twice()));
+    executor.spawn(Task::new(count_twice()));
+    executor.run_until_idle();
+    assert_eq!(STEPS.load(Ordering::Relaxed), 4);
+    assert_eq!(executor.task_count(), 0);
+}
+
+#[test_case]
+fn repeated_wakes_queue_a_task_once() {
+    static POLLS: AtomicU32 = AtomicU32::new(0);
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(core::future::poll_fn(|cx| {
+        if POLLS.fetch_add(1, Ordering::Relaxed) > 0 {
+            return Poll::Ready(());
+        }
+        // more wakes than the queue has room for
+        for _ in 0..1000 {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    })));
+    executor.run_until_idle();
+    assert_eq!(POLLS.load(Ordering::Relaxed), 2);
+    assert_eq!(executor.task_count(), 0);
+}
+
+#[test_case]
+fn key_wakes_waiting_task() {
+    static KEY: AtomicU8 = AtomicU8::new(0);
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+        let key = KeyStream::new().next().await.unwrap();
+        KEY.store(key, Ordering::Relaxed);
+    }));
+    executor.run_until_idle();
+    assert_eq!(executor.task_count(), 1, "task finished without input");
+
+    keyboard::add_to_buffer(b'x');
+    executor.run_until_idle();
+    assert_eq!(executor.task_count(), 0);
+    assert_eq!(KEY.load(Ordering::Relaxed), b'x');
+}
+
+#[test_case]
+fn read_line_collects_keys() {
+    let line = Arc::new(Mutex::new(String::new()));
+    let result = line.clone();
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async move {
+        *result.lock() = read_line().await;
+    }));
+    executor.run_until_idle();
+    for &key in b"hj\x08i\n" {
+        keyboard::add_to_buffer(key);
+        executor.run_until_idle();
+    }
+    assert_eq!(executor.task_count(), 0);
+    assert_eq!(line.lock().as_str(), "hi\n");
+}
@@ -0,0 +1,53 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rustos::cmdline;
+use rustos::time::{self, TickSource};
+
+entry_point!(main);
+
+// The test runner passes `tick=pit` on the command line, see test-args in Cargo.toml.
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustos::allocator;
+    use rustos::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    rustos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    cmdline::init();
+    time::init(TickSource::from_cmdline(), &mut mapper, &mut frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustos::test_panic_handler(info)
+}
+
+#[test_case]
+fn command_line_comes_from_fw_cfg() {
+    assert_eq!(cmdline::get(), "tick=pit");
+    assert_eq!(cmdline::value("tick").as_deref(), Some("pit"));
+    assert_eq!(cmdline::value("quiet"), None);
+}
+
+#[test_case]
+fn command_line_picks_the_pit() {
+    // there is an HPET, see tests/hpet.rs
+    assert_eq!(TickSource::from_cmdline(), TickSource::Pit);
+    assert_eq!(time::tick_source(), TickSource::Pit);
+    let start = time::ticks();
+    while time::ticks() < start + 5 {
+        x86_64::instructions::hlt();
+    }
+}
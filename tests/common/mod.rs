@@ -0,0 +1,40 @@
+// Helpers shared by the integration tests that run user programs.
+
+const LOAD_ADDRESS: u64 = rustos::memory::USER_START + 0x40_0000;
+pub const HEADERS_SIZE: usize = 64 + 56;
+
+const fn put(image: &mut [u8], offset: usize, bytes: &[u8]) {
+    let mut i = 0;
+    while i < bytes.len() {
+        image[offset + i] = bytes[i];
+        i += 1;
+    }
+}
+
+// Builds, at compile time, a statically linked executable with a single read and
+// execute PT_LOAD segment covering the whole file, entered right after the headers.
+pub const fn tiny_elf<const N: usize>(code: &[u8]) -> [u8; N] {
+    let mut image = [0u8; N];
+    let file_size = (HEADERS_SIZE + code.len()) as u64;
+    // ELF header: ELF64, little endian, version 1
+    put(&mut image, 0, &[0x7f, b'E', b'L', b'F', 2, 1, 1]);
+    put(&mut image, 16, &2u16.to_le_bytes()); // ET_EXEC
+    put(&mut image, 18, &62u16.to_le_bytes()); // EM_X86_64
+    put(&mut image, 20, &1u32.to_le_bytes());
+    put(&mut image, 24, &(LOAD_ADDRESS + HEADERS_SIZE as u64).to_le_bytes()); // entry
+    put(&mut image, 32, &64u64.to_le_bytes()); // program headers right after this header
+    put(&mut image, 52, &64u16.to_le_bytes());
+    put(&mut image, 54, &56u16.to_le_bytes());
+    put(&mut image, 56, &1u16.to_le_bytes());
+    // program header: PT_LOAD, R+X
+    put(&mut image, 64, &1u32.to_le_bytes());
+    put(&mut image, 68, &5u32.to_le_bytes());
+    put(&mut image, 72, &0u64.to_le_bytes());
+    put(&mut image, 80, &LOAD_ADDRESS.to_le_bytes());
+    put(&mut image, 88, &LOAD_ADDRESS.to_le_bytes());
+    put(&mut image, 96, &file_size.to_le_bytes());
+    put(&mut image, 104, &file_size.to_le_bytes());
+    put(&mut image, 112, &4096u64.to_le_bytes());
+    put(&mut image, HEADERS_SIZE, code);
+    image
+}
@@ -0,0 +1,76 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+mod common;
+
+use bootloader::{entry_point, BootInfo};
+use common::{tiny_elf, HEADERS_SIZE};
+use core::panic::PanicInfo;
+use rustos::elf::ElfError;
+use rustos::exec::{self, ExecError};
+use rustos::usermode::UserExit;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustos::allocator;
+    use rustos::memory::{self, BootInfoFrameAllocator};
+
+    rustos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    // programs run as processes, each in a thread of its own
+    rustos::thread::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustos::test_panic_handler(info)
+}
+
+// mov rdi, [rsp]; exit(rdi)
+const EXIT_ARGC: [u8; HEADERS_SIZE + 11] =
+    tiny_elf(&[0x48, 0x8b, 0x3c, 0x24, 0xb8, 0x04, 0x00, 0x00, 0x00, 0x0f, 0x05]);
+
+// mov rax, [rsp + 16]; movzx edi, byte [rax]; exit(rdi)
+const EXIT_ARGV1: [u8; HEADERS_SIZE + 15] =
+    tiny_elf(&[0x48, 0x8b, 0x44, 0x24, 0x10, 0x0f, 0xb6, 0x38, 0xb8, 0x04, 0x00, 0x00, 0x00, 0x0f, 0x05]);
+
+#[test_case]
+fn argc_is_passed() {
+    assert_eq!(exec::exec(&EXIT_ARGC, &["argc", "a", "b"], &[]), Ok(UserExit::Exited(3)));
+}
+
+#[test_case]
+fn argv_strings_are_passed() {
+    assert_eq!(exec::exec(&EXIT_ARGV1, &["argv", "R"], &["HOME=/"]), Ok(UserExit::Exited(b'R' as i32)));
+}
+
+#[test_case]
+fn environment_follows_empty_argv() {
+    // argc = 0, NULL, then envp: [rsp + 16] is the first variable
+    assert_eq!(exec::exec(&EXIT_ARGV1, &[], &["Z=1"]), Ok(UserExit::Exited(b'Z' as i32)));
+}
+
+#[test_case]
+fn address_space_is_reusable() {
+    // the same load address again, in a fresh address space
+    for _ in 0..3 {
+        assert_eq!(exec::exec(&EXIT_ARGC, &["argc"], &[]), Ok(UserExit::Exited(1)));
+    }
+}
+
+#[test_case]
+fn garbage_is_rejected() {
+    assert_eq!(exec::exec(&[0u8; 128], &[], &[]), Err(ExecError::Elf(ElfError::BadMagic)));
+}
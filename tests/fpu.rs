@@ -0,0 +1,130 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+mod common;
+
+use bootloader::{entry_point, BootInfo};
+use common::{tiny_elf, HEADERS_SIZE};
+use core::arch::asm;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use rustos::usermode::UserExit;
+use rustos::{interrupts, process, thread, time};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustos::allocator;
+    use rustos::memory::{self, BootInfoFrameAllocator};
+    use rustos::time::TickSource;
+    use x86_64::VirtAddr;
+
+    rustos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    time::init(TickSource::Pit, &mut mapper, &mut frame_allocator);
+    memory::install(mapper, frame_allocator);
+    thread::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustos::test_panic_handler(info)
+}
+
+// The kernel is built without SSE, so the tests use the registers through assembly
+// only; nothing else in the kernel touches xmm0 between these calls.
+fn set_xmm0(value: f64) {
+    unsafe { asm!("movq xmm0, {}", in(reg) value.to_bits(), options(nomem, nostack)) };
+}
+
+fn xmm0() -> f64 {
+    let bits: u64;
+    unsafe { asm!("movq {}, xmm0", out(reg) bits, options(nomem, nostack)) };
+    f64::from_bits(bits)
+}
+
+// xmm0 += step
+fn add_to_xmm0(step: f64) {
+    unsafe { asm!("addsd xmm0, [{}]", in(reg) &step, options(readonly, nostack)) };
+}
+
+// Adds `step` to `start` in xmm0 `rounds` times, giving up the CPU in between, both
+// voluntarily and to the timer.
+fn accumulate(start: f64, step: f64, rounds: u32) -> f64 {
+    set_xmm0(start);
+    for _ in 0..rounds {
+        thread::yield_now();
+        let until = time::ticks() + 1;
+        while time::ticks() < until {
+            core::hint::spin_loop();
+        }
+        add_to_xmm0(step);
+    }
+    xmm0()
+}
+
+#[test_case]
+fn two_threads_keep_their_own_fpu_registers() {
+    static FIRST: AtomicU64 = AtomicU64::new(0);
+    static SECOND: AtomicU64 = AtomicU64::new(0);
+    let first = thread::spawn("float 1", || FIRST.store(accumulate(1.0, 0.5, 20).to_bits(), Ordering::Relaxed));
+    let second = thread::spawn("float 2", || SECOND.store(accumulate(-1000.0, 0.25, 20).to_bits(), Ordering::Relaxed));
+    thread::join(first);
+    thread::join(second);
+    assert_eq!(f64::from_bits(FIRST.load(Ordering::Relaxed)), 11.0);
+    assert_eq!(f64::from_bits(SECOND.load(Ordering::Relaxed)), -995.0);
+}
+
+#[test_case]
+fn registers_survive_another_thread_using_the_fpu() {
+    set_xmm0(42.5);
+    let id = thread::spawn("clobber", || set_xmm0(-1.0));
+    thread::join(id);
+    assert_eq!(xmm0(), 42.5);
+}
+
+#[test_case]
+fn fpu_is_switched_lazily() {
+    set_xmm0(1.0);
+    let before = interrupts::vector_count(7);
+    // no other thread touches the FPU, the registers are still the main thread's
+    thread::sleep(20);
+    add_to_xmm0(1.0);
+    assert_eq!(xmm0(), 2.0);
+    assert_eq!(interrupts::vector_count(7), before);
+
+    let id = thread::spawn("clobber", || set_xmm0(0.0));
+    thread::join(id);
+    add_to_xmm0(1.0);
+    assert_eq!(xmm0(), 3.0);
+    assert!(interrupts::vector_count(7) > before);
+}
+
+// eax = 3; xmm0 = eax; xmm0 *= xmm0; exit(xmm0)
+const SQUARE_WITH_SSE: [u8; HEADERS_SIZE + 24] = tiny_elf(&[
+    0xb8, 0x03, 0x00, 0x00, 0x00, 0xf2, 0x0f, 0x2a, 0xc0, 0xf2, 0x0f, 0x59, 0xc0, 0xf2, 0x0f, 0x2c, 0xf8, 0xb8, 0x04,
+    0x00, 0x00, 0x00, 0x0f, 0x05,
+]);
+
+#[test_case]
+fn user_programs_use_the_fpu() {
+    set_xmm0(7.0);
+    let before = interrupts::vector_count(7);
+    // the program's first SSE instruction traps from ring 3
+    let pid = process::spawn(&SQUARE_WITH_SSE, &["square"], &[]).unwrap();
+    assert_eq!(process::wait(Some(pid)), Ok((pid, UserExit::Exited(9))));
+    assert!(interrupts::vector_count(7) > before);
+    add_to_xmm0(1.0);
+    assert_eq!(xmm0(), 8.0);
+}
@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rustos::time::{self, TickSource};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustos::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    rustos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    time::init(TickSource::Hpet, &mut mapper, &mut frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustos::test_panic_handler(info)
+}
+
+#[test_case]
+fn hpet_is_tick_source() {
+    assert_eq!(time::tick_source(), TickSource::Hpet);
+}
+
+#[test_case]
+fn main_counter_advances() {
+    let hpet = rustos::hpet::HPET.lock();
+    let hpet = hpet.as_ref().expect("HPET not initialized");
+    let first = hpet.counter();
+    for _ in 0..10000 {
+        core::hint::spin_loop();
+    }
+    assert!(hpet.counter() > first);
+}
+
+#[test_case]
+fn ticks_advance() {
+    let start = time::ticks();
+    while time::ticks() < start + 5 {
+        x86_64::instructions::hlt();
+    }
+}
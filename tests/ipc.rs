@@ -0,0 +1,162 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rustos::fs::FILE_SYSTEM;
+use rustos::ipc::{self, message_queue, pipe::PIPE_CAPACITY, BrokenPipe, MessageTooLong, RecvError, SendError};
+use rustos::process::{self, Descriptor, FdTable};
+use rustos::signal::Signal;
+use rustos::syscall::{STDIN, STDOUT};
+use rustos::thread;
+use rustos::usermode::UserExit;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustos::allocator;
+    use rustos::memory::{self, BootInfoFrameAllocator};
+    use rustos::time::{self, TickSource};
+
+    rustos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    time::init(TickSource::Pit, &mut mapper, &mut frame_allocator);
+    memory::install(mapper, frame_allocator);
+    thread::init();
+    rustos::programs::install();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustos::test_panic_handler(info)
+}
+
+#[test_case]
+fn pipe_transfers_bytes() {
+    let (reader, writer) = ipc::pipe();
+    assert_eq!(writer.write(b"abc"), Ok(3));
+    drop(writer);
+    let mut buffer = [0u8; 8];
+    assert_eq!(reader.read(&mut buffer), 3);
+    assert_eq!(&buffer[..3], b"abc");
+    // every write end is gone
+    assert_eq!(reader.read(&mut buffer), 0);
+}
+
+#[test_case]
+fn full_pipe_blocks_writer() {
+    let (reader, writer) = ipc::pipe();
+    let total = 3 * PIPE_CAPACITY;
+    let producer = thread::spawn("producer", move || {
+        assert_eq!(writer.write(&vec![7u8; total]), Ok(total));
+    });
+    let mut buffer = [0u8; 1000];
+    let mut received = 0;
+    loop {
+        let count = reader.read(&mut buffer);
+        if count == 0 {
+            break;
+        }
+        assert!(buffer[..count].iter().all(|&byte| byte == 7));
+        received += count;
+    }
+    thread::join(producer);
+    assert_eq!(received, total);
+}
+
+#[test_case]
+fn pipe_without_reader_is_broken() {
+    let (reader, writer) = ipc::pipe();
+    drop(reader);
+    assert_eq!(writer.write(b"lost"), Err(BrokenPipe));
+}
+
+#[test_case]
+fn channel_keeps_order() {
+    let (sender, receiver) = ipc::channel(4);
+    let producer = thread::spawn("producer", move || {
+        for i in 0..100u32 {
+            sender.send(i).unwrap();
+        }
+    });
+    let received: Vec<u32> = (0..100).map(|_| receiver.recv().unwrap()).collect();
+    thread::join(producer);
+    assert_eq!(received, (0..100).collect::<Vec<u32>>());
+    // the sender is gone with its thread
+    assert_eq!(receiver.recv(), Err(RecvError));
+}
+
+#[test_case]
+fn channel_without_receiver_returns_message() {
+    let (sender, receiver) = ipc::channel(1);
+    drop(receiver);
+    assert_eq!(sender.send(5), Err(SendError(5)));
+}
+
+#[test_case]
+fn message_queues_are_found_by_name() {
+    message_queue::open("test").send(b"ping").unwrap();
+    let queue = message_queue::open("test");
+    assert_eq!(queue.len(), 1);
+    assert_eq!(queue.receive(), b"ping");
+    assert_eq!(queue.try_receive(), None);
+    assert_eq!(queue.send(&[0; message_queue::MAX_MESSAGE_SIZE + 1]), Err(MessageTooLong));
+}
+
+#[test_case]
+fn kill_ends_a_process_blocked_on_a_pipe() {
+    let image = FILE_SYSTEM.lock().file("/bin/cat").expect("program missing").data.clone();
+    let (reader, writer) = ipc::pipe();
+    let mut files = FdTable::new();
+    files.set(STDIN, Descriptor::PipeReader(reader));
+    let pid = process::spawn_with_files(&image, &["/bin/cat"], &[], files).unwrap();
+    thread::sleep(50);
+    assert_eq!(process::try_wait(Some(pid)), Ok(None));
+    process::kill(pid, Signal::Kill).unwrap();
+    assert_eq!(process::wait(Some(pid)), Ok((pid, UserExit::Signaled(Signal::Kill))));
+    drop(writer);
+}
+
+#[test_case]
+fn pipeline_connects_programs() {
+    FILE_SYSTEM.lock().create_file_at("/piped").expect("create failed").data = b"through two pipes\n".to_vec();
+    let image = |path: &str| FILE_SYSTEM.lock().file(path).expect("program missing").data.clone();
+
+    // cat /piped | cat | (this test)
+    let (middle_reader, middle_writer) = ipc::pipe();
+    let (output_reader, output_writer) = ipc::pipe();
+    let mut first = FdTable::new();
+    first.set(STDOUT, Descriptor::PipeWriter(middle_writer));
+    let mut second = FdTable::new();
+    second.set(STDIN, Descriptor::PipeReader(middle_reader));
+    second.set(STDOUT, Descriptor::PipeWriter(output_writer));
+    let first = process::spawn_with_files(&image("/bin/cat"), &["/bin/cat", "/piped"], &[], first).unwrap();
+    let second = process::spawn_with_files(&image("/bin/cat"), &["/bin/cat"], &[], second).unwrap();
+
+    let mut output = Vec::new();
+    let mut buffer = [0u8; 64];
+    loop {
+        let count = output_reader.read(&mut buffer);
+        if count == 0 {
+            break;
+        }
+        output.extend_from_slice(&buffer[..count]);
+    }
+    assert_eq!(output, b"through two pipes\n");
+    assert_eq!(process::wait(Some(first)), Ok((first, UserExit::Exited(0))));
+    assert_eq!(process::wait(Some(second)), Ok((second, UserExit::Exited(0))));
+}
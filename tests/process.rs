@@ -0,0 +1,203 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+mod common;
+
+use bootloader::{entry_point, BootInfo};
+use common::{tiny_elf, HEADERS_SIZE};
+use core::panic::PanicInfo;
+use rustos::process::{self, KillError, ProcessState, WaitError};
+use rustos::signal::Signal;
+use rustos::syscall::Errno;
+use rustos::thread;
+use rustos::usermode::UserExit;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustos::allocator;
+    use rustos::memory::{self, BootInfoFrameAllocator};
+    use rustos::time::{self, TickSource};
+
+    rustos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    time::init(TickSource::Pit, &mut mapper, &mut frame_allocator);
+    memory::install(mapper, frame_allocator);
+    thread::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustos::test_panic_handler(info)
+}
+
+// mov rdi, [rsp]; exit(rdi)
+const EXIT_ARGC: [u8; HEADERS_SIZE + 11] =
+    tiny_elf(&[0x48, 0x8b, 0x3c, 0x24, 0xb8, 0x04, 0x00, 0x00, 0x00, 0x0f, 0x05]);
+
+// exit(getpid())
+const EXIT_PID: [u8; HEADERS_SIZE + 16] =
+    tiny_elf(&[0xb8, 0x06, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x89, 0xc7, 0xb8, 0x04, 0x00, 0x00, 0x00, 0x0f, 0x05]);
+
+// exit(wait(0, 0))
+const EXIT_WAIT: [u8; HEADERS_SIZE + 20] = tiny_elf(&[
+    0xb8, 0x09, 0x00, 0x00, 0x00, 0x31, 0xff, 0x31, 0xf6, 0x0f, 0x05, 0x89, 0xc7, 0xb8, 0x04, 0x00, 0x00, 0x00, 0x0f, 0x05,
+]);
+
+// sigaction(SIGINT, handler); ebx = 0; r12 = rsp; while ebx == 0 {}
+// exit(rsp == r12 ? ebx : 100)
+// handler: ebx = 7; return as the signal frame asks for
+const CATCH_INTERRUPT: [u8; HEADERS_SIZE + 67] = tiny_elf(&[
+    0xb8, 0x0d, 0x00, 0x00, 0x00, 0xbf, 0x02, 0x00, 0x00, 0x00, 0x48, 0x8d, 0x35, 0x25, 0x00, 0x00, 0x00, 0x0f, 0x05,
+    0x31, 0xdb, 0x49, 0x89, 0xe4, 0x85, 0xdb, 0x74, 0xfc, 0x4c, 0x39, 0xe4, 0x75, 0x09,
+    0x89, 0xdf, 0xb8, 0x04, 0x00, 0x00, 0x00, 0x0f, 0x05,
+    0xbf, 0x64, 0x00, 0x00, 0x00, 0xb8, 0x04, 0x00, 0x00, 0x00, 0x0f, 0x05,
+    0xbb, 0x07, 0x00, 0x00, 0x00, 0x48, 0x83, 0xc4, 0x08, 0x9d, 0xc2, 0x80, 0x00,
+]);
+
+// jmp $
+const LOOP: [u8; HEADERS_SIZE + 2] = tiny_elf(&[0xeb, 0xfe]);
+
+// exit(sleep(u64::MAX))
+const SLEEP_FOREVER: [u8; HEADERS_SIZE + 23] = tiny_elf(&[
+    0x48, 0xc7, 0xc7, 0xff, 0xff, 0xff, 0xff, 0xb8, 0x07, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x89, 0xc7, 0xb8, 0x04, 0x00,
+    0x00, 0x00, 0x0f, 0x05,
+]);
+
+fn state(pid: process::Pid) -> Option<ProcessState> {
+    process::processes().into_iter().find(|info| info.pid == pid).map(|info| info.state)
+}
+
+#[test_case]
+fn exit_status_reaches_parent() {
+    let pid = process::spawn(&EXIT_ARGC, &["argc", "a"], &[]).unwrap();
+    assert_eq!(process::wait(Some(pid)), Ok((pid, UserExit::Exited(2))));
+}
+
+#[test_case]
+fn getpid_returns_own_pid() {
+    let pid = process::spawn(&EXIT_PID, &["pid"], &[]).unwrap();
+    assert_eq!(process::wait(Some(pid)), Ok((pid, UserExit::Exited(pid as i32))));
+}
+
+#[test_case]
+fn pids_are_unique() {
+    let first = process::spawn(&EXIT_ARGC, &["argc"], &[]).unwrap();
+    let second = process::spawn(&EXIT_ARGC, &["argc"], &[]).unwrap();
+    assert_ne!(first, second);
+    let mut reaped = [process::wait(None).unwrap().0, process::wait(None).unwrap().0];
+    reaped.sort();
+    assert_eq!(reaped, [first.min(second), first.max(second)]);
+}
+
+#[test_case]
+fn waiting_without_children_fails() {
+    assert_eq!(process::wait(None), Err(WaitError::NoChild));
+    assert_eq!(process::try_wait(None), Err(WaitError::NoChild));
+}
+
+#[test_case]
+fn exited_child_is_zombie_until_reaped() {
+    let pid = process::spawn(&EXIT_ARGC, &["argc"], &[]).unwrap();
+    while state(pid) == Some(ProcessState::Running) {
+        thread::sleep(10);
+    }
+    assert_eq!(state(pid), Some(ProcessState::Zombie(UserExit::Exited(1))));
+    assert_eq!(process::try_wait(Some(pid)), Ok(Some((pid, UserExit::Exited(1)))));
+    assert_eq!(state(pid), None);
+}
+
+#[test_case]
+fn process_without_children_cannot_wait() {
+    let pid = process::spawn(&EXIT_WAIT, &["wait"], &[]).unwrap();
+    assert_eq!(process::wait(Some(pid)), Ok((pid, UserExit::Exited(-(Errno::NoChild as i32)))));
+}
+
+#[test_case]
+fn kill_ends_a_running_process() {
+    let pid = process::spawn(&LOOP, &["loop"], &[]).unwrap();
+    thread::sleep(50);
+    assert_eq!(process::try_wait(Some(pid)), Ok(None));
+    assert_eq!(process::kill(pid, Signal::Kill), Ok(()));
+    assert_eq!(process::wait(Some(pid)), Ok((pid, UserExit::Signaled(Signal::Kill))));
+    assert_eq!(UserExit::Signaled(Signal::Kill).status(), 137);
+}
+
+#[test_case]
+fn kill_ends_a_sleeping_process() {
+    let pid = process::spawn(&SLEEP_FOREVER, &["sleep"], &[]).unwrap();
+    thread::sleep(50);
+    assert_eq!(process::try_wait(Some(pid)), Ok(None));
+    assert_eq!(process::kill(pid, Signal::Kill), Ok(()));
+    assert_eq!(process::wait(Some(pid)), Ok((pid, UserExit::Signaled(Signal::Kill))));
+}
+
+#[test_case]
+fn ignored_signal_does_not_wake_a_sleeping_process() {
+    let pid = process::spawn(&SLEEP_FOREVER, &["sleep"], &[]).unwrap();
+    thread::sleep(20);
+    assert_eq!(process::kill(pid, Signal::Child), Ok(()));
+    thread::sleep(20);
+    assert_eq!(process::try_wait(Some(pid)), Ok(None));
+    process::kill(pid, Signal::Terminate).unwrap();
+    assert_eq!(process::wait(Some(pid)), Ok((pid, UserExit::Signaled(Signal::Terminate))));
+}
+
+#[test_case]
+fn killing_unknown_process_fails() {
+    assert_eq!(process::kill(100_000, Signal::Kill), Err(KillError::NoSuchProcess));
+}
+
+#[test_case]
+fn terminate_ends_a_process_by_default() {
+    let pid = process::spawn(&LOOP, &["loop"], &[]).unwrap();
+    thread::sleep(20);
+    assert_eq!(process::kill(pid, Signal::Terminate), Ok(()));
+    assert_eq!(process::wait(Some(pid)), Ok((pid, UserExit::Signaled(Signal::Terminate))));
+}
+
+#[test_case]
+fn child_signal_is_ignored_by_default() {
+    let pid = process::spawn(&LOOP, &["loop"], &[]).unwrap();
+    assert_eq!(process::kill(pid, Signal::Child), Ok(()));
+    thread::sleep(20);
+    assert_eq!(process::try_wait(Some(pid)), Ok(None));
+    process::kill(pid, Signal::Kill).unwrap();
+    assert_eq!(process::wait(Some(pid)), Ok((pid, UserExit::Signaled(Signal::Kill))));
+}
+
+#[test_case]
+fn handler_runs_and_returns() {
+    let pid = process::spawn(&CATCH_INTERRUPT, &["catch"], &[]).unwrap();
+    thread::sleep(20);
+    assert_eq!(process::kill(pid, Signal::Interrupt), Ok(()));
+    assert_eq!(process::wait(Some(pid)), Ok((pid, UserExit::Exited(7))));
+}
+
+#[test_case]
+fn ctrl_c_interrupts_the_foreground_job() {
+    let pid = process::spawn(&LOOP, &["loop"], &[]).unwrap();
+    process::set_foreground(&[pid]);
+    assert!(process::interrupt_foreground());
+    assert_eq!(process::wait(Some(pid)), Ok((pid, UserExit::Signaled(Signal::Interrupt))));
+    process::set_foreground(&[]);
+    assert!(!process::interrupt_foreground());
+}
+
+#[test_case]
+fn signals_parse_by_name_and_number() {
+    assert_eq!(Signal::parse("INT"), Some(Signal::Interrupt));
+    assert_eq!(Signal::parse("SIGKILL"), Some(Signal::Kill));
+    assert_eq!(Signal::parse("15"), Some(Signal::Terminate));
+    assert_eq!(Signal::parse("HUP"), None);
+}
@@ -0,0 +1,105 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::arch::asm;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+use rustos::memory::USER_START;
+use rustos::profiler::{self, Hotspot};
+use rustos::timer::{self, Action};
+use rustos::{thread, time};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustos::allocator;
+    use rustos::memory::{self, BootInfoFrameAllocator};
+    use rustos::time::TickSource;
+    use x86_64::VirtAddr;
+
+    rustos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    time::init(TickSource::Pit, &mut mapper, &mut frame_allocator);
+    memory::install(mapper, frame_allocator);
+    thread::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustos::test_panic_handler(info)
+}
+
+// Spins in a handful of instructions until `flag` is set.
+#[inline(never)]
+fn spin_until(flag: &AtomicBool) {
+    unsafe {
+        asm!("2:", "pause", "cmp byte ptr [{}], 0", "je 2b", in(reg) flag.as_ptr(), options(nostack, readonly));
+    }
+}
+
+#[test_case]
+fn samples_land_where_the_cpu_spins() {
+    static DONE: AtomicBool = AtomicBool::new(false);
+    fn done(_: usize) {
+        DONE.store(true, Ordering::Relaxed);
+    }
+
+    profiler::start().expect("profiler didn't start");
+    assert!(profiler::is_running());
+    timer::add_ms(200, Action::Call(done, 0));
+    spin_until(&DONE);
+    assert!(profiler::stop());
+
+    let samples = profiler::samples();
+    assert_eq!(samples.len(), profiler::taken());
+    assert!(samples.len() >= 15, "only {} samples", samples.len());
+    let start = spin_until as fn(&AtomicBool) as usize as u64;
+    let spinning = samples.iter().filter(|&&rip| (start..start + 64).contains(&rip)).count();
+    assert!(spinning * 2 > samples.len(), "{} of {} samples in the loop", spinning, samples.len());
+}
+
+#[test_case]
+fn stopped_profiler_takes_no_samples() {
+    profiler::start().expect("profiler didn't start");
+    thread::sleep(50);
+    assert!(profiler::stop());
+    assert!(!profiler::stop());
+    assert!(!profiler::is_running());
+    let taken = profiler::taken();
+    assert!(taken > 0);
+    thread::sleep(50);
+    assert_eq!(profiler::taken(), taken);
+
+    // starting again throws the old samples away
+    profiler::start().expect("profiler didn't start");
+    assert!(profiler::taken() < taken);
+    profiler::stop();
+}
+
+#[test_case]
+fn histogram_puts_the_hottest_address_first() {
+    // user addresses are never symbolized
+    let (a, b, c) = (USER_START + 0x10, USER_START + 0x20, USER_START + 0x30);
+    let histogram = profiler::histogram(&[b, a, b, c, b, a]);
+    assert_eq!(
+        histogram,
+        [
+            Hotspot { function: None, address: b, samples: 3 },
+            Hotspot { function: None, address: a, samples: 2 },
+            Hotspot { function: None, address: c, samples: 1 },
+        ]
+    );
+    assert!(profiler::histogram(&[]).is_empty());
+}
@@ -0,0 +1,61 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::format;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rustos::exec;
+use rustos::fs::FILE_SYSTEM;
+use rustos::usermode::UserExit;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustos::allocator;
+    use rustos::memory::{self, BootInfoFrameAllocator};
+
+    rustos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    // programs run as processes, each in a thread of its own
+    rustos::thread::init();
+    rustos::programs::install();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustos::test_panic_handler(info)
+}
+
+#[test_case]
+fn programs_are_installed() {
+    let file_system = FILE_SYSTEM.lock();
+    for (name, image) in rustos::programs::PROGRAMS {
+        let file = file_system.file(&format!("/bin/{}", name)).expect("program missing");
+        assert_eq!(&file.data[..], *image);
+    }
+}
+
+#[test_case]
+fn hello_exits_cleanly() {
+    assert_eq!(exec::exec_file("/bin/hello", &["/bin/hello", "from", "a", "test"]), Ok(UserExit::Exited(0)));
+}
+
+#[test_case]
+fn cat_reads_files() {
+    FILE_SYSTEM.lock().create_file_at("/greeting").expect("create failed").data = b"hi there\n".to_vec();
+    assert_eq!(exec::exec_file("/bin/cat", &["/bin/cat", "/greeting"]), Ok(UserExit::Exited(0)));
+    assert_eq!(exec::exec_file("/bin/cat", &["/bin/cat", "/missing"]), Ok(UserExit::Exited(1)));
+}
@@ -0,0 +1,59 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rustos::{acpi, smp};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustos::allocator;
+    use rustos::memory::{self, BootInfoFrameAllocator};
+    use rustos::time::{self, TickSource};
+    use x86_64::VirtAddr;
+
+    rustos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    time::init(TickSource::Hpet, &mut mapper, &mut frame_allocator);
+    memory::install(mapper, frame_allocator);
+    smp::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustos::test_panic_handler(info)
+}
+
+// the tests run with `-smp 4`, every processor in the MADT has to come up
+#[test_case]
+fn all_processors_are_online() {
+    let processors = acpi::madt().expect("no MADT").processor_apic_ids().count();
+    assert_eq!(processors, 4);
+    assert_eq!(smp::online_count(), processors);
+}
+
+#[test_case]
+fn bsp_is_cpu_zero() {
+    let bsp = smp::cpus().next().expect("no CPUs");
+    assert_eq!(bsp.apic_id(), rustos::apic::id());
+}
+
+#[test_case]
+fn apic_ids_are_unique() {
+    let cpus: alloc::vec::Vec<u8> = smp::cpus().map(|cpu| cpu.apic_id()).collect();
+    for (i, id) in cpus.iter().enumerate() {
+        assert!(!cpus[i + 1..].contains(id));
+    }
+}
@@ -0,0 +1,149 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use rustos::sync::{Condvar, Mutex, RwLock, Semaphore};
+use rustos::thread::{self, State};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustos::allocator;
+    use rustos::memory::{self, BootInfoFrameAllocator};
+    use rustos::time::{self, TickSource};
+    use x86_64::VirtAddr;
+
+    rustos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    time::init(TickSource::Pit, &mut mapper, &mut frame_allocator);
+    memory::install(mapper, frame_allocator);
+    thread::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustos::test_panic_handler(info)
+}
+
+fn join_all(ids: Vec<thread::ThreadId>) {
+    for id in ids {
+        thread::join(id);
+    }
+}
+
+#[test_case]
+fn mutex_serializes_updates() {
+    static COUNTER: Mutex<u64> = Mutex::new(0);
+    let ids = (0..4)
+        .map(|_| {
+            thread::spawn("adder", || {
+                for _ in 0..50 {
+                    let mut counter = COUNTER.lock();
+                    let value = *counter;
+                    // let the others try to get in
+                    thread::yield_now();
+                    *counter = value + 1;
+                }
+            })
+        })
+        .collect();
+    join_all(ids);
+    assert_eq!(*COUNTER.lock(), 200);
+}
+
+#[test_case]
+fn mutex_waiter_is_blocked() {
+    static LOCK: Mutex<()> = Mutex::new(());
+    let guard = LOCK.lock();
+    let id = thread::spawn("waiter", || drop(LOCK.lock()));
+    thread::sleep(20);
+    let state = thread::threads().into_iter().find(|info| info.id == id).map(|info| info.state);
+    assert_eq!(state, Some(State::Blocked));
+    drop(guard);
+    thread::join(id);
+}
+
+#[test_case]
+fn semaphore_limits_concurrency() {
+    static PERMITS: Semaphore = Semaphore::new(2);
+    static INSIDE: AtomicUsize = AtomicUsize::new(0);
+    static MOST: AtomicUsize = AtomicUsize::new(0);
+    let ids = (0..5)
+        .map(|_| {
+            thread::spawn("worker", || {
+                PERMITS.acquire();
+                let inside = INSIDE.fetch_add(1, Ordering::Relaxed) + 1;
+                MOST.fetch_max(inside, Ordering::Relaxed);
+                thread::sleep(10);
+                INSIDE.fetch_sub(1, Ordering::Relaxed);
+                PERMITS.release();
+            })
+        })
+        .collect();
+    join_all(ids);
+    assert_eq!(MOST.load(Ordering::Relaxed), 2);
+    assert_eq!(PERMITS.available(), 2);
+}
+
+#[test_case]
+fn condvar_hands_over_items() {
+    static QUEUE: Mutex<VecDeque<u64>> = Mutex::new(VecDeque::new());
+    static ITEMS: Condvar = Condvar::new();
+    static SUM: AtomicUsize = AtomicUsize::new(0);
+    let consumer = thread::spawn("consumer", || {
+        for _ in 0..10 {
+            let mut queue = ITEMS.wait_while(QUEUE.lock(), |queue| queue.is_empty());
+            let item = queue.pop_front().unwrap();
+            SUM.fetch_add(item as usize, Ordering::Relaxed);
+        }
+    });
+    for item in 1..=10 {
+        QUEUE.lock().push_back(item);
+        ITEMS.notify_one();
+        if item % 3 == 0 {
+            thread::sleep(10);
+        }
+    }
+    thread::join(consumer);
+    assert_eq!(SUM.load(Ordering::Relaxed), 55);
+}
+
+#[test_case]
+fn rwlock_shares_between_readers() {
+    static LOCK: RwLock<u64> = RwLock::new(0);
+    static READING: AtomicUsize = AtomicUsize::new(0);
+    static MOST: AtomicUsize = AtomicUsize::new(0);
+    let readers: Vec<_> = (0..3)
+        .map(|_| {
+            thread::spawn("reader", || {
+                let _value = LOCK.read();
+                let reading = READING.fetch_add(1, Ordering::Relaxed) + 1;
+                MOST.fetch_max(reading, Ordering::Relaxed);
+                thread::sleep(20);
+                READING.fetch_sub(1, Ordering::Relaxed);
+            })
+        })
+        .collect();
+    // let the readers get in before the writer
+    thread::sleep(5);
+    *LOCK.write() += 1;
+    assert_eq!(READING.load(Ordering::Relaxed), 0, "writer got in while readers held the lock");
+    join_all(readers);
+    assert!(MOST.load(Ordering::Relaxed) > 1);
+    assert_eq!(*LOCK.read(), 1);
+}
@@ -0,0 +1,89 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rustos::memory::{self, USER_START};
+use rustos::syscall::Errno;
+use rustos::process;
+use rustos::usermode::UserExit;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustos::allocator;
+    use rustos::memory::BootInfoFrameAllocator;
+
+    rustos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustos::test_panic_handler(info)
+}
+
+// Maps a code page holding `code` and a stack page below it at `base`, and runs the code
+// as a process.
+fn run_user_code(base: u64, code: &[u8]) -> UserExit {
+    let stack = VirtAddr::new(USER_START + base);
+    let text = stack + 4096u64;
+    memory::map_user_pages(stack, 1, PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE).unwrap();
+    memory::map_user_pages(text, 1, PageTableFlags::WRITABLE).unwrap();
+    unsafe { core::ptr::copy_nonoverlapping(code.as_ptr(), text.as_mut_ptr::<u8>(), code.len()) };
+    process::run("test", text, stack + 4096u64)
+}
+
+#[test_case]
+fn write_returns_length() {
+    // write(1, "hello\n", 6); exit(result)
+    let code = [
+        0xb8, 0x01, 0x00, 0x00, 0x00, 0xbf, 0x01, 0x00, 0x00, 0x00, 0x48, 0x8d, 0x35, 0x10, 0x00, 0x00, 0x00,
+        0xba, 0x06, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x89, 0xc7, 0xb8, 0x04, 0x00, 0x00, 0x00, 0x0f, 0x05,
+        b'h', b'e', b'l', b'l', b'o', b'\n',
+    ];
+    assert_eq!(run_user_code(0x0000, &code), UserExit::Exited(6));
+}
+
+#[test_case]
+fn getpid_is_nonzero() {
+    // exit(getpid())
+    let code = [0xb8, 0x06, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x89, 0xc7, 0xb8, 0x04, 0x00, 0x00, 0x00, 0x0f, 0x05];
+    match run_user_code(0x2000, &code) {
+        UserExit::Exited(pid) => assert!(pid > 0),
+        exit => panic!("unexpected {:?}", exit),
+    }
+}
+
+#[test_case]
+fn kernel_pointers_are_rejected() {
+    // exit(write(1, 0x200000, 16))
+    let code = [
+        0xb8, 0x01, 0x00, 0x00, 0x00, 0xbf, 0x01, 0x00, 0x00, 0x00, 0xbe, 0x00, 0x00, 0x20, 0x00,
+        0xba, 0x10, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x89, 0xc7, 0xb8, 0x04, 0x00, 0x00, 0x00, 0x0f, 0x05,
+    ];
+    assert_eq!(run_user_code(0x4000, &code), UserExit::Exited(-(Errno::BadAddress as i32)));
+}
+
+#[test_case]
+fn sbrk_maps_heap_memory() {
+    // p = sbrk(4096); p[4095] = 7; exit(p[4095])
+    let code = [
+        0xb8, 0x05, 0x00, 0x00, 0x00, 0xbf, 0x00, 0x10, 0x00, 0x00, 0x0f, 0x05,
+        0xc6, 0x80, 0xff, 0x0f, 0x00, 0x00, 0x07, 0x0f, 0xb6, 0xb8, 0xff, 0x0f, 0x00, 0x00,
+        0xb8, 0x04, 0x00, 0x00, 0x00, 0x0f, 0x05,
+    ];
+    assert_eq!(run_user_code(0x6000, &code), UserExit::Exited(7));
+}
@@ -0,0 +1,136 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use rustos::thread::{self, Priority, State};
+use spin::Mutex;
+use rustos::time;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustos::allocator;
+    use rustos::memory::{self, BootInfoFrameAllocator};
+    use rustos::time::TickSource;
+    use x86_64::VirtAddr;
+
+    rustos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    time::init(TickSource::Pit, &mut mapper, &mut frame_allocator);
+    memory::install(mapper, frame_allocator);
+    thread::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustos::test_panic_handler(info)
+}
+
+#[test_case]
+fn spawn_and_join() {
+    static RAN: AtomicBool = AtomicBool::new(false);
+    let id = thread::spawn("test", || RAN.store(true, Ordering::Relaxed));
+    thread::join(id);
+    assert!(RAN.load(Ordering::Relaxed));
+    // reaped once another thread got scheduled
+    thread::yield_now();
+    assert!(thread::threads().iter().all(|info| info.id != id));
+}
+
+#[test_case]
+fn threads_are_preempted() {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    static STOP: AtomicBool = AtomicBool::new(false);
+    let id = thread::spawn("spinner", || {
+        while !STOP.load(Ordering::Relaxed) {
+            COUNTER.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+    // neither thread yields, only the timer can switch between them
+    while COUNTER.load(Ordering::Relaxed) == 0 {
+        core::hint::spin_loop();
+    }
+    STOP.store(true, Ordering::Relaxed);
+    thread::join(id);
+}
+
+#[test_case]
+fn sleep_blocks_for_the_duration() {
+    let start = time::uptime_ms();
+    thread::sleep(50);
+    assert!(time::uptime_ms() - start >= 50);
+}
+
+#[test_case]
+fn sleeping_thread_is_listed() {
+    let id = thread::spawn("sleeper", || thread::sleep(100));
+    thread::yield_now();
+    let state = thread::threads().into_iter().find(|info| info.id == id).map(|info| info.state);
+    assert!(matches!(state, Some(State::Sleeping(_))));
+    thread::join(id);
+}
+
+#[test_case]
+fn many_threads() {
+    static SUM: AtomicU64 = AtomicU64::new(0);
+    // the stacks of finished workers get reused in later rounds
+    for _ in 0..4 {
+        let ids: Vec<_> = (1..=8).map(|i| thread::spawn("worker", move || {
+            SUM.fetch_add(i, Ordering::Relaxed);
+        })).collect();
+        for id in ids {
+            thread::join(id);
+        }
+    }
+    assert_eq!(SUM.load(Ordering::Relaxed), 4 * 36);
+}
+
+#[test_case]
+fn higher_priority_runs_first() {
+    static ORDER: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+    let low = thread::spawn_with_priority("low", Priority::Low, || ORDER.lock().push("low"));
+    let high = thread::spawn_with_priority("high", Priority::High, || ORDER.lock().push("high"));
+    // the low priority thread only gets a turn while this one is blocked
+    thread::sleep(20);
+    thread::join(low);
+    thread::join(high);
+    assert_eq!(*ORDER.lock(), ["high", "low"]);
+}
+
+#[test_case]
+fn cpu_time_is_charged() {
+    let ticks = || thread::threads().into_iter().find(|info| info.id == thread::current()).unwrap().ticks;
+    let before = ticks();
+    let end = time::uptime_ms() + 100;
+    while time::uptime_ms() < end {
+        core::hint::spin_loop();
+    }
+    // 10 ticks at 100 Hz, minus some for the boundaries
+    assert!(ticks() - before >= 8);
+}
+
+#[test_case]
+fn priority_can_change() {
+    let id = thread::spawn("sleeper", || thread::sleep(50));
+    assert!(thread::set_priority(id, Priority::High));
+    let priority = thread::threads().into_iter().find(|info| info.id == id).map(|info| info.priority);
+    assert_eq!(priority, Some(Priority::High));
+    thread::join(id);
+    // gone once reaped
+    thread::yield_now();
+    assert!(!thread::set_priority(id, Priority::Low));
+}
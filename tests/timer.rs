@@ -0,0 +1,228 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use rustos::ipc::{channel, RecvTimeoutError};
+use rustos::sync::{Condvar, Mutex, Semaphore};
+use rustos::task::delay::{self, Delay};
+use rustos::task::executor::Executor;
+use rustos::task::Task;
+use rustos::timer::{self, Action, TimerWheel};
+use rustos::{thread, time};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustos::allocator;
+    use rustos::memory::{self, BootInfoFrameAllocator};
+    use rustos::time::TickSource;
+    use x86_64::VirtAddr;
+
+    rustos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    time::init(TickSource::Pit, &mut mapper, &mut frame_allocator);
+    memory::install(mapper, frame_allocator);
+    thread::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustos::test_panic_handler(info)
+}
+
+// Advances `wheel` one tick at a time, returning (tick, value) of what expired.
+fn run_wheel(wheel: &mut TimerWheel<u64>, until: u64) -> Vec<(u64, u64)> {
+    let mut fired = Vec::new();
+    for now in 0..=until {
+        wheel.advance(now);
+        while let Some(value) = wheel.pop_expired() {
+            fired.push((now, value));
+        }
+    }
+    fired
+}
+
+#[test_case]
+fn wheel_fires_timers_at_their_tick_across_levels() {
+    let mut wheel = TimerWheel::new();
+    // on both sides of every level boundary
+    let expiries = [0, 1, 63, 64, 65, 4095, 4096, 5000, 262_143, 262_144, 300_000];
+    for &expires in expiries.iter().rev() {
+        wheel.add(expires, expires);
+    }
+    assert_eq!(wheel.len(), expiries.len());
+    let fired = run_wheel(&mut wheel, 300_000);
+    let expected: Vec<(u64, u64)> = expiries.iter().map(|&expires| (expires, expires)).collect();
+    assert_eq!(fired, expected);
+    assert!(wheel.is_empty());
+}
+
+#[test_case]
+fn wheel_cancels_and_reschedules() {
+    let mut wheel = TimerWheel::new();
+    let cancelled = wheel.add(10, 1);
+    let moved = wheel.add(5000, 2);
+    let kept = wheel.add(20, 3);
+    assert_eq!(wheel.cancel(cancelled), Some(1));
+    assert_eq!(wheel.cancel(cancelled), None);
+    assert!(wheel.reschedule(moved, 15));
+    assert_eq!(run_wheel(&mut wheel, 100), [(15, 2), (20, 3)]);
+    // expired timers can be neither cancelled nor moved, even once the entry is reused
+    assert!(!wheel.reschedule(kept, 200));
+    wheel.add(150, 4);
+    assert_eq!(wheel.cancel(kept), None);
+}
+
+#[test_case]
+fn wheel_fires_past_timers_right_away() {
+    let mut wheel = TimerWheel::new();
+    wheel.advance(100);
+    wheel.add(50, 7);
+    assert_eq!(wheel.pop_expired(), Some(7));
+}
+
+#[test_case]
+fn timers_call_their_function() {
+    static CALLED_AT: AtomicU64 = AtomicU64::new(0);
+    fn record(_: usize) {
+        CALLED_AT.store(time::ticks(), Ordering::Relaxed);
+    }
+
+    let start = time::ticks();
+    timer::add(start + 5, Action::Call(record, 0));
+    let cancelled = timer::add_ms(20, Action::Call(record, 0));
+    assert!(timer::cancel(cancelled));
+    thread::sleep(100);
+    assert_eq!(CALLED_AT.load(Ordering::Relaxed), start + 5);
+    assert!(!timer::cancel(cancelled));
+}
+
+#[test_case]
+fn sleep_lasts_long_enough() {
+    let before = timer::pending();
+    let start = time::uptime_ms();
+    thread::sleep(30);
+    assert!(time::uptime_ms() - start >= 30);
+    assert_eq!(timer::pending(), before);
+}
+
+#[test_case]
+fn endless_timeouts_do_not_overflow() {
+    assert!(time::ms_to_ticks(u64::MAX) > 0);
+    let before = timer::pending();
+    // never woken, it just mustn't take the kernel down on the way to sleep
+    thread::spawn("forever", || thread::sleep(u64::MAX));
+    thread::sleep(20);
+    assert_eq!(timer::pending(), before + 1);
+
+    static SEMAPHORE: Semaphore = Semaphore::new(0);
+    let id = thread::spawn("releaser", || SEMAPHORE.release());
+    assert!(SEMAPHORE.acquire_timeout(u64::MAX));
+    thread::join(id);
+
+    let (sender, receiver) = channel(1);
+    sender.send(1u32).unwrap();
+    assert_eq!(receiver.recv_timeout(u64::MAX), Ok(1));
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+        Delay::new(u64::MAX).await;
+    }));
+    executor.run_until_idle();
+    assert_eq!(executor.task_count(), 1);
+}
+
+#[test_case]
+fn many_sleepers_wake_up() {
+    static AWAKE: AtomicUsize = AtomicUsize::new(0);
+    let ids: Vec<_> = (0..20)
+        .map(|i| {
+            thread::spawn("sleeper", move || {
+                thread::sleep(10 + i * 5);
+                AWAKE.fetch_add(1, Ordering::Relaxed);
+            })
+        })
+        .collect();
+    for id in ids {
+        thread::join(id);
+    }
+    assert_eq!(AWAKE.load(Ordering::Relaxed), 20);
+}
+
+#[test_case]
+fn semaphore_acquire_times_out() {
+    static SEMAPHORE: Semaphore = Semaphore::new(0);
+    let start = time::uptime_ms();
+    assert!(!SEMAPHORE.acquire_timeout(30));
+    assert!(time::uptime_ms() - start >= 30);
+
+    let id = thread::spawn("releaser", || {
+        thread::sleep(10);
+        SEMAPHORE.release();
+    });
+    assert!(SEMAPHORE.acquire_timeout(1000));
+    thread::join(id);
+}
+
+#[test_case]
+fn condvar_wait_times_out() {
+    static READY: Mutex<bool> = Mutex::new(false);
+    static CHANGED: Condvar = Condvar::new();
+    let (guard, timed_out) = CHANGED.wait_timeout(READY.lock(), 20);
+    assert!(timed_out);
+    drop(guard);
+
+    let id = thread::spawn("notifier", || {
+        *READY.lock() = true;
+        CHANGED.notify_one();
+    });
+    let mut guard = READY.lock();
+    while !*guard {
+        let (next, timed_out) = CHANGED.wait_timeout(guard, 1000);
+        assert!(!timed_out);
+        guard = next;
+    }
+    drop(guard);
+    thread::join(id);
+}
+
+#[test_case]
+fn receive_times_out() {
+    let (sender, receiver) = channel(1);
+    assert_eq!(receiver.recv_timeout(20), Err(RecvTimeoutError::Timeout));
+    sender.send(5u32).unwrap();
+    assert_eq!(receiver.recv_timeout(20), Ok(5));
+    drop(sender);
+    assert_eq!(receiver.recv_timeout(20), Err(RecvTimeoutError::Disconnected));
+}
+
+#[test_case]
+fn delay_wakes_its_task() {
+    static DONE_AT: AtomicU64 = AtomicU64::new(0);
+    let start = time::uptime_ms();
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+        Delay::new(20).await;
+        delay::sleep(20).await;
+        DONE_AT.store(time::uptime_ms(), Ordering::Relaxed);
+    }));
+    while executor.task_count() > 0 {
+        executor.run_until_idle();
+        x86_64::instructions::hlt();
+    }
+    assert!(DONE_AT.load(Ordering::Relaxed) - start >= 40);
+}
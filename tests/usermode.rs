@@ -0,0 +1,85 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rustos::interrupts;
+use rustos::memory::{self, USER_START};
+use rustos::usermode::{self, UserExit};
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustos::allocator;
+    use rustos::memory::BootInfoFrameAllocator;
+
+    rustos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustos::test_panic_handler(info)
+}
+
+// Maps a code page holding `code` and a stack page below it at `base`, and runs the code.
+fn run_user_code(base: u64, code: &[u8]) -> UserExit {
+    let stack = VirtAddr::new(USER_START + base);
+    let text = stack + 4096u64;
+    memory::map_user_pages(stack, 1, PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE).unwrap();
+    memory::map_user_pages(text, 1, PageTableFlags::WRITABLE).unwrap();
+    unsafe { core::ptr::copy_nonoverlapping(code.as_ptr(), text.as_mut_ptr::<u8>(), code.len()) };
+    usermode::run(text, stack + 4096u64)
+}
+
+#[test_case]
+fn hlt_faults_in_user_mode() {
+    // hlt
+    assert_eq!(run_user_code(0x0000, &[0xf4]), UserExit::Fault(13));
+}
+
+#[test_case]
+fn cli_faults_in_user_mode() {
+    // nop, cli
+    assert_eq!(run_user_code(0x2000, &[0x90, 0xfa]), UserExit::Fault(13));
+}
+
+#[test_case]
+fn kernel_memory_is_not_accessible() {
+    // movabs rax, [0x200000], where the kernel is loaded
+    let code = [0x48, 0xa1, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00];
+    assert_eq!(run_user_code(0x4000, &code), UserExit::Fault(14));
+}
+
+#[test_case]
+fn floating_point_exceptions_kill_the_program() {
+    let (x87_before, simd_before) = (interrupts::vector_count(16), interrupts::vector_count(19));
+    // sub rsp, 8; fninit; mov word [rsp], 0x37e (invalid operation unmasked); fldcw [rsp];
+    // fldz; fdiv st0, st0; fwait
+    let x87 = [
+        0x48, 0x83, 0xec, 0x08, 0xdb, 0xe3, 0x66, 0xc7, 0x04, 0x24, 0x7e, 0x03, 0xd9, 0x2c, 0x24, 0xd9, 0xee,
+        0xd8, 0xf0, 0x9b,
+    ];
+    assert_eq!(run_user_code(0x6000, &x87), UserExit::Fault(16));
+    // sub rsp, 8; stmxcsr [rsp]; and dword [rsp], !0x80 (invalid operation unmasked);
+    // ldmxcsr [rsp]; xorps xmm0, xmm0; divss xmm0, xmm0
+    let simd = [
+        0x48, 0x83, 0xec, 0x08, 0x0f, 0xae, 0x1c, 0x24, 0x81, 0x24, 0x24, 0x7f, 0xff, 0xff, 0xff, 0x0f, 0xae, 0x14,
+        0x24, 0x0f, 0x57, 0xc0, 0xf3, 0x0f, 0x5e, 0xc0,
+    ];
+    assert_eq!(run_user_code(0x8000, &simd), UserExit::Fault(19));
+    assert_eq!(interrupts::vector_count(16), x87_before + 1);
+    assert_eq!(interrupts::vector_count(19), simd_before + 1);
+}
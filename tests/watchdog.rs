@@ -0,0 +1,156 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rustos::sync::{spinlock, Semaphore, SpinLock};
+use rustos::thread::{self, Priority};
+use rustos::fs::FILE_SYSTEM;
+use rustos::{time, watchdog};
+use x86_64::instructions::interrupts;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustos::allocator;
+    use rustos::memory::{self, BootInfoFrameAllocator};
+    use rustos::time::TickSource;
+    use x86_64::VirtAddr;
+
+    rustos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    time::init(TickSource::Pit, &mut mapper, &mut frame_allocator);
+    memory::install(mapper, frame_allocator);
+    thread::init();
+    watchdog::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustos::test_panic_handler(info)
+}
+
+// Runs a high priority thread that doesn't give up the CPU for `ms` milliseconds,
+// leaving the calling thread ready but waiting.
+fn hog_cpu(ms: u64) {
+    let id = thread::spawn_with_priority("hog", Priority::High, move || {
+        let until = time::uptime_ms() + ms;
+        while time::uptime_ms() < until {
+            core::hint::spin_loop();
+        }
+    });
+    thread::join(id);
+}
+
+#[test_case]
+fn sleeping_threads_are_no_lockup() {
+    watchdog::set_timeout(Some(50));
+    let before = watchdog::lockups();
+    thread::sleep(200);
+    assert_eq!(watchdog::lockups(), before);
+    watchdog::set_timeout(Some(watchdog::DEFAULT_TIMEOUT_MS));
+}
+
+#[test_case]
+fn threads_waking_up_after_the_timeout_are_no_lockup() {
+    static DONE: Semaphore = Semaphore::new(0);
+    watchdog::set_timeout(Some(50));
+    let before = watchdog::lockups();
+    // woken by a timer and by another thread, both long after the timeout
+    let sleeper = thread::spawn("sleeper", || thread::sleep(200));
+    let waiter = thread::spawn("waiter", || DONE.acquire());
+    thread::sleep(200);
+    DONE.release();
+    thread::join(sleeper);
+    thread::join(waiter);
+    assert_eq!(watchdog::lockups(), before);
+    watchdog::set_timeout(Some(watchdog::DEFAULT_TIMEOUT_MS));
+}
+
+#[test_case]
+fn starved_thread_is_reported_once() {
+    watchdog::set_timeout(Some(50));
+    let before = watchdog::lockups();
+    hog_cpu(300);
+    assert_eq!(watchdog::lockups(), before + 1);
+    watchdog::set_timeout(Some(watchdog::DEFAULT_TIMEOUT_MS));
+}
+
+#[test_case]
+fn disabled_watchdog_reports_nothing() {
+    watchdog::set_timeout(None);
+    assert_eq!(watchdog::timeout(), None);
+    let before = watchdog::lockups();
+    hog_cpu(100);
+    assert_eq!(watchdog::lockups(), before);
+    watchdog::set_timeout(Some(watchdog::DEFAULT_TIMEOUT_MS));
+}
+
+#[test_case]
+fn stopped_system_tick_is_reported_once() {
+    // what the application processor's timer does, here without one
+    let check_every = |ms| {
+        let until = time::uptime_ms() + ms;
+        while time::uptime_ms() < until {
+            core::hint::spin_loop();
+        }
+        watchdog::check_system_tick();
+    };
+    watchdog::set_timeout(Some(3 * watchdog::TICK_CHECK_MS));
+    let before = watchdog::tick_stalls();
+    for _ in 0..5 {
+        check_every(20);
+    }
+    assert_eq!(watchdog::tick_stalls(), before);
+    interrupts::without_interrupts(|| {
+        for _ in 0..6 {
+            watchdog::check_system_tick();
+        }
+    });
+    assert_eq!(watchdog::tick_stalls(), before + 1);
+    watchdog::set_timeout(Some(watchdog::DEFAULT_TIMEOUT_MS));
+}
+
+#[test_case]
+fn file_system_lock_is_tracked() {
+    let address = {
+        let _file_system = FILE_SYSTEM.lock();
+        let mut held = None;
+        spinlock::held_locks(|lock| {
+            if lock.location.file() == file!() {
+                held = Some(lock.address);
+            }
+        });
+        held
+    };
+    assert!(address.is_some());
+    let mut still_held = false;
+    spinlock::held_locks(|lock| still_held |= Some(lock.address) == address);
+    assert!(!still_held);
+}
+
+#[test_case]
+fn longest_spin_lock_hold_is_tracked() {
+    static LOCK: SpinLock<u64> = SpinLock::new(0);
+    {
+        let mut value = LOCK.lock();
+        // long enough to beat whatever was held before
+        for _ in 0..10_000_000 {
+            *value = core::hint::black_box(*value + 1);
+        }
+    }
+    let (cycles, location) = spinlock::longest_hold().expect("no hold recorded");
+    assert!(cycles > 0);
+    assert_eq!(location.file(), file!());
+}
@@ -0,0 +1,140 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use rustos::irq::{self, IrqReturn};
+use rustos::workqueue::{self, Work};
+use rustos::{thread, time};
+use x86_64::instructions::interrupts;
+use x86_64::structures::idt::InterruptStackFrame;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustos::allocator;
+    use rustos::memory::{self, BootInfoFrameAllocator};
+    use rustos::time::TickSource;
+    use x86_64::VirtAddr;
+
+    rustos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    time::init(TickSource::Pit, &mut mapper, &mut frame_allocator);
+    memory::install(mapper, frame_allocator);
+    thread::init();
+    workqueue::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustos::test_panic_handler(info)
+}
+
+fn wait_for(work: &Work, runs: u64) {
+    let deadline = time::uptime_ms() + 1000;
+    while work.runs() < runs {
+        assert!(time::uptime_ms() < deadline, "{} didn't run", work.name());
+        thread::sleep(5);
+    }
+}
+
+#[test_case]
+fn work_runs_in_the_worker_thread() {
+    static RAN_IN: AtomicU64 = AtomicU64::new(0);
+    static WORK: Work = Work::new("worker", || RAN_IN.store(thread::current(), Ordering::Relaxed));
+
+    assert!(WORK.schedule());
+    wait_for(&WORK, 1);
+    assert_eq!(Some(RAN_IN.load(Ordering::Relaxed)), workqueue::worker());
+}
+
+#[test_case]
+fn interrupt_handlers_defer_work() {
+    static SCHEDULED: AtomicBool = AtomicBool::new(false);
+    static INTERRUPTS_ENABLED: AtomicBool = AtomicBool::new(false);
+    static WORK: Work = Work::new("bottom half", || {
+        INTERRUPTS_ENABLED.store(x86_64::instructions::interrupts::are_enabled(), Ordering::Relaxed)
+    });
+
+    fn on_timer(_stack_frame: &InterruptStackFrame) -> IrqReturn {
+        if !SCHEDULED.swap(true, Ordering::Relaxed) {
+            WORK.schedule();
+        }
+        IrqReturn::NotHandled
+    }
+
+    let id = irq::register(time::TIMER_IRQ, "test", &on_timer).expect("register failed");
+    wait_for(&WORK, 1);
+    irq::unregister(id);
+    assert!(INTERRUPTS_ENABLED.load(Ordering::Relaxed));
+}
+
+#[test_case]
+fn delayed_work_waits_for_its_time() {
+    static RAN_AT: AtomicU64 = AtomicU64::new(0);
+    static WORK: Work = Work::new("delayed", || RAN_AT.store(time::uptime_ms(), Ordering::Relaxed));
+
+    let start = time::uptime_ms();
+    assert!(WORK.schedule_delayed(50));
+    assert!(!WORK.schedule_delayed(50));
+    thread::sleep(10);
+    assert_eq!(WORK.runs(), 0);
+    wait_for(&WORK, 1);
+    assert!(RAN_AT.load(Ordering::Relaxed) - start >= 50);
+}
+
+#[test_case]
+fn cancelled_work_does_not_run() {
+    static WORK: Work = Work::new("cancelled", || {});
+
+    assert!(WORK.schedule_delayed(30));
+    assert!(WORK.cancel());
+    assert!(!WORK.cancel());
+    thread::sleep(60);
+    assert_eq!(WORK.runs(), 0);
+    assert!(!WORK.is_pending());
+}
+
+#[test_case]
+fn cancelled_work_can_be_delayed_again() {
+    static RAN_AT: AtomicU64 = AtomicU64::new(0);
+    static WORK: Work = Work::new("delayed again", || RAN_AT.store(time::uptime_ms(), Ordering::Relaxed));
+
+    // queued, but taken back before the worker gets to it
+    interrupts::without_interrupts(|| {
+        assert!(WORK.schedule());
+        assert!(WORK.cancel());
+    });
+    let start = time::uptime_ms();
+    assert!(WORK.schedule_delayed(50));
+    thread::sleep(20);
+    assert_eq!(WORK.runs(), 0);
+    wait_for(&WORK, 1);
+    assert!(RAN_AT.load(Ordering::Relaxed) - start >= 50);
+}
+
+#[test_case]
+fn work_can_reschedule_itself() {
+    static WORK: Work = Work::new("periodic", || {
+        if WORK.runs() < 2 {
+            WORK.schedule_delayed(10);
+        }
+    });
+
+    WORK.schedule();
+    wait_for(&WORK, 3);
+    thread::sleep(30);
+    assert_eq!(WORK.runs(), 3);
+}
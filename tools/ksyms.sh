@@ -0,0 +1,27 @@
+#!/bin/sh
+# Builds the kernel twice to embed its own symbol table for backtraces:
+# the first pass produces the ELF that `nm` reads the function addresses from,
+# the second pass embeds them into the space reserved by build.rs.
+#
+# usage: tools/ksyms.sh <cargo subcommand> [args], e.g. `tools/ksyms.sh run --release`
+set -e
+
+cd "$(dirname "$0")/.."
+SUBCOMMAND=${1:-build}
+[ $# -gt 0 ] && shift
+
+PROFILE=debug
+for arg in "$@"; do
+    [ "$arg" = "--release" ] && PROFILE=release
+done
+
+cargo build "$@"
+KERNEL=target/x86_64-rustos/$PROFILE/rustos
+SYMBOLS=target/x86_64-rustos/$PROFILE/ksyms.txt
+
+# keep only code symbols: "<16 hex digit address> <demangled name>"
+nm -n -C --defined-only "$KERNEL" \
+    | awk '$2 ~ /^[tTwW]$/ { addr = $1; $1 = ""; $2 = ""; sub(/^ +/, ""); print addr, $0 }' \
+    > "$SYMBOLS"
+
+RUSTOS_KSYMS="$PWD/$SYMBOLS" cargo "$SUBCOMMAND" "$@"
@@ -0,0 +1,9 @@
+use std::{env, path::PathBuf};
+
+fn main() {
+    // link the programs at the start of the user address range
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let script = manifest_dir.join("../linker.ld");
+    println!("cargo:rerun-if-changed={}", script.display());
+    println!("cargo:rustc-link-arg-bins=-T{}", script.display());
+}
@@ -0,0 +1,43 @@
+// Prints the files given as arguments, or copies standard input until it ends
+// without any, e.g. at the end of a pipeline.
+#![no_std]
+#![no_main]
+
+use ulib::io::{self, STDIN, STDOUT};
+use ulib::syscall;
+use ulib::{entry, eprintln, fs};
+
+entry!(main);
+
+fn main(args: &[&'static str]) -> i32 {
+    if args.len() < 2 {
+        let mut buffer = [0u8; 512];
+        loop {
+            match syscall::read(STDIN, &mut buffer) {
+                Ok(0) => return 0,
+                Ok(count) => {
+                    if io::write_all(STDOUT, &buffer[..count]).is_err() {
+                        return 1;
+                    }
+                }
+                Err(err) => {
+                    eprintln!("cat: {}", err.description());
+                    return 1;
+                }
+            }
+        }
+    }
+    let mut status = 0;
+    for path in &args[1..] {
+        match fs::read(path) {
+            Ok(data) => {
+                let _ = io::write_all(STDOUT, &data);
+            }
+            Err(err) => {
+                eprintln!("cat: {}: {}", path, err.description());
+                status = 1;
+            }
+        }
+    }
+    status
+}
@@ -0,0 +1,21 @@
+// Greets from user mode and shows what the runtime provides: the arguments, the
+// process ID and a heap.
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use ulib::{entry, println};
+
+entry!(main);
+
+fn main(args: &[&'static str]) -> i32 {
+    println!("Hello from user mode! (pid {})", ulib::getpid());
+    if args.len() > 1 {
+        let rest: Vec<String> = args[1..].iter().map(|arg| String::from(*arg)).collect();
+        println!("Arguments: {}", rest.join(" "));
+    }
+    0
+}
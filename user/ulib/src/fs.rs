@@ -0,0 +1,65 @@
+use alloc::vec::Vec;
+use crate::syscall::{self, Errno, OPEN_APPEND, OPEN_CREATE, OPEN_TRUNCATE};
+
+// A file in the kernel's file system, closed when dropped.
+pub struct File {
+    fd: u64,
+}
+
+impl File {
+    // Opens an existing file for reading and writing from its start.
+    pub fn open(path: &str) -> Result<File, Errno> {
+        syscall::open(path, 0).map(|fd| File { fd })
+    }
+
+    // Opens `path` emptied, creating it if needed.
+    pub fn create(path: &str) -> Result<File, Errno> {
+        syscall::open(path, OPEN_CREATE | OPEN_TRUNCATE).map(|fd| File { fd })
+    }
+
+    // Opens `path` for writing at its end, creating it if needed.
+    pub fn append(path: &str) -> Result<File, Errno> {
+        syscall::open(path, OPEN_CREATE | OPEN_APPEND).map(|fd| File { fd })
+    }
+
+    pub fn fd(&self) -> u64 {
+        self.fd
+    }
+
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Errno> {
+        syscall::read(self.fd, buffer)
+    }
+
+    pub fn write(&mut self, buffer: &[u8]) -> Result<usize, Errno> {
+        syscall::write(self.fd, buffer)
+    }
+
+    // Reads from the current offset to the end of the file.
+    pub fn read_to_end(&mut self) -> Result<Vec<u8>, Errno> {
+        let mut data = Vec::new();
+        let mut buffer = [0u8; 512];
+        loop {
+            match self.read(&mut buffer)? {
+                0 => return Ok(data),
+                count => data.extend_from_slice(&buffer[..count]),
+            }
+        }
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        let _ = syscall::close(self.fd);
+    }
+}
+
+// Reads the whole file at `path`.
+pub fn read(path: &str) -> Result<Vec<u8>, Errno> {
+    File::open(path)?.read_to_end()
+}
+
+// Replaces the contents of the file at `path`, creating it if needed.
+pub fn write(path: &str, data: &[u8]) -> Result<(), Errno> {
+    let file = File::create(path)?;
+    crate::io::write_all(file.fd, data)
+}
@@ -0,0 +1,59 @@
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+use spin::Mutex;
+use crate::syscall;
+
+// the heap grows through sbrk in steps of at least this many bytes
+const GROW_SIZE: usize = 64 * 1024;
+
+// A bump allocator on top of the program break. Memory is handed back to the
+// kernel only once every allocation is freed again.
+struct Heap {
+    start: usize,
+    end: usize,
+    next: usize,
+    allocations: usize,
+}
+
+pub struct Allocator(Mutex<Heap>);
+
+#[global_allocator]
+static ALLOCATOR: Allocator = Allocator(Mutex::new(Heap { start: 0, end: 0, next: 0, allocations: 0 }));
+
+fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.0.lock();
+        if heap.start == 0 {
+            let Ok(start) = syscall::sbrk(0) else { return ptr::null_mut() };
+            heap.start = start as usize;
+            heap.end = start as usize;
+            heap.next = start as usize;
+        }
+        let alloc_start = align_up(heap.next, layout.align());
+        let Some(alloc_end) = alloc_start.checked_add(layout.size()) else { return ptr::null_mut() };
+        if alloc_end > heap.end {
+            let increment = align_up(alloc_end - heap.end, GROW_SIZE);
+            if syscall::sbrk(increment as i64).is_err() {
+                return ptr::null_mut();
+            }
+            heap.end += increment;
+        }
+        heap.next = alloc_end;
+        heap.allocations += 1;
+        alloc_start as *mut u8
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+        let mut heap = self.0.lock();
+        heap.allocations -= 1;
+        if heap.allocations == 0 {
+            let _ = syscall::sbrk(heap.start as i64 - heap.end as i64);
+            heap.end = heap.start;
+            heap.next = heap.start;
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use crate::syscall::{self, Errno};
+
+// standard file descriptors, connected to the console unless redirected
+pub const STDIN: u64 = 0;
+pub const STDOUT: u64 = 1;
+pub const STDERR: u64 = 2;
+
+// Writes everything in `buffer` to `fd`.
+pub fn write_all(fd: u64, mut buffer: &[u8]) -> Result<(), Errno> {
+    while !buffer.is_empty() {
+        let written = syscall::write(fd, buffer)?;
+        buffer = &buffer[written..];
+    }
+    Ok(())
+}
+
+struct Writer(u64);
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write_all(self.0, s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(fd: u64, args: fmt::Arguments) {
+    use fmt::Write;
+    let _ = Writer(fd).write_fmt(args);
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::io::_print($crate::io::STDOUT, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => ($crate::io::_print($crate::io::STDERR, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprintln {
+    () => ($crate::eprint!("\n"));
+    ($($arg:tt)*) => ($crate::eprint!("{}\n", format_args!($($arg)*)));
+}
+
+// Reads one line of standard input, without the line break. At the end of the
+// input it returns what's left, an empty string once there's nothing. Reads a byte
+// at a time, so whatever follows the line stays in a pipe for the next reader.
+pub fn read_line() -> Result<String, Errno> {
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+    while syscall::read(STDIN, &mut byte)? == 1 && byte[0] != b'\n' {
+        line.push(byte[0]);
+    }
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
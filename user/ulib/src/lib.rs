@@ -0,0 +1,67 @@
+// Runtime for RustOS user programs: system call wrappers, console and file I/O, a
+// heap on top of sbrk, the `_start` entry point and a panic handler. A program
+// names its main function with `entry!` and is linked with ../linker.ld.
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::arch::global_asm;
+use core::ffi::{c_char, CStr};
+use core::panic::PanicInfo;
+
+pub mod fs;
+pub mod heap;
+pub mod io;
+pub mod signal;
+pub mod syscall;
+
+pub use syscall::{exit, getpid, kill, mq_open, pipe, sleep, spawn, wait, Errno};
+
+// Defines the program's main function, `fn(&[&str]) -> i32`. It gets the command line
+// arguments, the first being the program's path, and returns the exit status.
+#[macro_export]
+macro_rules! entry {
+    ($path:path) => {
+        #[export_name = "__ulib_main"]
+        pub fn __ulib_main(args: &[&'static str]) -> i32 {
+            // validate the signature of the given function
+            let f: fn(&[&'static str]) -> i32 = $path;
+            f(args)
+        }
+    };
+}
+
+extern "Rust" {
+    fn __ulib_main(args: &[&'static str]) -> i32;
+}
+
+// The kernel enters here with argc at the stack pointer, followed by the argv and
+// envp arrays (see exec::setup_stack in the kernel).
+global_asm!(
+    ".global _start",
+    "_start:",
+    "    xor ebp, ebp",
+    "    mov rdi, rsp",
+    "    and rsp, -16",
+    "    call {start}",
+    "    ud2",
+    start = sym start,
+);
+
+extern "C" fn start(stack: *const u64) -> ! {
+    let args: Vec<&'static str> = unsafe {
+        let argc = *stack as usize;
+        let argv = stack.add(1) as *const *const c_char;
+        (0..argc).map(|i| CStr::from_ptr(*argv.add(i)).to_str().unwrap_or("")).collect()
+    };
+    let status = unsafe { __ulib_main(&args) };
+    drop(args);
+    exit(status)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    eprintln!("{}", info);
+    exit(101)
+}
@@ -0,0 +1,84 @@
+use core::arch::global_asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::syscall::{self, Errno, SIG_DEFAULT, SIG_IGNORE};
+
+pub const SIGINT: u64 = 2;
+pub const SIGKILL: u64 = 9;
+pub const SIGTERM: u64 = 15;
+pub const SIGCHLD: u64 = 17;
+
+// handler functions by signal number, 0 where there is none
+static HANDLERS: [AtomicUsize; 32] = [const { AtomicUsize::new(0) }; 32];
+
+// The kernel enters a handler with every register as the interrupted code left it.
+// Below the 128 byte red zone it pushed the flags and the address to go back to,
+// and the signal number on top. The trampoline saves the registers a Rust function
+// may change, SSE ones included, calls `dispatch`, restores everything and returns
+// past the red zone.
+global_asm!(
+    ".global __ulib_signal_trampoline",
+    "__ulib_signal_trampoline:",
+    "    push rax",
+    "    push rcx",
+    "    push rdx",
+    "    push rsi",
+    "    push rdi",
+    "    push r8",
+    "    push r9",
+    "    push r10",
+    "    push r11",
+    "    push rbp",
+    "    mov rbp, rsp",
+    "    mov rdi, [rbp + 80]",
+    "    and rsp, -16",
+    "    sub rsp, 512",
+    "    fxsave64 [rsp]",
+    "    cld",
+    "    call {dispatch}",
+    "    fxrstor64 [rsp]",
+    "    mov rsp, rbp",
+    "    pop rbp",
+    "    pop r11",
+    "    pop r10",
+    "    pop r9",
+    "    pop r8",
+    "    pop rdi",
+    "    pop rsi",
+    "    pop rdx",
+    "    pop rcx",
+    "    pop rax",
+    // the signal number, then the flags; popfq undoes what `add` and `and` changed
+    "    add rsp, 8",
+    "    popfq",
+    "    ret 128",
+    dispatch = sym dispatch,
+);
+
+extern "C" {
+    fn __ulib_signal_trampoline();
+}
+
+extern "C" fn dispatch(signal: u64) {
+    let handler = HANDLERS[signal as usize % HANDLERS.len()].load(Ordering::Relaxed);
+    if handler != 0 {
+        let handler: fn(u64) = unsafe { core::mem::transmute(handler) };
+        handler(signal);
+    }
+}
+
+// Calls `handler` with the signal's number whenever the process receives `signal`.
+// Blocking system calls interrupted by it fail with `Errno::INTERRUPTED`.
+pub fn set_handler(signal: u64, handler: fn(u64)) -> Result<(), Errno> {
+    let slot = HANDLERS.get(signal as usize).ok_or(Errno::INVALID_ARGUMENT)?;
+    slot.store(handler as usize, Ordering::Relaxed);
+    syscall::sigaction(signal, __ulib_signal_trampoline as unsafe extern "C" fn() as usize as u64)
+}
+
+pub fn ignore(signal: u64) -> Result<(), Errno> {
+    syscall::sigaction(signal, SIG_IGNORE)
+}
+
+// Goes back to the default action: ending the process, except for SIGCHLD.
+pub fn reset(signal: u64) -> Result<(), Errno> {
+    syscall::sigaction(signal, SIG_DEFAULT)
+}
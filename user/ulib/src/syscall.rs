@@ -0,0 +1,214 @@
+use core::arch::asm;
+
+// System call numbers, the same as in the kernel's syscall module. Arguments go in
+// rdi, rsi, rdx, r10, r8 and r9, the result comes back in rax: a value >= 0 on
+// success, a negated error number on failure.
+pub const SYS_READ: u64 = 0;
+pub const SYS_WRITE: u64 = 1;
+pub const SYS_OPEN: u64 = 2;
+pub const SYS_CLOSE: u64 = 3;
+pub const SYS_EXIT: u64 = 4;
+pub const SYS_SBRK: u64 = 5;
+pub const SYS_GETPID: u64 = 6;
+pub const SYS_SLEEP: u64 = 7;
+pub const SYS_SPAWN: u64 = 8;
+pub const SYS_WAIT: u64 = 9;
+pub const SYS_KILL: u64 = 10;
+pub const SYS_PIPE: u64 = 11;
+pub const SYS_MQ_OPEN: u64 = 12;
+pub const SYS_SIGACTION: u64 = 13;
+
+// actions of SYS_SIGACTION besides a handler's address
+pub const SIG_DEFAULT: u64 = 0;
+pub const SIG_IGNORE: u64 = 1;
+
+// flags of SYS_OPEN
+pub const OPEN_CREATE: u64 = 1 << 0;
+pub const OPEN_TRUNCATE: u64 = 1 << 1;
+pub const OPEN_APPEND: u64 = 1 << 2;
+
+// An error number returned by the kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Errno(pub i64);
+
+impl Errno {
+    pub const NO_ENTRY: Errno = Errno(2);
+    pub const NO_SUCH_PROCESS: Errno = Errno(3);
+    pub const INTERRUPTED: Errno = Errno(4);
+    pub const ARGUMENT_LIST_TOO_LONG: Errno = Errno(7);
+    pub const EXEC_FORMAT: Errno = Errno(8);
+    pub const BAD_FILE_DESCRIPTOR: Errno = Errno(9);
+    pub const NO_CHILD: Errno = Errno(10);
+    pub const NO_MEMORY: Errno = Errno(12);
+    pub const BAD_ADDRESS: Errno = Errno(14);
+    pub const INVALID_ARGUMENT: Errno = Errno(22);
+    pub const TOO_MANY_FILES: Errno = Errno(24);
+    pub const BROKEN_PIPE: Errno = Errno(32);
+    pub const NO_SYS: Errno = Errno(38);
+    pub const MESSAGE_TOO_LONG: Errno = Errno(90);
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Errno::NO_ENTRY => "no such file or directory",
+            Errno::NO_SUCH_PROCESS => "no such process",
+            Errno::INTERRUPTED => "interrupted system call",
+            Errno::ARGUMENT_LIST_TOO_LONG => "argument list too long",
+            Errno::EXEC_FORMAT => "exec format error",
+            Errno::BAD_FILE_DESCRIPTOR => "bad file descriptor",
+            Errno::NO_CHILD => "no child processes",
+            Errno::NO_MEMORY => "out of memory",
+            Errno::BAD_ADDRESS => "bad address",
+            Errno::INVALID_ARGUMENT => "invalid argument",
+            Errno::TOO_MANY_FILES => "too many open files",
+            Errno::BROKEN_PIPE => "broken pipe",
+            Errno::NO_SYS => "function not implemented",
+            Errno::MESSAGE_TOO_LONG => "message too long",
+            _ => "unknown error",
+        }
+    }
+}
+
+pub type SyscallResult = Result<u64, Errno>;
+
+fn result(value: u64) -> SyscallResult {
+    match value as i64 {
+        errno if errno < 0 => Err(Errno(-errno)),
+        _ => Ok(value),
+    }
+}
+
+// `syscall` itself clobbers rcx (return address) and r11 (flags)
+unsafe fn syscall0(number: u64) -> SyscallResult {
+    let value;
+    asm!("syscall", inlateout("rax") number => value, out("rcx") _, out("r11") _, options(nostack));
+    result(value)
+}
+
+unsafe fn syscall1(number: u64, a0: u64) -> SyscallResult {
+    let value;
+    asm!("syscall", inlateout("rax") number => value, in("rdi") a0, out("rcx") _, out("r11") _, options(nostack));
+    result(value)
+}
+
+unsafe fn syscall2(number: u64, a0: u64, a1: u64) -> SyscallResult {
+    let value;
+    asm!(
+        "syscall",
+        inlateout("rax") number => value,
+        in("rdi") a0,
+        in("rsi") a1,
+        out("rcx") _,
+        out("r11") _,
+        options(nostack),
+    );
+    result(value)
+}
+
+unsafe fn syscall3(number: u64, a0: u64, a1: u64, a2: u64) -> SyscallResult {
+    let value;
+    asm!(
+        "syscall",
+        inlateout("rax") number => value,
+        in("rdi") a0,
+        in("rsi") a1,
+        in("rdx") a2,
+        out("rcx") _,
+        out("r11") _,
+        options(nostack),
+    );
+    result(value)
+}
+
+unsafe fn syscall4(number: u64, a0: u64, a1: u64, a2: u64, a3: u64) -> SyscallResult {
+    let value;
+    asm!(
+        "syscall",
+        inlateout("rax") number => value,
+        in("rdi") a0,
+        in("rsi") a1,
+        in("rdx") a2,
+        in("r10") a3,
+        out("rcx") _,
+        out("r11") _,
+        options(nostack),
+    );
+    result(value)
+}
+
+pub fn read(fd: u64, buffer: &mut [u8]) -> Result<usize, Errno> {
+    unsafe { syscall3(SYS_READ, fd, buffer.as_mut_ptr() as u64, buffer.len() as u64).map(|n| n as usize) }
+}
+
+pub fn write(fd: u64, buffer: &[u8]) -> Result<usize, Errno> {
+    unsafe { syscall3(SYS_WRITE, fd, buffer.as_ptr() as u64, buffer.len() as u64).map(|n| n as usize) }
+}
+
+pub fn open(path: &str, flags: u64) -> Result<u64, Errno> {
+    unsafe { syscall3(SYS_OPEN, path.as_ptr() as u64, path.len() as u64, flags) }
+}
+
+pub fn close(fd: u64) -> Result<(), Errno> {
+    unsafe { syscall1(SYS_CLOSE, fd).map(|_| ()) }
+}
+
+pub fn exit(status: i32) -> ! {
+    unsafe {
+        let _ = syscall1(SYS_EXIT, status as u64);
+    }
+    // the kernel never returns from exit
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+// Moves the end of the heap by `increment` bytes and returns the previous end.
+pub fn sbrk(increment: i64) -> Result<u64, Errno> {
+    unsafe { syscall1(SYS_SBRK, increment as u64) }
+}
+
+pub fn getpid() -> u64 {
+    unsafe { syscall0(SYS_GETPID).unwrap_or(0) }
+}
+
+pub fn sleep(ms: u64) {
+    unsafe {
+        let _ = syscall1(SYS_SLEEP, ms);
+    }
+}
+
+// Starts the program at `path` as a child process and returns its pid. `args` are
+// its arguments after the path, separated by whitespace.
+pub fn spawn(path: &str, args: &str) -> Result<u64, Errno> {
+    unsafe { syscall4(SYS_SPAWN, path.as_ptr() as u64, path.len() as u64, args.as_ptr() as u64, args.len() as u64) }
+}
+
+// Waits for child `pid` (any child if 0) to exit and returns its pid and status.
+pub fn wait(pid: u64) -> Result<(u64, i32), Errno> {
+    let mut status = 0i32;
+    let pid = unsafe { syscall2(SYS_WAIT, pid, &raw mut status as u64)? };
+    Ok((pid, status))
+}
+
+// Sends `signal` (see the `signal` module) to process `pid`.
+pub fn kill(pid: u64, signal: u64) -> Result<(), Errno> {
+    unsafe { syscall2(SYS_KILL, pid, signal).map(|_| ()) }
+}
+
+// Sets the action for `signal`: SIG_DEFAULT, SIG_IGNORE or the address of a
+// handler entry like `signal`'s trampoline.
+pub fn sigaction(signal: u64, action: u64) -> Result<(), Errno> {
+    unsafe { syscall2(SYS_SIGACTION, signal, action).map(|_| ()) }
+}
+
+// Creates a pipe and returns its read and write descriptors.
+pub fn pipe() -> Result<(u64, u64), Errno> {
+    let mut fds = [0u32; 2];
+    unsafe { syscall1(SYS_PIPE, fds.as_mut_ptr() as u64)? };
+    Ok((fds[0] as u64, fds[1] as u64))
+}
+
+// Opens (or creates) the message queue `name`. Writing to the descriptor sends a
+// message of at most 256 bytes, reading receives one into a buffer of at least that.
+pub fn mq_open(name: &str) -> Result<u64, Errno> {
+    unsafe { syscall2(SYS_MQ_OPEN, name.as_ptr() as u64, name.len() as u64) }
+}